@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS renditions (
+    video_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    ext TEXT NOT NULL,
+    created DATETIME DEFAULT (DATETIME('now')),
+    PRIMARY KEY (video_id, name)
+);
\ No newline at end of file
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    video_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'queued',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    run_at DATETIME NOT NULL DEFAULT (DATETIME('now')),
+    created DATETIME DEFAULT (DATETIME('now')),
+    updated DATETIME
+);
+CREATE INDEX IF NOT EXISTS jobs_status_run_at_index ON jobs (status, run_at);
\ No newline at end of file
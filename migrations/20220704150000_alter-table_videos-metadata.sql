@@ -0,0 +1,9 @@
+ALTER TABLE videos ADD COLUMN duration REAL;
+ALTER TABLE videos ADD COLUMN width INTEGER;
+ALTER TABLE videos ADD COLUMN height INTEGER;
+ALTER TABLE videos ADD COLUMN video_codec TEXT;
+ALTER TABLE videos ADD COLUMN audio_codec TEXT;
+ALTER TABLE videos ADD COLUMN bitrate INTEGER;
+ALTER TABLE videos ADD COLUMN size INTEGER;
+CREATE INDEX IF NOT EXISTS videos_duration_index ON videos (duration);
+CREATE INDEX IF NOT EXISTS videos_size_index ON videos (size);
\ No newline at end of file
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS uploads (
+    id TEXT NOT NULL PRIMARY KEY,
+    length INTEGER NOT NULL,
+    received INTEGER NOT NULL DEFAULT 0,
+    metadata TEXT,
+    expires DATETIME NOT NULL,
+    created DATETIME DEFAULT (DATETIME('now'))
+);
+CREATE INDEX IF NOT EXISTS uploads_expires_index ON uploads (expires);
\ No newline at end of file
@@ -0,0 +1,4 @@
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'viewer';
+
+-- accounts created before roles existed had full access
+UPDATE users SET role = 'admin';
\ No newline at end of file
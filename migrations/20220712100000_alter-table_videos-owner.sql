@@ -0,0 +1,15 @@
+-- videos uploaded before ownership existed stay visible to admins only
+ALTER TABLE videos ADD COLUMN owner_id TEXT;
+ALTER TABLE videos ADD COLUMN visibility TEXT NOT NULL DEFAULT 'private';
+
+CREATE INDEX IF NOT EXISTS videos_owner_id_index ON videos (owner_id);
+
+CREATE TABLE IF NOT EXISTS video_shares (
+    video_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    PRIMARY KEY (video_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS video_shares_user_id_index ON video_shares (user_id);
+
+ALTER TABLE uploads ADD COLUMN owner_id TEXT;
\ No newline at end of file
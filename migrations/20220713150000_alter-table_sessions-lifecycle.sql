@@ -0,0 +1,8 @@
+ALTER TABLE sessions ADD COLUMN key TEXT;
+ALTER TABLE sessions ADD COLUMN user_agent TEXT;
+ALTER TABLE sessions ADD COLUMN ip TEXT;
+ALTER TABLE sessions ADD COLUMN last_seen DATETIME;
+
+UPDATE sessions SET key = LOWER(HEX(RANDOMBLOB(8))), last_seen = created;
+
+CREATE INDEX IF NOT EXISTS sessions_id_index ON sessions (id);
\ No newline at end of file
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS api_tokens (
+    key TEXT NOT NULL PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    -- SHA-256 of the token, tokens are random enough not to need a slow hash
+    hash TEXT NOT NULL UNIQUE,
+    -- comma separated, see `Scope`
+    scopes TEXT NOT NULL,
+    created DATETIME DEFAULT (DATETIME('now')),
+    expires DATETIME,
+    last_used DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS api_tokens_user_id_index ON api_tokens (user_id);
\ No newline at end of file
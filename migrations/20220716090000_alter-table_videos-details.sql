@@ -0,0 +1,5 @@
+ALTER TABLE videos ADD COLUMN title TEXT;
+ALTER TABLE videos ADD COLUMN description TEXT;
+-- YYYY-MM-DD, when the video was recorded rather than uploaded
+ALTER TABLE videos ADD COLUMN recorded TEXT;
+CREATE INDEX IF NOT EXISTS videos_recorded_index ON videos (recorded);
\ No newline at end of file
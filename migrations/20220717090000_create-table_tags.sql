@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS tags (
+    id INTEGER NOT NULL PRIMARY KEY,
+    -- normalized, see `Tag::normalize`
+    name TEXT NOT NULL UNIQUE,
+    created DATETIME DEFAULT (DATETIME('now'))
+);
+
+CREATE TABLE IF NOT EXISTS video_tags (
+    video_id TEXT NOT NULL,
+    tag_id INTEGER NOT NULL,
+    PRIMARY KEY (video_id, tag_id)
+);
+
+CREATE INDEX IF NOT EXISTS video_tags_tag_id_index ON video_tags (tag_id);
\ No newline at end of file
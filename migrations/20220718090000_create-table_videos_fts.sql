@@ -0,0 +1,41 @@
+-- the name of the uploaded file, only kept for searching
+ALTER TABLE videos ADD COLUMN original_name TEXT;
+
+CREATE VIRTUAL TABLE IF NOT EXISTS videos_fts USING fts5(
+    video_id UNINDEXED,
+    title,
+    description,
+    -- space separated tag names
+    tags,
+    original_name,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO videos_fts(video_id, title, description, tags, original_name)
+SELECT id, title, description, (SELECT GROUP_CONCAT(tags.name, ' ') FROM video_tags JOIN tags ON tags.id = video_tags.tag_id WHERE video_tags.video_id = videos.id), original_name FROM videos;
+
+-- the triggers keep the index in sync with every change to videos and tags
+
+CREATE TRIGGER IF NOT EXISTS videos_fts_insert AFTER INSERT ON videos BEGIN
+    INSERT INTO videos_fts(video_id, title, description, tags, original_name) VALUES (new.id, new.title, new.description, '', new.original_name);
+END;
+
+CREATE TRIGGER IF NOT EXISTS videos_fts_update AFTER UPDATE OF title, description, original_name ON videos BEGIN
+    UPDATE videos_fts SET title = new.title, description = new.description, original_name = new.original_name WHERE video_id = new.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS videos_fts_delete AFTER DELETE ON videos BEGIN
+    DELETE FROM videos_fts WHERE video_id = old.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS videos_fts_tag_insert AFTER INSERT ON video_tags BEGIN
+    UPDATE videos_fts SET tags = (SELECT GROUP_CONCAT(tags.name, ' ') FROM video_tags JOIN tags ON tags.id = video_tags.tag_id WHERE video_tags.video_id = new.video_id) WHERE video_id = new.video_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS videos_fts_tag_delete AFTER DELETE ON video_tags BEGIN
+    UPDATE videos_fts SET tags = (SELECT GROUP_CONCAT(tags.name, ' ') FROM video_tags JOIN tags ON tags.id = video_tags.tag_id WHERE video_tags.video_id = old.video_id) WHERE video_id = old.video_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS videos_fts_tag_rename AFTER UPDATE OF name ON tags BEGIN
+    UPDATE videos_fts SET tags = (SELECT GROUP_CONCAT(tags.name, ' ') FROM video_tags JOIN tags ON tags.id = video_tags.tag_id WHERE video_tags.video_id = videos_fts.video_id) WHERE video_id IN (SELECT video_id FROM video_tags WHERE tag_id = new.id);
+END;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE videos ADD COLUMN views INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS videos_views_index ON videos (views);
\ No newline at end of file
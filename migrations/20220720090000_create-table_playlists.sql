@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS playlists (
+    id TEXT NOT NULL PRIMARY KEY,
+    owner_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT,
+    -- private, unlisted or public, playlists can't be shared with users
+    visibility TEXT NOT NULL DEFAULT 'private',
+    created DATETIME DEFAULT (DATETIME('now'))
+);
+
+CREATE INDEX IF NOT EXISTS playlists_owner_id_index ON playlists (owner_id);
+
+CREATE TABLE IF NOT EXISTS playlist_items (
+    playlist_id TEXT NOT NULL,
+    video_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (playlist_id, video_id)
+);
+
+CREATE INDEX IF NOT EXISTS playlist_items_video_id_index ON playlist_items (video_id);
\ No newline at end of file
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS collections (
+    id TEXT NOT NULL PRIMARY KEY,
+    owner_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    -- a search query, evaluated whenever the collection is viewed
+    query TEXT NOT NULL,
+    pinned BOOLEAN NOT NULL DEFAULT FALSE,
+    created DATETIME DEFAULT (DATETIME('now'))
+);
+
+CREATE INDEX IF NOT EXISTS collections_owner_id_index ON collections (owner_id);
\ No newline at end of file
@@ -1,23 +1,192 @@
+use std::{convert::Infallible, marker::PhantomData, sync::Arc};
+
 use axum::{
-    extract::{FromRequest, RequestParts},
+    extract::{ConnectInfo, FromRequest, Query, RequestParts},
     Extension,
 };
-use http::StatusCode;
+use http::{header, Extensions, HeaderMap, StatusCode};
+use ipnet::IpNet;
 use sqlx::SqlitePool;
 use tower_cookies::Cookies;
+use uuid::Uuid;
+
+use crate::{
+    cache::Cache,
+    config::Config,
+    database,
+    error::Error,
+    listen::{ListenerTls, Peer},
+    models::{Role, Scope, User, Video, Visibility},
+    proxy,
+    signing::{self, Signer},
+    SESSION,
+};
+
+/// How long a session lasts after signing in, however much it's used.
+pub(crate) const SESSION_LIFETIME_DAYS: i64 = 7;
+
+/// How long a session lasts without being used.
+pub(crate) const SESSION_IDLE_DAYS: i64 = 2;
 
-use crate::{database, SESSION};
+/// [`SESSION_LIFETIME_DAYS`] as an SQLite date modifier.
+pub(crate) fn session_lifetime() -> String {
+    format!("-{} days", SESSION_LIFETIME_DAYS)
+}
+
+/// [`SESSION_IDLE_DAYS`] as an SQLite date modifier.
+pub(crate) fn session_idle() -> String {
+    format!("-{} days", SESSION_IDLE_DAYS)
+}
+
+/// Where a request came from, recorded for every session so users can tell
+/// their devices apart.
+///
+/// Forwarded headers from trusted proxies are taken into account, this is the
+/// one place that decides who the client is.
+pub(crate) struct Client {
+    pub(crate) ip: Option<String>,
+    pub(crate) user_agent: Option<String>,
+    /// Whether the client reached hawk, or the proxy in front of it, over HTTPS.
+    pub(crate) https: bool,
+}
 
-pub(crate) struct Auth;
+impl Client {
+    pub(crate) fn new(extensions: &Extensions, headers: &HeaderMap, trusted: &[IpNet]) -> Self {
+        let origin = match extensions.get::<ConnectInfo<Peer>>() {
+            Some(ConnectInfo(peer)) => {
+                let https = extensions
+                    .get::<ListenerTls>()
+                    .is_some_and(|ListenerTls(tls)| *tls);
+
+                Some(proxy::resolve(*peer, https, headers, trusted))
+            }
+            None => None,
+        };
+
+        let user_agent = headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        Client {
+            ip: origin.and_then(|origin| origin.ip).map(|ip| ip.to_string()),
+            user_agent,
+            https: origin.is_some_and(|origin| origin.https),
+        }
+    }
+}
 
 #[async_trait::async_trait]
-impl<B: Send> FromRequest<B> for Auth {
+impl<B: Send> FromRequest<B> for Client {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Extension(config) = Extension::<Arc<Config>>::from_request(req)
+            .await
+            .expect("`Config` extension missing");
+
+        Ok(Client::new(
+            req.extensions(),
+            req.headers(),
+            &config.server.trusted_proxies,
+        ))
+    }
+}
+
+/// A role and API token scope that an [`Auth`] extractor requires.
+pub(crate) trait Requirement {
+    const ROLE: Role;
+    const SCOPE: Scope;
+}
+
+pub(crate) struct Viewer;
+
+impl Requirement for Viewer {
+    const ROLE: Role = Role::Viewer;
+    const SCOPE: Scope = Scope::Read;
+}
+
+/// Any user, for routes that change data, so read only tokens can't use them.
+pub(crate) struct Writer;
+
+impl Requirement for Writer {
+    const ROLE: Role = Role::Viewer;
+    const SCOPE: Scope = Scope::Upload;
+}
+
+pub(crate) struct Uploader;
+
+impl Requirement for Uploader {
+    const ROLE: Role = Role::Uploader;
+    const SCOPE: Scope = Scope::Upload;
+}
+
+pub(crate) struct Admin;
+
+impl Requirement for Admin {
+    const ROLE: Role = Role::Admin;
+    const SCOPE: Scope = Scope::Admin;
+}
+
+/// The user behind the request's API token or session, rejected with `401` when
+/// there is neither and `403` when the user's role is below `R`.
+///
+/// API tokens are passed as `Authorization: Bearer <token>` and also need a
+/// [`Scope`] covering `R`, a request with a token never falls back to the
+/// session cookie.
+pub(crate) struct Auth<R = Viewer> {
+    pub(crate) user: User,
+    /// The highest scope the request's API token grants, `None` for sessions
+    /// which can do whatever the user's role allows.
+    pub(crate) scope: Option<Scope>,
+    _requirement: PhantomData<fn() -> R>,
+}
+
+impl<R> Auth<R> {
+    /// Whether the token, if any, grants `scope`.
+    pub(crate) fn allows(&self, scope: Scope) -> bool {
+        self.scope.is_none_or(|granted| granted >= scope)
+    }
+}
+
+#[async_trait::async_trait]
+impl<B: Send, R: Requirement> FromRequest<B> for Auth<R> {
     type Rejection = StatusCode;
 
     async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
         let Extension(pool) = Extension::<SqlitePool>::from_request(req)
             .await
             .expect("`SqlitePool` extension missing");
+        let Extension(cache) = Extension::<Cache>::from_request(req)
+            .await
+            .expect("`Cache` extension missing");
+
+        if let Some(token) = bearer(req.headers()) {
+            let grant = database::db_get_token_grant(&pool, &cache, token)
+                .await
+                .map_err(|_| StatusCode::UNAUTHORIZED)?
+                .ok_or(StatusCode::UNAUTHORIZED)?;
+
+            // scopes above the user's role are of no use, in case it changed
+            // since the token was created
+            let scope = grant
+                .scopes
+                .iter()
+                .map(|scope| (*scope).min(Scope::for_role(grant.user.role)))
+                .max();
+
+            let auth = Auth {
+                user: grant.user,
+                scope: Some(scope.ok_or(StatusCode::FORBIDDEN)?),
+                _requirement: PhantomData,
+            };
+
+            if auth.user.role < R::ROLE || !auth.allows(R::SCOPE) {
+                return Err(StatusCode::FORBIDDEN);
+            }
+
+            return Ok(auth);
+        }
 
         let cookie = Option::<Cookies>::from_request(req)
             .await
@@ -27,13 +196,311 @@ impl<B: Send> FromRequest<B> for Auth {
         let session = cookie.get(SESSION).ok_or(StatusCode::UNAUTHORIZED)?;
         let value = session.value();
 
-        if !database::db_is_session_valid(pool, value)
+        let Ok(client) = Client::from_request(req).await;
+
+        let user = database::db_get_session_user(&pool, &cache, value, &client)
             .await
             .map_err(|_| StatusCode::UNAUTHORIZED)?
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        if user.role < R::ROLE {
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        Ok(Auth {
+            user,
+            scope: None,
+            _requirement: PhantomData,
+        })
+    }
+}
+
+/// An [`Auth`] from a session cookie, for managing API tokens.
+///
+/// Requests with an API token are rejected with `403`, whatever its scope, so a
+/// leaked token can't mint itself a broader one or revoke the user's others.
+pub(crate) struct SessionAuth<R = Viewer>(pub(crate) Auth<R>);
+
+#[async_trait::async_trait]
+impl<B: Send, R: Requirement> FromRequest<B> for SessionAuth<R> {
+    type Rejection = StatusCode;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let auth = Auth::from_request(req).await?;
+
+        if auth.scope.is_some() {
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        Ok(SessionAuth(auth))
+    }
+}
+
+/// An optional [`Auth`], for routes anyone may use.
+///
+/// Unlike `Option<Auth>` a request with an `Authorization` header has to
+/// authenticate with it, a script whose token expired or lacks the scope gets a
+/// `401` or `403` instead of quietly being served as anonymous.
+pub(crate) struct MaybeAuth<R = Viewer>(pub(crate) Option<Auth<R>>);
+
+#[async_trait::async_trait]
+impl<B: Send, R: Requirement> FromRequest<B> for MaybeAuth<R> {
+    type Rejection = StatusCode;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        if req.headers().contains_key(header::AUTHORIZATION) {
+            return Auth::from_request(req)
+                .await
+                .map(|auth| MaybeAuth(Some(auth)));
+        }
+
+        Ok(MaybeAuth(Auth::from_request(req).await.ok()))
+    }
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+#[derive(serde::Deserialize)]
+struct Signature {
+    expires: Option<u64>,
+    signature: Option<String>,
+}
+
+/// Whether `user` may watch `video`, looking up shares only when they matter.
+pub(crate) async fn can_view(
+    pool: &SqlitePool,
+    user: Option<&User>,
+    video: &Video,
+) -> Result<bool, Error> {
+    let shared = match user {
+        Some(user) if video.visibility == Visibility::Shared && !video.is_owned_by(user) => {
+            database::db_is_video_shared(pool, &video.id, &user.id).await?
+        }
+        _ => false,
+    };
+
+    Ok(video.is_viewable_by(user, shared))
+}
+
+/// Access to media files, granted by either a signed URL, or a session or
+/// visibility that allows watching the video the file belongs to.
+pub(crate) struct MediaAuth {
+    signed: bool,
+    user: Option<User>,
+}
+
+impl MediaAuth {
+    /// Returns the status to reject the request with when the video can't be
+    /// watched, `401` for anonymous requests so they know to sign in.
+    pub(crate) async fn check(
+        &self,
+        pool: &SqlitePool,
+        cache: &Cache,
+        id: &Uuid,
+    ) -> Result<Option<StatusCode>, Error> {
+        if self.signed {
+            return Ok(None);
+        }
+
+        let allowed = match database::db_get_video(pool, cache, id).await? {
+            Some(video) => can_view(pool, self.user.as_ref(), &video).await?,
+            None => false,
+        };
+
+        Ok(match (allowed, &self.user) {
+            (true, _) => None,
+            (false, None) => Some(StatusCode::UNAUTHORIZED),
+            (false, Some(_)) => Some(StatusCode::NOT_FOUND),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl<B: Send> FromRequest<B> for MediaAuth {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Extension(signer) = Extension::<Signer>::from_request(req)
+            .await
+            .expect("`Signer` extension missing");
+
+        if let Ok(Query(Signature {
+            expires: Some(expires),
+            signature: Some(signature),
+        })) = Query::<Signature>::from_request(req).await
         {
-            return Err(StatusCode::UNAUTHORIZED);
+            let scope = signing::scope(req.uri().path());
+
+            if signer.verify(&scope, expires, &signature) {
+                return Ok(MediaAuth {
+                    signed: true,
+                    user: None,
+                });
+            }
+        }
+
+        let user = Auth::<Viewer>::from_request(req)
+            .await
+            .ok()
+            .map(|auth| auth.user);
+
+        Ok(MediaAuth {
+            signed: false,
+            user,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Request;
+
+    use super::*;
+    use crate::database::memory_pool as pool;
+
+    async fn token(pool: &SqlitePool, username: &str, role: Role, scopes: &[Scope]) -> String {
+        let user = database::db_create_user(pool, username, "password", role)
+            .await
+            .unwrap();
+
+        database::db_create_token(pool, &user, "test", scopes, None)
+            .await
+            .unwrap()
+            .1
+    }
+
+    fn request(pool: &SqlitePool, authorization: Option<&str>) -> RequestParts<()> {
+        let mut request = Request::builder();
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+
+        let mut request = request.body(()).unwrap();
+        request.extensions_mut().insert(pool.clone());
+        request.extensions_mut().insert(Cache::new());
+
+        RequestParts::new(request)
+    }
+
+    async fn check<R: Requirement>(pool: &SqlitePool, token: &str) -> Result<(), StatusCode> {
+        let bearer = format!("Bearer {}", token);
+
+        Auth::<R>::from_request(&mut request(pool, Some(&bearer)))
+            .await
+            .map(|_| ())
+    }
+
+    #[test]
+    fn sessions_allow_every_scope() {
+        let auth = Auth::<Viewer> {
+            user: User {
+                id: Uuid::new_v4(),
+                username: "viewer".to_string(),
+                role: Role::Viewer,
+            },
+            scope: None,
+            _requirement: PhantomData,
+        };
+
+        assert!(Scope::ALL.iter().all(|scope| auth.allows(*scope)));
+    }
+
+    #[tokio::test]
+    async fn read_tokens_cant_change_anything() {
+        let pool = pool().await;
+        let token = token(&pool, "admin", Role::Admin, &[Scope::Read]).await;
+
+        assert_eq!(check::<Viewer>(&pool, &token).await, Ok(()));
+        assert_eq!(
+            check::<Writer>(&pool, &token).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            check::<Uploader>(&pool, &token).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            check::<Admin>(&pool, &token).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+    }
+
+    #[tokio::test]
+    async fn upload_tokens_can_change_but_not_administer() {
+        let pool = pool().await;
+        let token = token(&pool, "uploader", Role::Uploader, &[Scope::Upload]).await;
+
+        assert_eq!(check::<Viewer>(&pool, &token).await, Ok(()));
+        assert_eq!(check::<Writer>(&pool, &token).await, Ok(()));
+        assert_eq!(check::<Uploader>(&pool, &token).await, Ok(()));
+        assert_eq!(
+            check::<Admin>(&pool, &token).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+    }
+
+    #[tokio::test]
+    async fn scopes_are_capped_by_the_role() {
+        let pool = pool().await;
+        // the user was demoted after creating the token
+        let token = token(&pool, "demoted", Role::Viewer, &[Scope::Admin]).await;
+
+        assert_eq!(check::<Viewer>(&pool, &token).await, Ok(()));
+        assert_eq!(
+            check::<Writer>(&pool, &token).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            check::<Admin>(&pool, &token).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_tokens_are_unauthorized() {
+        let pool = pool().await;
+
+        assert_eq!(
+            check::<Viewer>(&pool, "hawk_unknown").await,
+            Err(StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    #[tokio::test]
+    async fn optional_auth_rejects_bad_authorization_headers() {
+        let pool = pool().await;
+        let token = token(&pool, "viewer", Role::Viewer, &[Scope::Read]).await;
+
+        let anonymous = MaybeAuth::<Viewer>::from_request(&mut request(&pool, None))
+            .await
+            .unwrap();
+        assert!(anonymous.0.is_none());
+
+        let bearer = format!("Bearer {}", token);
+        let signed_in = MaybeAuth::<Viewer>::from_request(&mut request(&pool, Some(&bearer)))
+            .await
+            .unwrap();
+        assert_eq!(signed_in.0.unwrap().scope, Some(Scope::Read));
+
+        for header in ["Bearer hawk_unknown", "Basic dXNlcjpwYXNz"] {
+            assert_eq!(
+                MaybeAuth::<Viewer>::from_request(&mut request(&pool, Some(header)))
+                    .await
+                    .err(),
+                Some(StatusCode::UNAUTHORIZED)
+            );
         }
 
-        Ok(Auth)
+        assert_eq!(
+            MaybeAuth::<Writer>::from_request(&mut request(&pool, Some(&bearer)))
+                .await
+                .err(),
+            Some(StatusCode::FORBIDDEN)
+        );
     }
 }
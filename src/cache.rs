@@ -0,0 +1,222 @@
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+use crate::models::{TokenGrant, User, Video};
+
+/// How long the video listing is kept, mutations invalidate it right away.
+const VIDEOS_TTL: Duration = Duration::from_secs(10 * 60);
+const VIDEO_TTL: Duration = Duration::from_secs(10 * 60);
+const VIDEO_CAPACITY: usize = 4096;
+
+/// How long a session is trusted before it's checked against the database and
+/// its last use recorded, this also bounds how long a session revoked from the
+/// command line keeps working.
+const SESSION_TTL: Duration = Duration::from_secs(5 * 60);
+const SESSION_CAPACITY: usize = 4096;
+
+/// Unknown tokens are remembered briefly, in a small map of their own so random
+/// cookies can't push out real sessions.
+const MISSING_SESSION_TTL: Duration = Duration::from_secs(60);
+const MISSING_SESSION_CAPACITY: usize = 1024;
+
+/// API tokens are cached like sessions, keyed by their hash.
+const TOKEN_TTL: Duration = SESSION_TTL;
+const TOKEN_CAPACITY: usize = 1024;
+
+/// A map whose entries expire after `ttl`, once `capacity` is reached the
+/// oldest entry makes room for new ones.
+struct TtlMap<K, V> {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> TtlMap<K, V> {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<K, (Instant, V)>> {
+        // the map is always left in a consistent state, a panic elsewhere
+        // doesn't make it unusable
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let mut entries = self.entries();
+
+        match entries.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, key: K, value: V) {
+        let mut entries = self.entries();
+
+        if entries.len() >= self.capacity {
+            let ttl = self.ttl;
+            entries.retain(|_, (inserted, _)| inserted.elapsed() < ttl);
+        }
+
+        if entries.len() >= self.capacity {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (inserted, _))| *inserted)
+                .map(|(key, _)| key.clone());
+
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(key, (Instant::now(), value));
+    }
+
+    fn remove<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries().remove(key);
+    }
+
+    fn clear(&self) {
+        self.entries().clear();
+    }
+}
+
+/// Caches the hot database lookups, cheap to clone.
+///
+/// Every handler that changes cached data invalidates the affected keys, the
+/// TTLs only matter for changes made outside of the server, like the `user`
+/// subcommands.
+#[derive(Clone)]
+pub(crate) struct Cache {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    videos: TtlMap<(), Arc<Vec<Video>>>,
+    video: TtlMap<Uuid, Option<Video>>,
+    sessions: TtlMap<String, User>,
+    missing_sessions: TtlMap<String, ()>,
+    tokens: TtlMap<String, TokenGrant>,
+    missing_tokens: TtlMap<String, ()>,
+}
+
+impl Cache {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                videos: TtlMap::new(VIDEOS_TTL, 1),
+                video: TtlMap::new(VIDEO_TTL, VIDEO_CAPACITY),
+                sessions: TtlMap::new(SESSION_TTL, SESSION_CAPACITY),
+                missing_sessions: TtlMap::new(MISSING_SESSION_TTL, MISSING_SESSION_CAPACITY),
+                tokens: TtlMap::new(TOKEN_TTL, TOKEN_CAPACITY),
+                missing_tokens: TtlMap::new(MISSING_SESSION_TTL, MISSING_SESSION_CAPACITY),
+            }),
+        }
+    }
+
+    pub(crate) fn videos(&self) -> Option<Arc<Vec<Video>>> {
+        self.inner.videos.get(&())
+    }
+
+    pub(crate) fn set_videos(&self, videos: Arc<Vec<Video>>) {
+        self.inner.videos.insert((), videos);
+    }
+
+    /// `Some(None)` when the video is known not to exist.
+    pub(crate) fn video(&self, id: &Uuid) -> Option<Option<Video>> {
+        self.inner.video.get(id)
+    }
+
+    pub(crate) fn set_video(&self, id: Uuid, video: Option<Video>) {
+        self.inner.video.insert(id, video);
+    }
+
+    /// Drops a video and the listing it's part of, for when it was added,
+    /// changed or removed.
+    pub(crate) fn invalidate_video(&self, id: &Uuid) {
+        self.inner.video.remove(id);
+        self.inner.videos.clear();
+    }
+
+    /// `Some(None)` when the token is known not to belong to a valid session.
+    pub(crate) fn session(&self, token: &str) -> Option<Option<User>> {
+        if let Some(user) = self.inner.sessions.get(token) {
+            return Some(Some(user));
+        }
+
+        self.inner.missing_sessions.get(token).map(|()| None)
+    }
+
+    pub(crate) fn set_session(&self, token: String, user: Option<User>) {
+        match user {
+            Some(user) => {
+                self.inner.missing_sessions.remove(&token);
+                self.inner.sessions.insert(token, user);
+            }
+            None => {
+                self.inner.sessions.remove(&token);
+                self.inner.missing_sessions.insert(token, ());
+            }
+        }
+    }
+
+    pub(crate) fn invalidate_session(&self, token: &str) {
+        self.inner.sessions.remove(token);
+    }
+
+    /// Drops every cached session and API token, for when users changed.
+    pub(crate) fn invalidate_sessions(&self) {
+        self.inner.sessions.clear();
+        self.inner.tokens.clear();
+    }
+
+    /// `Some(None)` when the hash is known not to belong to a valid token.
+    pub(crate) fn token(&self, hash: &str) -> Option<Option<TokenGrant>> {
+        if let Some(grant) = self.inner.tokens.get(hash) {
+            return Some(Some(grant));
+        }
+
+        self.inner.missing_tokens.get(hash).map(|()| None)
+    }
+
+    pub(crate) fn set_token(&self, hash: String, grant: Option<TokenGrant>) {
+        match grant {
+            Some(grant) => {
+                self.inner.missing_tokens.remove(&hash);
+                self.inner.tokens.insert(hash, grant);
+            }
+            None => {
+                self.inner.tokens.remove(&hash);
+                self.inner.missing_tokens.insert(hash, ());
+            }
+        }
+    }
+
+    pub(crate) fn invalidate_token(&self, hash: &str) {
+        self.inner.tokens.remove(hash);
+    }
+}
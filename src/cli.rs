@@ -0,0 +1,299 @@
+use std::io::BufRead as _;
+
+use sqlx::SqlitePool;
+
+use crate::{
+    cache::Cache,
+    config::Config,
+    database,
+    error::Error,
+    handlers,
+    models::{Role, Scope, Visibility},
+};
+
+#[derive(clap::Subcommand, Debug)]
+pub(crate) enum UserCommand {
+    /// Create a new user
+    Add {
+        username: String,
+
+        /// What the user is allowed to do
+        #[clap(long, value_enum, default_value = "viewer")]
+        role: Role,
+
+        /// Read the password from the first line of stdin instead of prompting
+        #[clap(long, value_parser)]
+        password_stdin: bool,
+    },
+
+    /// List all users
+    List,
+
+    /// Change the password of a user
+    Passwd {
+        username: String,
+
+        /// Read the password from the first line of stdin instead of prompting
+        #[clap(long, value_parser)]
+        password_stdin: bool,
+    },
+
+    /// Change the role of a user
+    Role {
+        username: String,
+
+        #[clap(value_enum)]
+        role: Role,
+    },
+
+    /// Remove a user and sign them out everywhere
+    Remove { username: String },
+
+    /// Stop a user from signing in and sign them out everywhere
+    Disable { username: String },
+
+    /// Allow a disabled user to sign in again
+    Enable { username: String },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub(crate) enum TokenCommand {
+    /// Create an API token for a user and print it
+    Create {
+        username: String,
+
+        /// A name to recognize the token by
+        name: String,
+
+        /// What the token may be used for, can be repeated
+        #[clap(long, value_enum, required = true)]
+        scope: Vec<Scope>,
+
+        /// Days until the token expires, it never does by default
+        #[clap(long, value_parser)]
+        expires_days: Option<u32>,
+    },
+
+    /// List the API tokens of a user
+    List { username: String },
+
+    /// Revoke an API token of a user
+    Revoke { username: String, key: String },
+
+    /// Print a ShareX custom uploader config for the token read from stdin
+    Sharex {
+        /// The URL hawk is reached at, e.g. `https://videos.example.com`
+        #[clap(long, value_parser)]
+        url: String,
+
+        /// Visibility of the uploaded videos
+        #[clap(long, value_enum, default_value = "unlisted")]
+        visibility: Visibility,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub(crate) enum ConfigCommand {
+    /// Print the effective configuration as TOML
+    Show,
+}
+
+pub(crate) fn config(config: &Config, command: ConfigCommand) -> Result<(), Error> {
+    match command {
+        ConfigCommand::Show => {
+            let toml = toml::to_string_pretty(config)
+                .map_err(|err| Error::Config(format!("unable to serialize: {}", err)))?;
+
+            print!("{}", toml);
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn user(pool: &SqlitePool, command: UserCommand) -> Result<(), Error> {
+    match command {
+        UserCommand::Add {
+            username,
+            role,
+            password_stdin,
+        } => {
+            // don't ask for a password that can't be used
+            if database::db_get_account(pool, &username).await?.is_some() {
+                return Err(Error::UserExists(username));
+            }
+
+            let password = read_password(password_stdin)?;
+
+            database::db_create_user(pool, &username, &password, role).await?;
+
+            println!("added {} {}", role.as_str(), username);
+        }
+        UserCommand::List => {
+            let accounts = database::db_get_accounts(pool).await?;
+
+            println!(
+                "{:<24} {:<9} {:<20} {:<9} SESSIONS",
+                "USERNAME", "ROLE", "CREATED", "DISABLED"
+            );
+
+            for account in accounts {
+                println!(
+                    "{:<24} {:<9} {:<20} {:<9} {}",
+                    account.username,
+                    account.role.as_str(),
+                    account.created.unwrap_or_default(),
+                    if account.disabled { "yes" } else { "no" },
+                    account.sessions
+                );
+            }
+        }
+        UserCommand::Passwd {
+            username,
+            password_stdin,
+        } => {
+            let id = database::db_get_user_id(pool, &username).await?;
+
+            database::db_set_password(pool, &id, &read_password(password_stdin)?).await?;
+
+            println!("changed password of {}", username);
+        }
+        UserCommand::Role { username, role } => {
+            let id = database::db_get_user_id(pool, &username).await?;
+
+            database::db_set_role(pool, &id, role).await?;
+
+            println!("{} is now {}", username, role.as_str());
+        }
+        UserCommand::Remove { username } => {
+            let id = database::db_get_user_id(pool, &username).await?;
+
+            let revoked = database::db_remove_user(pool, &id).await?;
+
+            println!("removed user {}, revoked {} sessions", username, revoked);
+        }
+        UserCommand::Disable { username } => {
+            let id = database::db_get_user_id(pool, &username).await?;
+
+            let revoked = database::db_set_disabled(pool, &id, true).await?;
+
+            println!("disabled user {}, revoked {} sessions", username, revoked);
+        }
+        UserCommand::Enable { username } => {
+            let id = database::db_get_user_id(pool, &username).await?;
+
+            database::db_set_disabled(pool, &id, false).await?;
+
+            println!("enabled user {}", username);
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn token(pool: &SqlitePool, command: TokenCommand) -> Result<(), Error> {
+    match command {
+        TokenCommand::Create {
+            username,
+            name,
+            scope,
+            expires_days,
+        } => {
+            let account = database::db_get_account(pool, &username)
+                .await?
+                .ok_or_else(|| Error::UnknownUser(username.clone()))?;
+            let id = database::db_get_user_id(pool, &username).await?;
+
+            let scopes = Scope::ALL
+                .iter()
+                .copied()
+                .filter(|known| scope.contains(known))
+                .collect::<Vec<_>>();
+
+            if let Some(scope) = scopes.iter().find(|scope| scope.role() > account.role) {
+                eprintln!(
+                    "warning: {} is {} and can't make use of the {} scope",
+                    username,
+                    account.role.as_str(),
+                    scope.as_str()
+                );
+            }
+
+            let (key, token) =
+                database::db_create_token(pool, &id, &name, &scopes, expires_days).await?;
+
+            eprintln!("created token {} for {}", key, username);
+            println!("{}", token);
+        }
+        TokenCommand::List { username } => {
+            let id = database::db_get_user_id(pool, &username).await?;
+
+            println!(
+                "{:<16} {:<24} {:<18} {:<20} {:<20} LAST USED",
+                "KEY", "NAME", "SCOPES", "CREATED", "EXPIRES"
+            );
+
+            for token in database::db_get_tokens(pool, &id).await? {
+                println!(
+                    "{:<16} {:<24} {:<18} {:<20} {:<20} {}",
+                    token.key,
+                    token.name,
+                    token.scopes,
+                    token.display_created(),
+                    if token.expired {
+                        "expired"
+                    } else {
+                        token.display_expires()
+                    },
+                    token.display_last_used()
+                );
+            }
+        }
+        TokenCommand::Revoke { username, key } => {
+            let id = database::db_get_user_id(pool, &username).await?;
+
+            // a running server may keep accepting the token until its cache
+            // entry expires
+            if !database::db_revoke_token(pool, &Cache::new(), &id, &key).await? {
+                return Err(Error::UnknownToken(key));
+            }
+
+            println!("revoked token {} of {}", key, username);
+        }
+        TokenCommand::Sharex { url, visibility } => {
+            let mut token = String::new();
+            std::io::stdin().lock().read_line(&mut token)?;
+
+            let token = token.trim();
+            if token.is_empty() {
+                return Err(Error::UnknownToken(token.to_string()));
+            }
+
+            let request_url = format!("{}/upload/sharex", url.trim_end_matches('/'));
+            let sxcu = handlers::sharex_config(&request_url, token, visibility);
+
+            println!("{}", serde_json::to_string_pretty(&sxcu)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prompts for a password twice, or takes the first line of stdin so accounts
+/// can be managed from scripts.
+fn read_password(stdin: bool) -> Result<String, Error> {
+    if stdin {
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line)?;
+
+        return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    let password = rpassword::prompt_password("Password: ")?;
+
+    if password != rpassword::prompt_password("Confirm password: ")? {
+        return Err(Error::Password("passwords do not match"));
+    }
+
+    Ok(password)
+}
@@ -0,0 +1,405 @@
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use ipnet::IpNet;
+
+use crate::{error::Error, listen::ListenAddr, media::Rendition};
+
+/// Prefix of the environment variables that override the config file, a
+/// setting's variable is its section and key in upper case, e.g.
+/// `HAWK_SERVER_PORT` or `HAWK_MEDIA_FFMPEG`.
+const ENV_PREFIX: &str = "HAWK_";
+
+/// The effective configuration, built from the defaults, the config file, the
+/// `HAWK_*` environment variables and the command line, in that order.
+///
+/// Relative paths are resolved against the working directory.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    /// Log filter used by the server when `RUST_LOG` isn't set.
+    pub log: String,
+    pub server: ServerConfig,
+    pub paths: PathsConfig,
+    pub media: MediaConfig,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ServerConfig {
+    /// `::` also accepts IPv4 connections where the system allows dual-stack
+    /// sockets.
+    pub host: IpAddr,
+    pub port: u16,
+    /// Serve HTTPS using `cert` and `key`, turn off when a reverse proxy
+    /// terminates TLS.
+    pub tls: bool,
+    /// Reloaded on `SIGHUP` or when either file changes.
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Port of an optional plain HTTP listener that redirects to HTTPS.
+    pub redirect_port: Option<u16>,
+    /// Path hawk is served under behind a reverse proxy, like `/hawk`. The
+    /// proxy passes the full path on, the prefix is part of every route.
+    pub base_path: String,
+    /// Where clients reach hawk, like `https://videos.example.com`, without
+    /// `base_path`. Links that leave the browser, like the ones handed to
+    /// ShareX, are built from it and aren't offered when it's unset.
+    pub public_url: Option<String>,
+    /// Proxies whose `Forwarded`, `X-Forwarded-For` and `X-Forwarded-Proto`
+    /// headers are believed, connections over unix sockets always are.
+    pub trusted_proxies: Vec<IpNet>,
+    /// Addresses to listen on besides `host` and `port`.
+    pub listeners: Vec<ListenerConfig>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ListenerConfig {
+    pub address: ListenAddr,
+    /// Defaults to `server.tls` for TCP and to off for Unix sockets.
+    pub tls: Option<bool>,
+}
+
+impl ListenerConfig {
+    pub(crate) fn tls(&self, server: &ServerConfig) -> bool {
+        match self.address {
+            ListenAddr::Tcp(_) => self.tls.unwrap_or(server.tls),
+            ListenAddr::Unix(_) => self.tls.unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct PathsConfig {
+    pub database: PathBuf,
+    /// Uploaded videos and their renditions.
+    pub videos: PathBuf,
+    /// Thumbnails.
+    pub images: PathBuf,
+    /// HLS playlists and segments, one directory per video.
+    pub hls: PathBuf,
+    /// Uploads that are still being received.
+    pub staging: PathBuf,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct MediaConfig {
+    pub ffmpeg: PathBuf,
+    pub ffprobe: PathBuf,
+    /// Thumbnails are scaled down to fit into these bounds.
+    pub thumbnail_width: u32,
+    pub thumbnail_height: u32,
+    /// Also encode uploads into a VP9/Opus WebM rendition.
+    pub webm: bool,
+    /// Also package uploads for adaptive streaming over HLS.
+    pub hls: bool,
+    /// How many media processing jobs may run at the same time.
+    pub workers: usize,
+    /// How many times a failed job is attempted before giving up.
+    pub max_attempts: i64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            log: "hawk=debug,sqlx=debug,tower_http=debug".into(),
+            server: ServerConfig::default(),
+            paths: PathsConfig::default(),
+            media: MediaConfig::default(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: Ipv4Addr::UNSPECIFIED.into(),
+            port: 25575,
+            tls: true,
+            cert: "cert.pem".into(),
+            key: "key.pem".into(),
+            redirect_port: None,
+            base_path: String::new(),
+            public_url: None,
+            trusted_proxies: Vec::new(),
+            listeners: Vec::new(),
+        }
+    }
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        let assets = Path::new("assets");
+
+        Self {
+            database: "hawk.db".into(),
+            videos: assets.join("video"),
+            images: assets.join("images"),
+            hls: assets.join("hls"),
+            staging: assets.join("staging"),
+        }
+    }
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            ffmpeg: "ffmpeg".into(),
+            ffprobe: "ffprobe".into(),
+            thumbnail_width: 1920 / 5,
+            thumbnail_height: 1080 / 5,
+            webm: false,
+            hls: false,
+            workers: 2,
+            max_attempts: 5,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file, if any, and applies the environment on top of it.
+    pub(crate) fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let mut config = match path {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path).map_err(|err| {
+                    Error::Config(format!("unable to read {}: {}", path.display(), err))
+                })?;
+
+                toml::from_str(&contents).map_err(|err| {
+                    Error::Config(format!("unable to parse {}: {}", path.display(), err))
+                })?
+            }
+            None => Self::default(),
+        };
+
+        config.apply_env()?;
+
+        let base_path = config.server.base_path.trim_matches('/');
+        config.server.base_path = match base_path {
+            "" => String::new(),
+            base_path => format!("/{}", base_path),
+        };
+
+        if let Some(public_url) = &mut config.server.public_url {
+            public_url.truncate(public_url.trim_end_matches('/').len());
+        }
+
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), Error> {
+        env("LOG", &mut self.log)?;
+
+        env("SERVER_HOST", &mut self.server.host)?;
+        env("SERVER_PORT", &mut self.server.port)?;
+        env("SERVER_TLS", &mut self.server.tls)?;
+        env("SERVER_CERT", &mut self.server.cert)?;
+        env("SERVER_KEY", &mut self.server.key)?;
+        env_opt("SERVER_REDIRECT_PORT", &mut self.server.redirect_port)?;
+
+        env("SERVER_BASE_PATH", &mut self.server.base_path)?;
+        env_opt("SERVER_PUBLIC_URL", &mut self.server.public_url)?;
+        env_list("SERVER_TRUSTED_PROXIES", &mut self.server.trusted_proxies)?;
+
+        // listeners from the environment use the default TLS setting
+        let mut addresses = Vec::new();
+        env_list("SERVER_LISTENERS", &mut addresses)?;
+        if !addresses.is_empty() {
+            self.server.listeners = addresses
+                .into_iter()
+                .map(|address| ListenerConfig { address, tls: None })
+                .collect();
+        }
+
+        env("PATHS_DATABASE", &mut self.paths.database)?;
+        env("PATHS_VIDEOS", &mut self.paths.videos)?;
+        env("PATHS_IMAGES", &mut self.paths.images)?;
+        env("PATHS_HLS", &mut self.paths.hls)?;
+        env("PATHS_STAGING", &mut self.paths.staging)?;
+
+        env("MEDIA_FFMPEG", &mut self.media.ffmpeg)?;
+        env("MEDIA_FFPROBE", &mut self.media.ffprobe)?;
+        env("MEDIA_THUMBNAIL_WIDTH", &mut self.media.thumbnail_width)?;
+        env("MEDIA_THUMBNAIL_HEIGHT", &mut self.media.thumbnail_height)?;
+        env("MEDIA_WEBM", &mut self.media.webm)?;
+        env("MEDIA_HLS", &mut self.media.hls)?;
+        env("MEDIA_WORKERS", &mut self.media.workers)?;
+        env("MEDIA_MAX_ATTEMPTS", &mut self.media.max_attempts)?;
+
+        Ok(())
+    }
+
+    /// Checks the settings that would otherwise only fail once they're used.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if let Err(err) = tracing_subscriber::EnvFilter::try_new(&self.log) {
+            return Err(Error::Config(format!("invalid log filter: {}", err)));
+        }
+
+        match self.server.redirect_port {
+            Some(_) if !self.server.tls => {
+                return Err(Error::Config(
+                    "redirect_port requires tls to be enabled".into(),
+                ));
+            }
+            Some(port) if port == self.server.port => {
+                return Err(Error::Config("redirect_port must differ from port".into()));
+            }
+            _ => {}
+        }
+
+        if let Some(public_url) = &self.server.public_url {
+            let host = public_url
+                .strip_prefix("https://")
+                .or_else(|| public_url.strip_prefix("http://"));
+
+            match host {
+                Some(host) if !host.is_empty() && !host.contains('/') => {}
+                _ => {
+                    return Err(Error::Config(format!(
+                        "public_url must be an http or https URL without a path, got {}",
+                        public_url
+                    )));
+                }
+            }
+        }
+
+        for listener in &self.server.listeners {
+            if let ListenAddr::Unix(_) = listener.address {
+                if cfg!(not(unix)) {
+                    return Err(Error::Config(format!(
+                        "{}: unix sockets aren't supported on this platform",
+                        listener.address
+                    )));
+                }
+
+                if listener.tls(&self.server) {
+                    return Err(Error::Config(format!(
+                        "{}: tls isn't supported on unix sockets",
+                        listener.address
+                    )));
+                }
+            }
+        }
+
+        if self.media.thumbnail_width == 0 || self.media.thumbnail_height == 0 {
+            return Err(Error::Config("thumbnail size must not be zero".into()));
+        }
+
+        if self.media.workers == 0 {
+            return Err(Error::Config("at least one worker is required".into()));
+        }
+
+        if self.media.max_attempts < 1 {
+            return Err(Error::Config("max_attempts must be at least 1".into()));
+        }
+
+        for (name, path) in [
+            ("videos", &self.paths.videos),
+            ("images", &self.paths.images),
+            ("hls", &self.paths.hls),
+            ("staging", &self.paths.staging),
+        ] {
+            if path.as_os_str().is_empty() {
+                return Err(Error::Config(format!("paths.{} must not be empty", name)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prefixes an absolute path with the base path.
+    pub(crate) fn url(&self, path: &str) -> String {
+        format!("{}{}", self.server.base_path, path)
+    }
+
+    /// The path the session cookie is scoped to.
+    pub(crate) fn cookie_path(&self) -> &str {
+        match self.server.base_path.as_str() {
+            "" => "/",
+            base_path => base_path,
+        }
+    }
+
+    /// Renditions produced for every uploaded video.
+    pub(crate) fn renditions(&self) -> Vec<Rendition> {
+        let mut renditions = vec![Rendition::H264];
+        if self.media.webm {
+            renditions.push(Rendition::Vp9);
+        }
+
+        renditions
+    }
+}
+
+fn env<T>(key: &str, value: &mut T) -> Result<(), Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some(parsed) = parse_env(key)? {
+        *value = parsed;
+    }
+
+    Ok(())
+}
+
+/// Like [`env`], an empty variable unsets the value.
+fn env_opt<T>(key: &str, value: &mut Option<T>) -> Result<(), Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(format!("{}{}", ENV_PREFIX, key)) {
+        Ok(var) if var.is_empty() => *value = None,
+        Ok(_) => *value = parse_env(key)?,
+        Err(_) => {}
+    }
+
+    Ok(())
+}
+
+/// Like [`env`], for comma separated lists.
+fn env_list<T>(key: &str, value: &mut Vec<T>) -> Result<(), Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let name = format!("{}{}", ENV_PREFIX, key);
+
+    if let Ok(var) = std::env::var(&name) {
+        *value = var
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                item.parse()
+                    .map_err(|err| Error::Config(format!("invalid {}: {}", name, err)))
+            })
+            .collect::<Result<_, _>>()?;
+    }
+
+    Ok(())
+}
+
+fn parse_env<T>(key: &str) -> Result<Option<T>, Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let name = format!("{}{}", ENV_PREFIX, key);
+
+    match std::env::var(&name) {
+        Ok(var) => var
+            .parse()
+            .map(Some)
+            .map_err(|err| Error::Config(format!("invalid {}: {}", name, err))),
+        Err(_) => Ok(None),
+    }
+}
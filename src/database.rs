@@ -1,74 +1,1690 @@
 use std::{
-    collections::HashMap,
-    sync::atomic::{AtomicBool, Ordering},
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
 };
 
-use once_cell::sync::Lazy;
-use sqlx::SqlitePool;
-use tokio::sync::RwLock;
+use sha2::{Digest as _, Sha256};
+use sqlx::{FromRow as _, QueryBuilder, Row as _, Sqlite, SqlitePool};
 use uuid::Uuid;
 
-use crate::error::Error;
+use crate::{
+    auth::{self, Client},
+    cache::Cache,
+    config::Config,
+    error::Error,
+    models::{
+        Account, ApiToken, Collection, Playlist, Rendition, Role, Scope, Session, Sort, Tag,
+        TokenGrant, User, Video, VideoDetails, Visibility,
+    },
+    pagination::{Cursor, VideoFilter, VideoPage},
+    search::{Fragment, Search, SearchHit, SearchPage, HIGHLIGHT_START},
+};
+
+pub(crate) async fn db_get_all_videos(
+    pool: &SqlitePool,
+    cache: &Cache,
+) -> Result<Vec<Video>, Error> {
+    if let Some(videos) = cache.videos() {
+        return Ok(videos.as_ref().clone());
+    }
+
+    let videos = sqlx::query_as!(
+        Video,
+        r#"SELECT id as "id: Uuid", ext, owner_id as "owner_id: Uuid", visibility as "visibility: Visibility", duration, width, height, video_codec, audio_codec, bitrate, size, title, description, recorded, created as "created!: String", views FROM videos ORDER BY created DESC"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    cache.set_videos(Arc::new(videos.clone()));
+
+    Ok(videos)
+}
+
+/// The videos listed for `user`, newest first.
+pub(crate) async fn db_get_listed_videos(
+    pool: &SqlitePool,
+    cache: &Cache,
+    user: Option<&User>,
+) -> Result<Vec<Video>, Error> {
+    let shared = match user {
+        Some(user) => db_get_shared_video_ids(pool, &user.id).await?,
+        None => HashSet::new(),
+    };
+
+    let videos = db_get_all_videos(pool, cache)
+        .await?
+        .into_iter()
+        .filter(|video| video.is_listed_for(user, shared.contains(&video.id)))
+        .collect();
+
+    Ok(videos)
+}
+
+/// The columns of [`Video`], for queries that are put together at runtime.
+const VIDEO_COLUMNS: &str = "id, ext, owner_id, visibility, duration, width, height, video_codec, audio_codec, bitrate, size, title, description, recorded, created, views";
+
+/// Adds the conditions for the videos listed for `user` that pass `filter`.
+fn push_video_filter(
+    query: &mut QueryBuilder<'_, Sqlite>,
+    user: Option<&User>,
+    filter: &VideoFilter<'_>,
+) {
+    // mirrors `Video::is_listed_for`
+    match user {
+        Some(user) if user.is_admin() => {
+            query.push("TRUE");
+        }
+        Some(user) => {
+            query
+                .push("(visibility = 'public' OR owner_id = ")
+                .push_bind(user.id)
+                .push(" OR (visibility = 'shared' AND id IN (SELECT video_id FROM video_shares WHERE user_id = ")
+                .push_bind(user.id)
+                .push(")))");
+        }
+        None => {
+            query.push("visibility = 'public'");
+        }
+    }
+
+    if let Some(visibility) = filter.visibility {
+        query.push(" AND visibility = ").push_bind(visibility);
+    }
 
-pub(crate) static DB_GET_ALL_VIDEOS_CACHE_INVALIDATE: AtomicBool = AtomicBool::new(false);
+    if let Some(owner_id) = filter.owner_id {
+        query.push(" AND owner_id = ").push_bind(owner_id);
+    }
 
-pub(crate) async fn db_get_all_videos(pool: SqlitePool) -> Result<Vec<Uuid>, Error> {
-    static DB_GET_ALL_VIDEOS_CACHE: Lazy<RwLock<Vec<Uuid>>> = Lazy::new(|| RwLock::new(Vec::new()));
+    let search_tags = filter.search.map(Search::tags).unwrap_or_default();
+    let tags = filter
+        .tags
+        .iter()
+        .chain(search_tags)
+        .collect::<HashSet<_>>();
+    if !tags.is_empty() {
+        query.push(
+            " AND id IN (SELECT video_tags.video_id FROM video_tags JOIN tags ON tags.id = video_tags.tag_id WHERE tags.name IN (",
+        );
+
+        let mut names = query.separated(", ");
+        for tag in &tags {
+            names.push_bind(tag.to_string());
+        }
+
+        query
+            .push(") GROUP BY video_tags.video_id HAVING COUNT(*) = ")
+            .push_bind(tags.len() as i64)
+            .push(")");
+    }
 
-    if DB_GET_ALL_VIDEOS_CACHE_INVALIDATE.load(Ordering::Acquire) {
-        DB_GET_ALL_VIDEOS_CACHE.write().await.clear();
-        DB_GET_ALL_VIDEOS_CACHE_INVALIDATE.store(false, Ordering::Release);
+    if let Some(search) = filter.search {
+        search.push_conditions(query);
     }
+}
+
+/// Narrows a query down to the videos matching `search`'s terms, if it has any.
+fn push_search_terms(query: &mut QueryBuilder<'_, Sqlite>, search: Option<&Search>) {
+    if let Some(terms) = search.and_then(Search::fts_query) {
+        query
+            .push(" AND id IN (SELECT video_id FROM videos_fts WHERE videos_fts MATCH ")
+            .push_bind(terms)
+            .push(")");
+    }
+}
+
+/// A page of the videos listed for `user` that pass `filter`, in `sort` order
+/// and skipping `offset` videos after `cursor`.
+pub(crate) async fn db_get_video_page(
+    pool: &SqlitePool,
+    user: Option<&User>,
+    filter: &VideoFilter<'_>,
+    sort: Sort,
+    cursor: Option<&str>,
+    offset: usize,
+    limit: usize,
+) -> Result<VideoPage, Error> {
+    let cursor = cursor
+        .map(|cursor| Cursor::decode_for(cursor, sort))
+        .transpose()?;
+
+    let total = db_count_videos(pool, user, filter).await?;
+
+    let key = sort.key_sql();
+    let direction = sort.direction_sql();
+
+    let mut query = QueryBuilder::new(format!(
+        "SELECT {}, {} AS sort_key FROM videos WHERE ",
+        VIDEO_COLUMNS, key
+    ));
+    push_video_filter(&mut query, user, filter);
+    push_search_terms(&mut query, filter.search);
+
+    if let Some(cursor) = &cursor {
+        cursor.push_condition(&mut query);
+    }
+
+    // one more than asked for, to tell whether there's a next page
+    query
+        .push(format_args!(
+            " ORDER BY sort_key {}, id {} LIMIT ",
+            direction, direction
+        ))
+        .push_bind(limit.saturating_add(1) as i64)
+        .push(" OFFSET ")
+        .push_bind(offset as i64);
+
+    let rows = query.build().fetch_all(pool).await?;
+
+    VideoPage::from_rows(rows, sort, limit, total)
+}
+
+async fn db_count_videos(
+    pool: &SqlitePool,
+    user: Option<&User>,
+    filter: &VideoFilter<'_>,
+) -> Result<usize, Error> {
+    let mut count = QueryBuilder::new("SELECT COUNT(*) FROM videos WHERE ");
+    push_video_filter(&mut count, user, filter);
+    push_search_terms(&mut count, filter.search);
+
+    let total = count.build().fetch_one(pool).await?.try_get::<i64, _>(0)?;
+
+    Ok(total as usize)
+}
+
+/// The renditions of a video, in the order browsers should try them.
+pub(crate) async fn db_get_renditions(
+    pool: &SqlitePool,
+    video_id: &Uuid,
+) -> Result<Vec<Rendition>, Error> {
+    // h264 sorts before vp9, browsers pick the first source they can play
+    let renditions = sqlx::query_as!(
+        Rendition,
+        "SELECT name, ext FROM renditions WHERE video_id = ? ORDER BY name",
+        video_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(renditions)
+}
+
+/// Counts a view of the video's page.
+///
+/// The cached videos aren't invalidated, so the count they have lags behind a
+/// little.
+pub(crate) async fn db_add_view(pool: &SqlitePool, id: &Uuid) -> Result<(), Error> {
+    sqlx::query!("UPDATE videos SET views = views + 1 WHERE id = ?", id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// The videos listed for `user` that pass `filter` and match `search`, best
+/// matches first when it has terms to look up and newest first otherwise.
+pub(crate) async fn db_search_videos(
+    pool: &SqlitePool,
+    user: Option<&User>,
+    search: &Search,
+    filter: &VideoFilter<'_>,
+    offset: usize,
+    limit: usize,
+) -> Result<SearchPage, Error> {
+    let filter = VideoFilter {
+        search: Some(search),
+        ..*filter
+    };
+
+    let terms = match search.fts_query() {
+        Some(terms) => terms,
+        None => {
+            let page =
+                db_get_video_page(pool, user, &filter, Sort::Newest, None, offset, limit).await?;
+
+            return Ok(SearchPage {
+                hits: page.videos.into_iter().map(SearchHit::plain).collect(),
+                total: page.total,
+            });
+        }
+    };
+
+    let total = db_count_videos(pool, user, &filter).await?;
+
+    // titles are weighted highest, then tags, descriptions and file names
+    let mut query = QueryBuilder::new(format!(
+        "SELECT {}, matches.highlighted_title, matches.snippet FROM videos JOIN (SELECT video_id, highlight(videos_fts, 1, char(2), char(3)) AS highlighted_title, snippet(videos_fts, 2, char(2), char(3), '…', 24) AS snippet, bm25(videos_fts, 0.0, 10.0, 2.0, 5.0, 1.0) AS rank FROM videos_fts WHERE videos_fts MATCH ",
+        VIDEO_COLUMNS
+    ));
+    query
+        .push_bind(terms)
+        .push(") AS matches ON matches.video_id = videos.id WHERE ");
+    push_video_filter(&mut query, user, &filter);
+    query
+        .push(" ORDER BY matches.rank, id LIMIT ")
+        .push_bind(limit as i64)
+        .push(" OFFSET ")
+        .push_bind(offset as i64);
+
+    let rows = query.build().fetch_all(pool).await?;
 
-    if DB_GET_ALL_VIDEOS_CACHE.read().await.is_empty() {
-        let videos =
-            sqlx::query_scalar!(r#"SELECT id as "id: Uuid" FROM videos ORDER BY created DESC"#)
-                .fetch_all(&pool)
+    let hits = rows
+        .iter()
+        .map(|row| {
+            let video = Video::from_row(row)?;
+
+            let title = match row
+                .try_get::<Option<String>, _>("highlighted_title")?
+                .filter(|title| !title.is_empty())
+            {
+                Some(title) => Fragment::split(&title),
+                None => Fragment::plain(video.display_title()),
+            };
+
+            // the snippet is empty when the description has no match
+            let snippet = match row.try_get::<Option<String>, _>("snippet")? {
+                Some(snippet) if snippet.contains(HIGHLIGHT_START) => Fragment::split(&snippet),
+                _ => Vec::new(),
+            };
+
+            Ok(SearchHit {
+                video,
+                title,
+                snippet,
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+
+    Ok(SearchPage { hits, total })
+}
+
+pub(crate) async fn db_get_video(
+    pool: &SqlitePool,
+    cache: &Cache,
+    id: &Uuid,
+) -> Result<Option<Video>, Error> {
+    if let Some(video) = cache.video(id) {
+        return Ok(video);
+    }
+
+    let video = sqlx::query_as!(
+        Video,
+        r#"SELECT id as "id: Uuid", ext, owner_id as "owner_id: Uuid", visibility as "visibility: Visibility", duration, width, height, video_codec, audio_codec, bitrate, size, title, description, recorded, created as "created!: String", views FROM videos WHERE id = ?"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    cache.set_video(*id, video.clone());
+
+    Ok(video)
+}
+
+/// IDs of the videos that have been shared with `user_id`.
+pub(crate) async fn db_get_shared_video_ids(
+    pool: &SqlitePool,
+    user_id: &Uuid,
+) -> Result<HashSet<Uuid>, Error> {
+    let ids = sqlx::query_scalar!(
+        r#"SELECT video_id as "video_id: Uuid" FROM video_shares WHERE user_id = ?"#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(ids.into_iter().collect())
+}
+
+pub(crate) async fn db_is_video_shared(
+    pool: &SqlitePool,
+    video_id: &Uuid,
+    user_id: &Uuid,
+) -> Result<bool, Error> {
+    let shared = sqlx::query_scalar!(
+        "SELECT 1 FROM video_shares WHERE video_id = ? AND user_id = ?",
+        video_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(shared.is_some())
+}
+
+/// Usernames of the users `video_id` has been shared with.
+pub(crate) async fn db_get_video_shares(
+    pool: &SqlitePool,
+    video_id: &Uuid,
+) -> Result<Vec<String>, Error> {
+    let usernames = sqlx::query_scalar!(
+        "SELECT users.username FROM video_shares JOIN users ON users.id = video_shares.user_id WHERE video_shares.video_id = ? ORDER BY users.username",
+        video_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(usernames)
+}
+
+/// Changes who can find and watch a video, `shared_with` replaces the users it
+/// is shared with and is ignored unless the video becomes
+/// [`Visibility::Shared`].
+pub(crate) async fn db_set_visibility(
+    pool: &SqlitePool,
+    cache: &Cache,
+    id: &Uuid,
+    visibility: Visibility,
+    shared_with: &[String],
+) -> Result<(), Error> {
+    let mut trans = pool.begin().await?;
+
+    set_visibility(&mut trans, id, visibility, shared_with).await?;
+
+    trans.commit().await?;
+
+    cache.invalidate_video(id);
+
+    Ok(())
+}
+
+async fn set_visibility(
+    trans: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    id: &Uuid,
+    visibility: Visibility,
+    shared_with: &[String],
+) -> Result<(), Error> {
+    sqlx::query!(
+        "UPDATE videos SET visibility = ? WHERE id = ?",
+        visibility,
+        id
+    )
+    .execute(&mut *trans)
+    .await?;
+
+    sqlx::query!("DELETE FROM video_shares WHERE video_id = ?", id)
+        .execute(&mut *trans)
+        .await?;
+
+    if visibility == Visibility::Shared {
+        for username in shared_with {
+            let user_id = sqlx::query_scalar!(
+                r#"SELECT id as "id: Uuid" FROM users WHERE username = ?"#,
+                username
+            )
+            .fetch_optional(&mut *trans)
+            .await?
+            .ok_or_else(|| Error::UnknownUser(username.clone()))?;
+
+            sqlx::query!(
+                "INSERT OR IGNORE INTO video_shares(video_id, user_id) VALUES (?, ?)",
+                id,
+                user_id
+            )
+            .execute(&mut *trans)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn db_set_details(
+    pool: &SqlitePool,
+    cache: &Cache,
+    id: &Uuid,
+    details: &VideoDetails,
+) -> Result<(), Error> {
+    let mut trans = pool.begin().await?;
+
+    set_details(&mut trans, id, details).await?;
+
+    trans.commit().await?;
+
+    cache.invalidate_video(id);
+
+    Ok(())
+}
+
+async fn set_details(
+    trans: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    id: &Uuid,
+    details: &VideoDetails,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "UPDATE videos SET title = ?, description = ?, recorded = ? WHERE id = ?",
+        details.title,
+        details.description,
+        details.recorded,
+        id
+    )
+    .execute(trans)
+    .await?;
+
+    Ok(())
+}
+
+/// Changes whichever of a video's visibility, along with who it's shared
+/// with, details and tags are given, all of them or none.
+pub(crate) async fn db_update_video(
+    pool: &SqlitePool,
+    cache: &Cache,
+    id: &Uuid,
+    visibility: Option<(Visibility, &[String])>,
+    details: Option<&VideoDetails>,
+    tags: Option<&[String]>,
+) -> Result<(), Error> {
+    let mut trans = pool.begin().await?;
+
+    if let Some((visibility, shared_with)) = visibility {
+        set_visibility(&mut trans, id, visibility, shared_with).await?;
+    }
+
+    if let Some(details) = details {
+        set_details(&mut trans, id, details).await?;
+    }
+
+    if let Some(tags) = tags {
+        set_video_tags(&mut trans, id, tags).await?;
+    }
+
+    trans.commit().await?;
+
+    cache.invalidate_video(id);
+
+    Ok(())
+}
+
+/// The tags of `video_id`, sorted by name.
+pub(crate) async fn db_get_video_tags(
+    pool: &SqlitePool,
+    video_id: &Uuid,
+) -> Result<Vec<String>, Error> {
+    let tags = sqlx::query_scalar!(
+        "SELECT tags.name FROM video_tags JOIN tags ON tags.id = video_tags.tag_id WHERE video_tags.video_id = ? ORDER BY tags.name",
+        video_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tags)
+}
+
+/// The tags of every video that has any, sorted by name.
+pub(crate) async fn db_get_all_video_tags(
+    pool: &SqlitePool,
+) -> Result<HashMap<Uuid, Vec<String>>, Error> {
+    let rows = sqlx::query!(
+        r#"SELECT video_tags.video_id as "video_id: Uuid", tags.name FROM video_tags JOIN tags ON tags.id = video_tags.tag_id ORDER BY tags.name"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut tags = HashMap::<Uuid, Vec<String>>::new();
+    for row in rows {
+        tags.entry(row.video_id).or_default().push(row.name);
+    }
+
+    Ok(tags)
+}
+
+/// The tags of the videos in `ids` that have any, sorted by name.
+pub(crate) async fn db_get_tags_of_videos(
+    pool: &SqlitePool,
+    ids: &[Uuid],
+) -> Result<HashMap<Uuid, Vec<String>>, Error> {
+    let mut tags = HashMap::<Uuid, Vec<String>>::new();
+
+    // SQLite only allows so many variables in one statement
+    for ids in ids.chunks(500) {
+        let mut query = QueryBuilder::new(
+            "SELECT video_tags.video_id, tags.name FROM video_tags JOIN tags ON tags.id = video_tags.tag_id WHERE video_tags.video_id IN (",
+        );
+
+        let mut separated = query.separated(", ");
+        for id in ids {
+            separated.push_bind(*id);
+        }
+
+        query.push(") ORDER BY tags.name");
+
+        for row in query.build().fetch_all(pool).await? {
+            tags.entry(row.try_get("video_id")?)
+                .or_default()
+                .push(row.try_get("name")?);
+        }
+    }
+
+    Ok(tags)
+}
+
+/// Every tag with the number of videos it's on, sorted by name.
+pub(crate) async fn db_get_tags(pool: &SqlitePool) -> Result<Vec<Tag>, Error> {
+    let tags = sqlx::query_as!(
+        Tag,
+        r#"SELECT tags.name, COUNT(video_tags.video_id) as "videos!: i64" FROM tags LEFT JOIN video_tags ON video_tags.tag_id = tags.id GROUP BY tags.id ORDER BY tags.name"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tags)
+}
+
+/// The tags on the videos listed for `user`, counting only those videos.
+pub(crate) async fn db_get_listed_tags(
+    pool: &SqlitePool,
+    cache: &Cache,
+    user: Option<&User>,
+) -> Result<Vec<Tag>, Error> {
+    let videos = db_get_listed_videos(pool, cache, user).await?;
+    let all_tags = db_get_all_video_tags(pool).await?;
+
+    let mut counts = BTreeMap::<&str, i64>::new();
+    for tags in videos.iter().filter_map(|video| all_tags.get(&video.id)) {
+        for tag in tags {
+            *counts.entry(tag).or_default() += 1;
+        }
+    }
+
+    Ok(counts
+        .into_iter()
+        .map(|(name, videos)| Tag {
+            name: name.to_string(),
+            videos,
+        })
+        .collect())
+}
+
+/// Adds `tags`, which have to be normalized, to every video in `video_ids`.
+pub(crate) async fn db_add_tags(
+    pool: &SqlitePool,
+    video_ids: &[Uuid],
+    tags: &[String],
+) -> Result<(), Error> {
+    let mut trans = pool.begin().await?;
+
+    add_tags(&mut trans, video_ids, tags).await?;
+
+    trans.commit().await?;
+
+    Ok(())
+}
+
+async fn add_tags(
+    trans: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    video_ids: &[Uuid],
+    tags: &[String],
+) -> Result<(), Error> {
+    for tag in tags {
+        sqlx::query!("INSERT OR IGNORE INTO tags(name) VALUES (?)", tag)
+            .execute(&mut *trans)
+            .await?;
+
+        for video_id in video_ids {
+            sqlx::query!(
+                "INSERT OR IGNORE INTO video_tags(video_id, tag_id) SELECT ?, id FROM tags WHERE name = ?",
+                video_id,
+                tag
+            )
+            .execute(&mut *trans)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes `tags` from every video in `video_ids`, tags that end up on no
+/// video at all are dropped.
+pub(crate) async fn db_remove_tags(
+    pool: &SqlitePool,
+    video_ids: &[Uuid],
+    tags: &[String],
+) -> Result<(), Error> {
+    let mut trans = pool.begin().await?;
+
+    remove_tags(&mut trans, video_ids, tags).await?;
+
+    trans.commit().await?;
+
+    Ok(())
+}
+
+async fn remove_tags(
+    trans: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    video_ids: &[Uuid],
+    tags: &[String],
+) -> Result<(), Error> {
+    for tag in tags {
+        for video_id in video_ids {
+            sqlx::query!(
+                "DELETE FROM video_tags WHERE video_id = ? AND tag_id = (SELECT id FROM tags WHERE name = ?)",
+                video_id,
+                tag
+            )
+            .execute(&mut *trans)
+            .await?;
+        }
+    }
+
+    prune_tags(trans).await
+}
+
+/// Replaces the tags of `video_id`.
+async fn set_video_tags(
+    trans: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    video_id: &Uuid,
+    tags: &[String],
+) -> Result<(), Error> {
+    let current = sqlx::query_scalar!(
+        "SELECT tags.name FROM video_tags JOIN tags ON tags.id = video_tags.tag_id WHERE video_tags.video_id = ?",
+        video_id
+    )
+    .fetch_all(&mut *trans)
+    .await?;
+
+    let removed = current
+        .into_iter()
+        .filter(|tag| !tags.contains(tag))
+        .collect::<Vec<_>>();
+
+    add_tags(trans, &[*video_id], tags).await?;
+    remove_tags(trans, &[*video_id], &removed).await
+}
+
+/// Renames the tag `from` to `to`, merging it into `to` when that tag exists
+/// already.
+pub(crate) async fn db_rename_tag(pool: &SqlitePool, from: &str, to: &str) -> Result<(), Error> {
+    let mut trans = pool.begin().await?;
+
+    let from_id = sqlx::query_scalar!("SELECT id FROM tags WHERE name = ?", from)
+        .fetch_optional(&mut trans)
+        .await?
+        .ok_or_else(|| Error::UnknownTag(from.to_string()))?;
+
+    let to_id = sqlx::query_scalar!("SELECT id FROM tags WHERE name = ?", to)
+        .fetch_optional(&mut trans)
+        .await?;
+
+    match to_id {
+        Some(to_id) if to_id != from_id => {
+            sqlx::query!(
+                "INSERT OR IGNORE INTO video_tags(video_id, tag_id) SELECT video_id, ? FROM video_tags WHERE tag_id = ?",
+                to_id,
+                from_id
+            )
+            .execute(&mut trans)
+            .await?;
+
+            sqlx::query!("DELETE FROM video_tags WHERE tag_id = ?", from_id)
+                .execute(&mut trans)
                 .await?;
 
-        *DB_GET_ALL_VIDEOS_CACHE.write().await = videos.clone();
+            sqlx::query!("DELETE FROM tags WHERE id = ?", from_id)
+                .execute(&mut trans)
+                .await?;
+        }
+        Some(_) => {}
+        None => {
+            sqlx::query!("UPDATE tags SET name = ? WHERE id = ?", to, from_id)
+                .execute(&mut trans)
+                .await?;
+        }
+    }
+
+    trans.commit().await?;
+
+    Ok(())
+}
+
+/// Removes a tag from every video, returns whether there was such a tag.
+pub(crate) async fn db_remove_tag(pool: &SqlitePool, name: &str) -> Result<bool, Error> {
+    let mut trans = pool.begin().await?;
+
+    sqlx::query!(
+        "DELETE FROM video_tags WHERE tag_id = (SELECT id FROM tags WHERE name = ?)",
+        name
+    )
+    .execute(&mut trans)
+    .await?;
+
+    let removed = sqlx::query!("DELETE FROM tags WHERE name = ?", name)
+        .execute(&mut trans)
+        .await?
+        .rows_affected();
+
+    trans.commit().await?;
+
+    Ok(removed != 0)
+}
+
+async fn prune_tags(trans: &mut sqlx::Transaction<'_, sqlx::Sqlite>) -> Result<(), Error> {
+    sqlx::query!("DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM video_tags)")
+        .execute(trans)
+        .await?;
+
+    Ok(())
+}
+
+/// Removes a video along with its renditions, jobs, shares and files, returns
+/// whether there was such a video.
+pub(crate) async fn db_remove_video(
+    pool: &SqlitePool,
+    cache: &Cache,
+    config: &Config,
+    id: &Uuid,
+) -> Result<bool, Error> {
+    let mut trans = pool.begin().await?;
+
+    let video = sqlx::query!("SELECT ext FROM videos WHERE id = ?;", id)
+        .fetch_optional(&mut trans)
+        .await?;
+
+    let video = match video {
+        Some(video) => video,
+        None => return Ok(false),
+    };
+
+    sqlx::query!("DELETE FROM videos WHERE id = ?;", id)
+        .execute(&mut trans)
+        .await?;
 
-        return Ok(videos);
+    let renditions = sqlx::query!("SELECT name, ext FROM renditions WHERE video_id = ?;", id)
+        .fetch_all(&mut trans)
+        .await?;
+
+    sqlx::query!("DELETE FROM renditions WHERE video_id = ?;", id)
+        .execute(&mut trans)
+        .await?;
+
+    sqlx::query!("DELETE FROM jobs WHERE video_id = ?;", id)
+        .execute(&mut trans)
+        .await?;
+
+    sqlx::query!("DELETE FROM video_shares WHERE video_id = ?;", id)
+        .execute(&mut trans)
+        .await?;
+
+    sqlx::query!("DELETE FROM video_tags WHERE video_id = ?;", id)
+        .execute(&mut trans)
+        .await?;
+
+    sqlx::query!("DELETE FROM playlist_items WHERE video_id = ?;", id)
+        .execute(&mut trans)
+        .await?;
+
+    prune_tags(&mut trans).await?;
+
+    trans.commit().await?;
+
+    cache.invalidate_video(id);
+
+    // only once the rows are gone, a failed commit mustn't leave them pointing
+    // at missing files
+    let renditions = renditions
+        .into_iter()
+        .map(|rendition| (rendition.name, rendition.ext))
+        .collect::<Vec<_>>();
+    if let Err(err) = remove_video_files(config, id, &video.ext, &renditions).await {
+        tracing::warn!("unable to remove the files of video {}: {}", id, err);
     }
 
-    let ids = DB_GET_ALL_VIDEOS_CACHE.read().await.clone();
+    Ok(true)
+}
 
-    Ok(ids)
+/// Removes the original, renditions, thumbnail and HLS files of a video.
+async fn remove_video_files(
+    config: &Config,
+    id: &Uuid,
+    ext: &str,
+    renditions: &[(String, String)],
+) -> Result<(), Error> {
+    let paths = &config.paths;
+
+    remove_file(&paths.videos.join(format!("{}.{}", id, ext))).await?;
+
+    for (name, ext) in renditions {
+        let path = paths.videos.join(format!("{}-{}.{}", id, name, ext));
+        remove_file(&path).await?;
+    }
+
+    // there's no thumbnail until the video has been processed
+    let path = paths.images.join(format!("{}.webp", id));
+    remove_file(&path).await?;
+
+    let path = paths.hls.join(id.to_string());
+    if path.exists() {
+        tokio::fs::remove_dir_all(&path).await?;
+    }
+
+    Ok(())
+}
+
+async fn remove_file(path: &std::path::Path) -> Result<(), Error> {
+    match tokio::fs::remove_file(path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// The sessions of `user_id` that haven't expired, most recently used first.
+/// `current` is the token of the session asking.
+pub(crate) async fn db_get_sessions(
+    pool: &SqlitePool,
+    user_id: &Uuid,
+    current: &str,
+) -> Result<Vec<Session>, Error> {
+    let (lifetime, idle) = (auth::session_lifetime(), auth::session_idle());
+
+    let sessions = sqlx::query_as!(
+        Session,
+        r#"SELECT key as "key!", token = ? as "current!: bool", user_agent, ip, created as "created: String", last_seen as "last_seen: String" FROM sessions WHERE id = ? AND created > DATETIME('now', ?) AND last_seen > DATETIME('now', ?) ORDER BY last_seen DESC"#,
+        current,
+        user_id,
+        lifetime,
+        idle
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(sessions)
+}
+
+/// Revokes the session of `user_id` with `key`, returns whether there was one.
+pub(crate) async fn db_revoke_session(
+    pool: &SqlitePool,
+    cache: &Cache,
+    user_id: &Uuid,
+    key: &str,
+) -> Result<bool, Error> {
+    let token = sqlx::query_scalar!(
+        "SELECT token FROM sessions WHERE key = ? AND id = ?",
+        key,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let token = match token {
+        Some(token) => token,
+        None => return Ok(false),
+    };
+
+    sqlx::query!("DELETE FROM sessions WHERE token = ?", token)
+        .execute(pool)
+        .await?;
+
+    cache.invalidate_session(&token);
+
+    Ok(true)
 }
 
-pub(crate) static DB_IS_SESSION_VALID_CACHE_INVALIDATE: AtomicBool = AtomicBool::new(false);
+pub(crate) async fn db_get_accounts(pool: &SqlitePool) -> Result<Vec<Account>, Error> {
+    let accounts = sqlx::query_as!(
+        Account,
+        r#"SELECT username, role as "role: Role", created as "created: String", disabled, (SELECT COUNT(*) FROM sessions WHERE sessions.id = users.id) as "sessions!: i64" FROM users ORDER BY username"#
+    )
+    .fetch_all(pool)
+    .await?;
 
-pub(crate) async fn db_is_session_valid(pool: SqlitePool, token: &str) -> Result<bool, Error> {
-    static DB_IS_SESSION_VALID_CACHE: Lazy<RwLock<HashMap<String, bool>>> =
-        Lazy::new(|| RwLock::new(HashMap::new()));
+    Ok(accounts)
+}
+
+pub(crate) async fn db_get_account(
+    pool: &SqlitePool,
+    username: &str,
+) -> Result<Option<Account>, Error> {
+    let account = sqlx::query_as!(
+        Account,
+        r#"SELECT username, role as "role: Role", created as "created: String", disabled, (SELECT COUNT(*) FROM sessions WHERE sessions.id = users.id) as "sessions!: i64" FROM users WHERE username = ?"#,
+        username
+    )
+    .fetch_optional(pool)
+    .await?;
 
-    if DB_IS_SESSION_VALID_CACHE_INVALIDATE.load(Ordering::Acquire) {
-        DB_IS_SESSION_VALID_CACHE.write().await.clear();
-        DB_IS_SESSION_VALID_CACHE_INVALIDATE.store(false, Ordering::Release);
+    Ok(account)
+}
+
+pub(crate) async fn db_get_user_id(pool: &SqlitePool, username: &str) -> Result<Uuid, Error> {
+    sqlx::query_scalar!(
+        r#"SELECT id as "id: Uuid" FROM users WHERE username = ?"#,
+        username
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| Error::UnknownUser(username.to_string()))
+}
+
+pub(crate) async fn db_create_user(
+    pool: &SqlitePool,
+    username: &str,
+    password: &str,
+    role: Role,
+) -> Result<Uuid, Error> {
+    let exists = sqlx::query_scalar!("SELECT 1 FROM users WHERE username = ?", username)
+        .fetch_optional(pool)
+        .await?;
+
+    if exists.is_some() {
+        return Err(Error::UserExists(username.to_string()));
     }
 
-    if !DB_IS_SESSION_VALID_CACHE.read().await.contains_key(token) {
-        let user_id = sqlx::query!(
-            r#"SELECT id as "id: Uuid" FROM sessions WHERE token = ?"#,
+    let hash = hash_password(password)?;
+    let id = Uuid::new_v4();
+
+    sqlx::query!(
+        "INSERT INTO users(id, username, hash, role) VALUES (?, ?, ?, ?)",
+        id,
+        username,
+        hash,
+        role
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(id)
+}
+
+pub(crate) async fn db_set_password(
+    pool: &SqlitePool,
+    id: &Uuid,
+    password: &str,
+) -> Result<(), Error> {
+    db_update_user(pool, id, Some(password), None, None).await?;
+
+    Ok(())
+}
+
+pub(crate) async fn db_set_role(pool: &SqlitePool, id: &Uuid, role: Role) -> Result<(), Error> {
+    db_update_user(pool, id, None, Some(role), None).await?;
+
+    Ok(())
+}
+
+/// Disabling a user also signs them out everywhere, returns the number of
+/// revoked sessions.
+pub(crate) async fn db_set_disabled(
+    pool: &SqlitePool,
+    id: &Uuid,
+    disabled: bool,
+) -> Result<u64, Error> {
+    db_update_user(pool, id, None, None, Some(disabled)).await
+}
+
+/// Changes whichever of a user's password, role and disabled state are given,
+/// all of them or none. Returns the number of sessions revoked by disabling.
+pub(crate) async fn db_update_user(
+    pool: &SqlitePool,
+    id: &Uuid,
+    password: Option<&str>,
+    role: Option<Role>,
+    disabled: Option<bool>,
+) -> Result<u64, Error> {
+    // hashed before the transaction, it takes a while
+    let hash = password.map(hash_password).transpose()?;
+
+    let mut trans = pool.begin().await?;
+
+    if let Some(hash) = hash {
+        sqlx::query!("UPDATE users SET hash = ? WHERE id = ?", hash, id)
+            .execute(&mut trans)
+            .await?;
+    }
+
+    if let Some(role) = role {
+        sqlx::query!("UPDATE users SET role = ? WHERE id = ?", role, id)
+            .execute(&mut trans)
+            .await?;
+    }
+
+    let mut revoked = 0;
+
+    if let Some(disabled) = disabled {
+        sqlx::query!("UPDATE users SET disabled = ? WHERE id = ?", disabled, id)
+            .execute(&mut trans)
+            .await?;
+
+        if disabled {
+            revoked = sqlx::query!("DELETE FROM sessions WHERE id = ?", id)
+                .execute(&mut trans)
+                .await?
+                .rows_affected();
+        }
+    }
+
+    trans.commit().await?;
+
+    Ok(revoked)
+}
+
+/// Removes a user and their sessions, API tokens and shares, returns the number of revoked
+/// sessions. Their videos are kept.
+pub(crate) async fn db_remove_user(pool: &SqlitePool, id: &Uuid) -> Result<u64, Error> {
+    let mut trans = pool.begin().await?;
+
+    let revoked = sqlx::query!("DELETE FROM sessions WHERE id = ?", id)
+        .execute(&mut trans)
+        .await?
+        .rows_affected();
+
+    sqlx::query!("DELETE FROM video_shares WHERE user_id = ?", id)
+        .execute(&mut trans)
+        .await?;
+
+    sqlx::query!("DELETE FROM api_tokens WHERE user_id = ?", id)
+        .execute(&mut trans)
+        .await?;
+
+    sqlx::query!(
+        "DELETE FROM playlist_items WHERE playlist_id IN (SELECT id FROM playlists WHERE owner_id = ?)",
+        id
+    )
+    .execute(&mut trans)
+    .await?;
+
+    sqlx::query!("DELETE FROM playlists WHERE owner_id = ?", id)
+        .execute(&mut trans)
+        .await?;
+
+    sqlx::query!("DELETE FROM collections WHERE owner_id = ?", id)
+        .execute(&mut trans)
+        .await?;
+
+    sqlx::query!("DELETE FROM users WHERE id = ?", id)
+        .execute(&mut trans)
+        .await?;
+
+    trans.commit().await?;
+
+    Ok(revoked)
+}
+
+/// Creates an API token for `user_id`, returning its key and the token itself,
+/// which isn't stored and can't be shown again.
+pub(crate) async fn db_create_token(
+    pool: &SqlitePool,
+    user_id: &Uuid,
+    name: &str,
+    scopes: &[Scope],
+    expires_days: Option<u32>,
+) -> Result<(String, String), Error> {
+    let key = nanoid::nanoid!(16);
+    let token = format!("hawk_{}", nanoid::nanoid!(48));
+    let hash = hash_token(&token);
+    let scopes = Scope::join(scopes);
+    let expires = expires_days.map(|days| format!("+{} days", days));
+
+    sqlx::query!(
+        "INSERT INTO api_tokens(key, user_id, name, hash, scopes, expires) VALUES (?, ?, ?, ?, ?, DATETIME('now', ?))",
+        key,
+        user_id,
+        name,
+        hash,
+        scopes,
+        expires
+    )
+    .execute(pool)
+    .await?;
+
+    Ok((key, token))
+}
+
+/// The API tokens of `user_id`, newest first, expired ones included.
+pub(crate) async fn db_get_tokens(
+    pool: &SqlitePool,
+    user_id: &Uuid,
+) -> Result<Vec<ApiToken>, Error> {
+    let tokens = sqlx::query_as!(
+        ApiToken,
+        r#"SELECT key, name, scopes, created as "created: String", expires as "expires: String", last_used as "last_used: String", COALESCE(expires <= DATETIME('now'), FALSE) as "expired!: bool" FROM api_tokens WHERE user_id = ? ORDER BY created DESC"#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tokens)
+}
+
+/// Revokes the API token of `user_id` with `key`, returns whether there was one.
+pub(crate) async fn db_revoke_token(
+    pool: &SqlitePool,
+    cache: &Cache,
+    user_id: &Uuid,
+    key: &str,
+) -> Result<bool, Error> {
+    let hash = sqlx::query_scalar!(
+        "SELECT hash FROM api_tokens WHERE key = ? AND user_id = ?",
+        key,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let hash = match hash {
+        Some(hash) => hash,
+        None => return Ok(false),
+    };
+
+    sqlx::query!("DELETE FROM api_tokens WHERE hash = ?", hash)
+        .execute(pool)
+        .await?;
+
+    cache.invalidate_token(&hash);
+
+    Ok(true)
+}
+
+/// Resolves an API token to its user and scopes, skipping expired tokens and
+/// disabled users and recording when the token was last used.
+///
+/// Like sessions, tokens are only checked against the database once their
+/// cache entry expired.
+pub(crate) async fn db_get_token_grant(
+    pool: &SqlitePool,
+    cache: &Cache,
+    token: &str,
+) -> Result<Option<TokenGrant>, Error> {
+    let hash = hash_token(token);
+
+    if let Some(grant) = cache.token(&hash) {
+        return Ok(grant);
+    }
+
+    let row = sqlx::query!(
+        r#"SELECT users.id as "id: Uuid", users.username, users.role as "role: Role", api_tokens.scopes FROM api_tokens JOIN users ON users.id = api_tokens.user_id WHERE api_tokens.hash = ? AND users.disabled = FALSE AND (api_tokens.expires IS NULL OR api_tokens.expires > DATETIME('now'))"#,
+        hash
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let grant = row.map(|row| TokenGrant {
+        user: User {
+            id: row.id,
+            username: row.username,
+            role: row.role,
+        },
+        scopes: Scope::parse_list(&row.scopes),
+    });
+
+    if grant.is_some() {
+        sqlx::query!(
+            "UPDATE api_tokens SET last_used = DATETIME('now') WHERE hash = ?",
+            hash
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    cache.set_token(hash, grant.clone());
+
+    Ok(grant)
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn hash_password(password: &str) -> Result<String, Error> {
+    if password.is_empty() {
+        return Err(Error::Password("password must not be empty"));
+    }
+
+    Ok(bcrypt::hash(password, bcrypt::DEFAULT_COST)?)
+}
+
+/// Resolves a session token to its user, enforcing the session's absolute and
+/// idle timeouts and recording the client it was last used from.
+///
+/// Sessions are only checked against the database once their cache entry
+/// expired, which is also when their last use is recorded.
+pub(crate) async fn db_get_session_user(
+    pool: &SqlitePool,
+    cache: &Cache,
+    token: &str,
+    client: &Client,
+) -> Result<Option<User>, Error> {
+    if let Some(user) = cache.session(token) {
+        return Ok(user);
+    }
+
+    let (lifetime, idle) = (auth::session_lifetime(), auth::session_idle());
+
+    let user = sqlx::query_as!(
+        User,
+        r#"SELECT users.id as "id: Uuid", users.username, users.role as "role: Role" FROM sessions JOIN users ON users.id = sessions.id WHERE sessions.token = ? AND users.disabled = FALSE AND sessions.created > DATETIME('now', ?) AND sessions.last_seen > DATETIME('now', ?)"#,
+        token,
+        lifetime,
+        idle
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    if user.is_some() {
+        sqlx::query!(
+            "UPDATE sessions SET last_seen = DATETIME('now'), ip = ?, user_agent = ? WHERE token = ?",
+            client.ip,
+            client.user_agent,
             token
         )
-        .fetch_optional(&pool)
+        .execute(pool)
+        .await?;
+    }
+
+    cache.set_session(token.to_string(), user.clone());
+
+    Ok(user)
+}
+
+pub(crate) async fn db_create_playlist(
+    pool: &SqlitePool,
+    owner_id: &Uuid,
+    name: &str,
+    description: Option<&str>,
+    visibility: Visibility,
+) -> Result<Uuid, Error> {
+    let id = Uuid::new_v4();
+
+    sqlx::query!(
+        "INSERT INTO playlists(id, owner_id, name, description, visibility) VALUES (?, ?, ?, ?, ?)",
+        id,
+        owner_id,
+        name,
+        description,
+        visibility
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(id)
+}
+
+/// Every playlist, by name.
+pub(crate) async fn db_get_playlists(pool: &SqlitePool) -> Result<Vec<Playlist>, Error> {
+    let playlists = sqlx::query_as!(
+        Playlist,
+        r#"SELECT id as "id: Uuid", owner_id as "owner_id: Uuid", name, description, visibility as "visibility: Visibility", created as "created!: String", (SELECT COUNT(*) FROM playlist_items WHERE playlist_id = playlists.id) as "videos!: i64" FROM playlists ORDER BY name"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(playlists)
+}
+
+pub(crate) async fn db_get_playlist(
+    pool: &SqlitePool,
+    id: &Uuid,
+) -> Result<Option<Playlist>, Error> {
+    let playlist = sqlx::query_as!(
+        Playlist,
+        r#"SELECT id as "id: Uuid", owner_id as "owner_id: Uuid", name, description, visibility as "visibility: Visibility", created as "created!: String", (SELECT COUNT(*) FROM playlist_items WHERE playlist_id = playlists.id) as "videos!: i64" FROM playlists WHERE id = ?"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(playlist)
+}
+
+pub(crate) async fn db_update_playlist(
+    pool: &SqlitePool,
+    id: &Uuid,
+    name: &str,
+    description: Option<&str>,
+    visibility: Visibility,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "UPDATE playlists SET name = ?, description = ?, visibility = ? WHERE id = ?",
+        name,
+        description,
+        visibility,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Removes a playlist, the videos in it stay. Returns whether there was such a
+/// playlist.
+pub(crate) async fn db_remove_playlist(pool: &SqlitePool, id: &Uuid) -> Result<bool, Error> {
+    let mut trans = pool.begin().await?;
+
+    sqlx::query!("DELETE FROM playlist_items WHERE playlist_id = ?", id)
+        .execute(&mut trans)
+        .await?;
+
+    let removed = sqlx::query!("DELETE FROM playlists WHERE id = ?", id)
+        .execute(&mut trans)
+        .await?
+        .rows_affected();
+
+    trans.commit().await?;
+
+    Ok(removed != 0)
+}
+
+/// IDs of the videos in a playlist, in order.
+pub(crate) async fn db_get_playlist_items(
+    pool: &SqlitePool,
+    id: &Uuid,
+) -> Result<Vec<Uuid>, Error> {
+    let ids = sqlx::query_scalar!(
+        r#"SELECT video_id as "video_id: Uuid" FROM playlist_items WHERE playlist_id = ? ORDER BY position"#,
+        id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(ids)
+}
+
+/// The videos of a playlist `user` can watch, in order.
+pub(crate) async fn db_get_playlist_videos(
+    pool: &SqlitePool,
+    cache: &Cache,
+    user: Option<&User>,
+    id: &Uuid,
+) -> Result<Vec<Video>, Error> {
+    let mut videos = Vec::new();
+
+    for video_id in db_get_playlist_items(pool, id).await? {
+        match db_get_video(pool, cache, &video_id).await? {
+            Some(video) if auth::can_view(pool, user, &video).await? => videos.push(video),
+            _ => {}
+        }
+    }
+
+    Ok(videos)
+}
+
+/// Adds a video to the end of a playlist, unless it's in there already.
+pub(crate) async fn db_add_to_playlist(
+    pool: &SqlitePool,
+    id: &Uuid,
+    video_id: &Uuid,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "INSERT OR IGNORE INTO playlist_items(playlist_id, video_id, position) SELECT ?, ?, COALESCE(MAX(position) + 1, 0) FROM playlist_items WHERE playlist_id = ?",
+        id,
+        video_id,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Returns whether the video was in the playlist.
+pub(crate) async fn db_remove_from_playlist(
+    pool: &SqlitePool,
+    id: &Uuid,
+    video_id: &Uuid,
+) -> Result<bool, Error> {
+    let removed = sqlx::query!(
+        "DELETE FROM playlist_items WHERE playlist_id = ? AND video_id = ?",
+        id,
+        video_id
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(removed != 0)
+}
+
+/// Puts the videos of a playlist in the order of `order`.
+///
+/// Videos that aren't in the playlist are skipped, the ones `order` leaves out
+/// keep their order after the others. Those are usually the ones the user
+/// can't watch and doesn't know about.
+pub(crate) async fn db_reorder_playlist(
+    pool: &SqlitePool,
+    id: &Uuid,
+    order: &[Uuid],
+) -> Result<(), Error> {
+    let current = db_get_playlist_items(pool, id).await?;
+
+    let mut ordered = Vec::with_capacity(current.len());
+    for video_id in order.iter().chain(&current) {
+        if current.contains(video_id) && !ordered.contains(video_id) {
+            ordered.push(*video_id);
+        }
+    }
+
+    let mut trans = pool.begin().await?;
+
+    for (position, video_id) in ordered.iter().enumerate() {
+        let position = position as i64;
+
+        sqlx::query!(
+            "UPDATE playlist_items SET position = ? WHERE playlist_id = ? AND video_id = ?",
+            position,
+            id,
+            video_id
+        )
+        .execute(&mut trans)
         .await?;
+    }
+
+    trans.commit().await?;
 
-        let is_valid = user_id.is_some();
+    Ok(())
+}
+
+pub(crate) async fn db_create_collection(
+    pool: &SqlitePool,
+    owner_id: &Uuid,
+    name: &str,
+    query: &str,
+    pinned: bool,
+) -> Result<Uuid, Error> {
+    let id = Uuid::new_v4();
+
+    sqlx::query!(
+        "INSERT INTO collections(id, owner_id, name, query, pinned) VALUES (?, ?, ?, ?, ?)",
+        id,
+        owner_id,
+        name,
+        query,
+        pinned
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(id)
+}
+
+/// The collections of a user, by name.
+pub(crate) async fn db_get_collections(
+    pool: &SqlitePool,
+    owner_id: &Uuid,
+) -> Result<Vec<Collection>, Error> {
+    let collections = sqlx::query_as!(
+        Collection,
+        r#"SELECT id as "id: Uuid", owner_id as "owner_id: Uuid", name, query, pinned as "pinned: bool", created as "created!: String" FROM collections WHERE owner_id = ? ORDER BY name"#,
+        owner_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(collections)
+}
+
+pub(crate) async fn db_get_collection(
+    pool: &SqlitePool,
+    id: &Uuid,
+) -> Result<Option<Collection>, Error> {
+    let collection = sqlx::query_as!(
+        Collection,
+        r#"SELECT id as "id: Uuid", owner_id as "owner_id: Uuid", name, query, pinned as "pinned: bool", created as "created!: String" FROM collections WHERE id = ?"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(collection)
+}
+
+pub(crate) async fn db_update_collection(
+    pool: &SqlitePool,
+    id: &Uuid,
+    name: &str,
+    query: &str,
+    pinned: bool,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "UPDATE collections SET name = ?, query = ?, pinned = ? WHERE id = ?",
+        name,
+        query,
+        pinned,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Returns whether there was such a collection.
+pub(crate) async fn db_remove_collection(pool: &SqlitePool, id: &Uuid) -> Result<bool, Error> {
+    let removed = sqlx::query!("DELETE FROM collections WHERE id = ?", id)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(removed != 0)
+}
+
+/// A fresh database for tests, on a single connection as every in-memory
+/// connection would get its own.
+#[cfg(test)]
+pub(crate) async fn memory_pool() -> SqlitePool {
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+
+    crate::MIGRATIONS.run(&pool).await.unwrap();
+
+    pool
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn video(pool: &SqlitePool, title: &str, tags: &[String]) -> Uuid {
+        let id = Uuid::new_v4();
+
+        sqlx::query(
+            "INSERT INTO videos(id, ext, visibility, title) VALUES (?, 'mp4', 'public', ?)",
+        )
+        .bind(id)
+        .bind(title)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        db_add_tags(pool, &[id], tags).await.unwrap();
+
+        id
+    }
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
 
-        DB_IS_SESSION_VALID_CACHE
-            .write()
+    #[tokio::test]
+    async fn failed_video_updates_change_nothing() {
+        let pool = memory_pool().await;
+        let cache = Cache::new();
+        let id = video(&pool, "before", &tags(&["cats"])).await;
+
+        let details = VideoDetails::new("after", "", "").unwrap();
+        let result = db_update_video(
+            &pool,
+            &cache,
+            &id,
+            Some((Visibility::Shared, &["nobody".to_string()])),
+            Some(&details),
+            Some(&tags(&["dogs"])),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::UnknownUser(_))));
+
+        let video = db_get_video(&pool, &cache, &id).await.unwrap().unwrap();
+        assert_eq!(video.title.as_deref(), Some("before"));
+        assert_eq!(video.visibility, Visibility::Public);
+        assert_eq!(db_get_video_tags(&pool, &id).await.unwrap(), ["cats"]);
+
+        db_update_video(
+            &pool,
+            &cache,
+            &id,
+            Some((Visibility::Private, &[])),
+            Some(&details),
+            Some(&tags(&["dogs"])),
+        )
+        .await
+        .unwrap();
+
+        let video = db_get_video(&pool, &cache, &id).await.unwrap().unwrap();
+        assert_eq!(video.title.as_deref(), Some("after"));
+        assert_eq!(video.visibility, Visibility::Private);
+        assert_eq!(db_get_video_tags(&pool, &id).await.unwrap(), ["dogs"]);
+        // the tag that's on no video anymore is gone
+        assert_eq!(db_get_tags(&pool).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn tags_are_only_fetched_for_the_given_videos() {
+        let pool = memory_pool().await;
+        let first = video(&pool, "first", &tags(&["b", "a"])).await;
+        let second = video(&pool, "second", &tags(&["c"])).await;
+        let untagged = video(&pool, "untagged", &[]).await;
+
+        let found = db_get_tags_of_videos(&pool, &[first, untagged])
             .await
-            .insert(token.to_string(), is_valid);
+            .unwrap();
 
-        return Ok(is_valid);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[&first], ["a", "b"]);
+        assert!(!found.contains_key(&second));
     }
 
-    Ok(DB_IS_SESSION_VALID_CACHE
-        .read()
+    #[tokio::test]
+    async fn user_updates_apply_together() {
+        let pool = memory_pool().await;
+        let id = db_create_user(&pool, "user", "password", Role::Viewer)
+            .await
+            .unwrap();
+
+        sqlx::query("INSERT INTO sessions(id, token, key) VALUES (?, 'token', 'key')")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let revoked = db_update_user(
+            &pool,
+            &id,
+            Some("changed"),
+            Some(Role::Uploader),
+            Some(true),
+        )
         .await
-        .get(token)
-        .copied()
-        .unwrap_or(false))
+        .unwrap();
+        assert_eq!(revoked, 1);
+
+        let row = sqlx::query("SELECT hash, role, disabled FROM users WHERE id = ?")
+            .bind(id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(bcrypt::verify("changed", row.get::<&str, _>("hash")).unwrap());
+        assert_eq!(row.get::<Role, _>("role"), Role::Uploader);
+        assert!(row.get::<bool, _>("disabled"));
+    }
 }
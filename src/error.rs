@@ -1,40 +1,72 @@
-use axum::response::IntoResponse;
-use http::StatusCode;
-
-#[derive(Debug, thiserror::Error)]
-pub enum Error {
-    #[error("address parse: {0}")]
-    AddrParse(#[from] std::net::AddrParseError),
-    #[error("askama: {0}")]
-    Askama(#[from] askama::Error),
-    #[error("bcrypt: {0}")]
-    Bcrypt(#[from] bcrypt::BcryptError),
-    #[error("ffmpeg: {0}")]
-    Ffmpeg(String),
-    #[error("image: {0}")]
-    Image(#[from] image::error::ImageError),
-    #[error("infer: {0}")]
-    Infer(&'static str),
-    #[error("io: {0}")]
-    Io(#[from] tokio::io::Error),
-    #[error("invalid file type uploaded")]
-    InvalidFileType,
-    #[error("multipart: {0}")]
-    Multipart(#[from] axum::extract::multipart::MultipartError),
-    #[error("sqlx: {0}")]
-    Sql(#[from] sqlx::Error),
-    #[error("sqlx migration: {0}")]
-    SqlMigrate(#[from] sqlx::migrate::MigrateError),
-    #[error("tokio join: {0}")]
-    TokioJoin(#[from] tokio::task::JoinError),
-    #[error("webp encoding: {0}")]
-    Webp(String),
-}
-
-impl IntoResponse for Error {
-    fn into_response(self) -> axum::response::Response {
-        tracing::error!("{}", self);
-
-        StatusCode::INTERNAL_SERVER_ERROR.into_response()
-    }
-}
+use axum::response::IntoResponse;
+use http::StatusCode;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("address parse: {0}")]
+    AddrParse(#[from] std::net::AddrParseError),
+    #[error("askama: {0}")]
+    Askama(#[from] askama::Error),
+    #[error("video details: {0}")]
+    Details(&'static str),
+    #[error("request body: {0}")]
+    Body(#[from] axum::Error),
+    #[error("bcrypt: {0}")]
+    Bcrypt(#[from] bcrypt::BcryptError),
+    #[error("config: {0}")]
+    Config(String),
+    #[error("ffmpeg: {0}")]
+    Ffmpeg(String),
+    #[error("hyper: {0}")]
+    Hyper(#[from] hyper::Error),
+    #[error("image: {0}")]
+    Image(#[from] image::error::ImageError),
+    #[error("infer: {0}")]
+    Infer(&'static str),
+    #[error("io: {0}")]
+    Io(#[from] tokio::io::Error),
+    #[error("invalid cursor")]
+    InvalidCursor,
+    #[error("invalid file type uploaded")]
+    InvalidFileType,
+    #[error("unknown job kind: {0}")]
+    UnknownJob(String),
+    #[error("unknown API token: {0}")]
+    UnknownToken(String),
+    #[error("unknown tag: {0}")]
+    UnknownTag(String),
+    #[error("unknown user: {0}")]
+    UnknownUser(String),
+    #[error("upload is larger than its declared length")]
+    UploadTooLarge,
+    #[error("collection: {0}")]
+    Collection(&'static str),
+    #[error("multipart: {0}")]
+    Multipart(#[from] axum::extract::multipart::MultipartError),
+    #[error("playlist: {0}")]
+    Playlist(&'static str),
+    #[error("password: {0}")]
+    Password(&'static str),
+    #[error("search: {0}")]
+    Search(&'static str),
+    #[error("serde json: {0}")]
+    SerdeJson(#[from] serde_json::Error),
+    #[error("sqlx: {0}")]
+    Sql(#[from] sqlx::Error),
+    #[error("sqlx migration: {0}")]
+    SqlMigrate(#[from] sqlx::migrate::MigrateError),
+    #[error("tokio join: {0}")]
+    TokioJoin(#[from] tokio::task::JoinError),
+    #[error("user already exists: {0}")]
+    UserExists(String),
+    #[error("webp encoding: {0}")]
+    Webp(String),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        tracing::error!("{}", self);
+
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
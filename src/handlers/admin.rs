@@ -1,58 +1,172 @@
+use std::{collections::HashMap, sync::Arc};
+
 use askama::Template;
-use axum::{response::Html, Extension, Form};
+use axum::{
+    response::{Html, IntoResponse as _, Redirect, Response},
+    Extension, Form,
+};
 use http::StatusCode;
 use sqlx::SqlitePool;
 use uuid::Uuid;
 
-use crate::{auth::Auth, database, error::Error};
+use crate::{
+    auth::{Admin, Auth},
+    cache::Cache,
+    config::Config,
+    database,
+    error::Error,
+    models::{Tag, Video},
+    signing::Signer,
+};
 
 pub(crate) async fn get(
-    _auth: Auth,
+    _auth: Auth<Admin>,
     Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
 ) -> Result<Html<String>, Error> {
     #[derive(Template)]
     #[template(path = "admin.html")]
     struct Page {
-        videos: Vec<Uuid>,
+        videos: Vec<Video>,
+        video_tags: HashMap<Uuid, Vec<String>>,
+        tags: Vec<Tag>,
+        signer: Signer,
+        base: String,
+    }
+
+    impl Page {
+        fn tags_of(&self, video: &Video) -> String {
+            self.video_tags
+                .get(&video.id)
+                .map(|tags| tags.join(", "))
+                .unwrap_or_default()
+        }
     }
 
-    let videos = database::db_get_all_videos(pool).await?;
+    let videos = database::db_get_all_videos(&pool, &cache).await?;
+    let video_tags = database::db_get_all_video_tags(&pool).await?;
+    let tags = database::db_get_tags(&pool).await?;
 
-    Ok(Html(Page { videos }.render()?))
+    Ok(Html(
+        Page {
+            videos,
+            video_tags,
+            tags,
+            signer,
+            base: config.server.base_path.clone(),
+        }
+        .render()?,
+    ))
 }
 
 pub(crate) async fn clear_sessions(
-    _auth: Auth,
+    _auth: Auth<Admin>,
     Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
 ) -> Result<StatusCode, Error> {
     sqlx::query!("DELETE FROM sessions;").execute(&pool).await?;
 
+    cache.invalidate_sessions();
+
     Ok(StatusCode::OK)
 }
 
 #[derive(serde::Deserialize)]
 pub(crate) struct RemoveVideo {
-    id: String,
+    id: Uuid,
 }
 
 pub(crate) async fn remove_video(
-    _auth: Auth,
+    _auth: Auth<Admin>,
     Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
     Form(form): Form<RemoveVideo>,
 ) -> Result<StatusCode, Error> {
-    let mut trans = pool.begin().await?;
+    if !database::db_remove_video(&pool, &cache, &config, &form.id).await? {
+        return Ok(StatusCode::NOT_FOUND);
+    }
 
-    sqlx::query!("DELETE FROM videos WHERE id = ?;", form.id)
-        .execute(&mut trans)
-        .await?;
+    Ok(StatusCode::OK)
+}
 
-    let path = std::env::current_dir()?
-        .join("assets")
-        .join("video")
-        .join(format!("{}.webp", form.id));
-    tokio::fs::remove_file(&path).await?;
+/// Adds or removes tags on every selected video at once.
+///
+/// The form repeats `ids` for each selected video, so it's read as pairs.
+pub(crate) async fn tag_videos(
+    _auth: Auth<Admin>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Result<Response, Error> {
+    let mut ids = Vec::new();
+    let mut tags = Vec::new();
+    let mut remove = false;
 
-    trans.commit().await?;
+    for (key, value) in form {
+        match key.as_str() {
+            "ids" => match value.parse::<Uuid>() {
+                Ok(id) => ids.push(id),
+                Err(_) => return Ok(StatusCode::BAD_REQUEST.into_response()),
+            },
+            "tags" => tags = Tag::parse_list(&value),
+            "action" => remove = value == "remove",
+            _ => {}
+        }
+    }
 
-    Ok(StatusCode::OK)
+    if remove {
+        database::db_remove_tags(&pool, &ids, &tags).await?;
+    } else {
+        database::db_add_tags(&pool, &ids, &tags).await?;
+    }
+
+    Ok(Redirect::to(&config.url("/admin")).into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct RenameTag {
+    name: String,
+    new_name: String,
+}
+
+/// Renames a tag, merging it into `new_name` when that tag already exists.
+pub(crate) async fn rename_tag(
+    _auth: Auth<Admin>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Form(form): Form<RenameTag>,
+) -> Result<Response, Error> {
+    let new_name = match Tag::normalize(&form.new_name) {
+        Some(new_name) => new_name,
+        None => return Ok(StatusCode::BAD_REQUEST.into_response()),
+    };
+
+    match database::db_rename_tag(&pool, &form.name, &new_name).await {
+        Ok(()) => {}
+        Err(Error::UnknownTag(_)) => return Ok(StatusCode::NOT_FOUND.into_response()),
+        Err(err) => return Err(err),
+    }
+
+    Ok(Redirect::to(&config.url("/admin")).into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct RemoveTag {
+    name: String,
+}
+
+pub(crate) async fn remove_tag(
+    _auth: Auth<Admin>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Form(form): Form<RemoveTag>,
+) -> Result<Response, Error> {
+    if !database::db_remove_tag(&pool, &form.name).await? {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    Ok(Redirect::to(&config.url("/admin")).into_response())
 }
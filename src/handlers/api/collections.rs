@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use axum::{extract::Path, Extension, Json};
+use http::StatusCode;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use super::{videos::VideoInfo, Api, ApiError};
+use crate::{
+    auth::{Auth, Writer},
+    config::Config,
+    database,
+    models::Collection,
+    pagination::VideoFilter,
+    search::{Search, MAX_HITS},
+    signing::Signer,
+};
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub(crate) struct CollectionInfo {
+    #[serde(flatten)]
+    collection: Collection,
+    /// The videos matching the query right now, most relevant first and no
+    /// more than 100 of them.
+    videos: Vec<VideoInfo>,
+    /// Number of videos matching the query, there may be more than listed.
+    total: usize,
+}
+
+/// The signed in user's collections.
+#[utoipa::path(
+    get,
+    path = "/collections",
+    tag = "collections",
+    responses(
+        (status = 200, body = [Collection]),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool), err)]
+pub(super) async fn list(
+    Api(auth): Api<Auth>,
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<Json<Vec<Collection>>, ApiError> {
+    Ok(Json(
+        database::db_get_collections(&pool, &auth.user.id).await?,
+    ))
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CreateCollection {
+    name: String,
+    /// In the syntax of the search page.
+    query: String,
+    #[serde(default)]
+    pinned: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/collections",
+    tag = "collections",
+    request_body = CreateCollection,
+    responses(
+        (status = 201, body = Collection),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, create), err)]
+pub(super) async fn create(
+    Api(auth): Api<Auth<Writer>>,
+    Extension(pool): Extension<SqlitePool>,
+    Api(Json(create)): Api<Json<CreateCollection>>,
+) -> Result<(StatusCode, Json<Collection>), ApiError> {
+    let (name, query) = Collection::validate(&create.name, &create.query)?;
+
+    let id =
+        database::db_create_collection(&pool, &auth.user.id, &name, &query, create.pinned).await?;
+
+    let collection = find(&pool, &auth, &id).await?;
+
+    Ok((StatusCode::CREATED, Json(collection)))
+}
+
+/// Collections of other users don't exist.
+async fn find<R>(pool: &SqlitePool, auth: &Auth<R>, id: &Uuid) -> Result<Collection, ApiError> {
+    database::db_get_collection(pool, id)
+        .await?
+        .filter(|collection| collection.owner_id == auth.user.id)
+        .ok_or_else(super::not_found)
+}
+
+/// A collection along with the videos its query matches.
+#[utoipa::path(
+    get,
+    path = "/collections/{id}",
+    tag = "collections",
+    params(("id" = Uuid, Path, description = "ID of the collection")),
+    responses(
+        (status = 200, body = CollectionInfo),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, config, signer), err)]
+pub(super) async fn get(
+    Api(auth): Api<Auth>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Api(Path(id)): Api<Path<Uuid>>,
+) -> Result<Json<CollectionInfo>, ApiError> {
+    let collection = find(&pool, &auth, &id).await?;
+
+    let search = Search::parse(&collection.query)?;
+
+    let found = database::db_search_videos(
+        &pool,
+        Some(&auth.user),
+        &search,
+        &VideoFilter::default(),
+        0,
+        MAX_HITS,
+    )
+    .await?;
+
+    let ids = found
+        .hits
+        .iter()
+        .map(|hit| hit.video.id)
+        .collect::<Vec<_>>();
+    let mut video_tags = database::db_get_tags_of_videos(&pool, &ids).await?;
+
+    let videos = found
+        .hits
+        .into_iter()
+        .map(|hit| {
+            let tags = video_tags.remove(&hit.video.id).unwrap_or_default();
+
+            VideoInfo::new(hit.video, tags, &config, &signer)
+        })
+        .collect();
+
+    Ok(Json(CollectionInfo {
+        collection,
+        videos,
+        total: found.total,
+    }))
+}
+
+/// Fields that are left out stay unchanged.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct UpdateCollection {
+    name: Option<String>,
+    query: Option<String>,
+    pinned: Option<bool>,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/collections/{id}",
+    tag = "collections",
+    params(("id" = Uuid, Path, description = "ID of the collection")),
+    request_body = UpdateCollection,
+    responses(
+        (status = 200, body = Collection),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, update), err)]
+pub(super) async fn update(
+    Api(auth): Api<Auth<Writer>>,
+    Extension(pool): Extension<SqlitePool>,
+    Api(Path(id)): Api<Path<Uuid>>,
+    Api(Json(update)): Api<Json<UpdateCollection>>,
+) -> Result<Json<Collection>, ApiError> {
+    let collection = find(&pool, &auth, &id).await?;
+
+    let (name, query) = Collection::validate(
+        update.name.as_deref().unwrap_or(&collection.name),
+        update.query.as_deref().unwrap_or(&collection.query),
+    )?;
+    let pinned = update.pinned.unwrap_or(collection.pinned);
+
+    database::db_update_collection(&pool, &id, &name, &query, pinned).await?;
+
+    Ok(Json(find(&pool, &auth, &id).await?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/collections/{id}",
+    tag = "collections",
+    params(("id" = Uuid, Path, description = "ID of the collection")),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool), err)]
+pub(super) async fn remove(
+    Api(auth): Api<Auth<Writer>>,
+    Extension(pool): Extension<SqlitePool>,
+    Api(Path(id)): Api<Path<Uuid>>,
+) -> Result<StatusCode, ApiError> {
+    find(&pool, &auth, &id).await?;
+
+    if !database::db_remove_collection(&pool, &id).await? {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
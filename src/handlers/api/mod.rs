@@ -0,0 +1,310 @@
+//! The JSON API under `/api/v1`, described by the OpenAPI document served at
+//! `/api/v1/openapi.json`.
+//!
+//! Errors are always answered with an [`ErrorBody`], including rejected
+//! requests and, through [`super::fallback`], unknown paths.
+
+mod collections;
+mod playlists;
+mod sessions;
+mod users;
+mod videos;
+
+use std::{fmt, sync::Arc};
+
+use axum::{
+    extract::{
+        multipart::MultipartRejection,
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        FromRequest, RequestParts,
+    },
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Extension, Json, Router,
+};
+use http::StatusCode;
+use utoipa::{
+    openapi::{
+        security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme},
+        Server,
+    },
+    Modify, OpenApi,
+};
+
+use crate::{
+    auth::Auth,
+    config::Config,
+    error::Error,
+    models::{Account, Collection, Playlist, Role, Session, Sort, User, Visibility},
+    SESSION,
+};
+
+pub(super) fn routes() -> Router {
+    Router::new()
+        .route("/openapi.json", get(openapi))
+        .route("/me", get(me))
+        .route("/videos", get(videos::list).post(videos::upload))
+        .route(
+            "/videos/:id",
+            get(videos::get)
+                .patch(videos::update)
+                .delete(videos::remove),
+        )
+        .route(
+            "/collections",
+            get(collections::list).post(collections::create),
+        )
+        .route(
+            "/collections/:id",
+            get(collections::get)
+                .patch(collections::update)
+                .delete(collections::remove),
+        )
+        .route("/playlists", get(playlists::list).post(playlists::create))
+        .route(
+            "/playlists/:id",
+            get(playlists::get)
+                .patch(playlists::update)
+                .delete(playlists::remove),
+        )
+        .route(
+            "/playlists/:id/items",
+            post(playlists::add_item).put(playlists::reorder_items),
+        )
+        .route(
+            "/playlists/:id/items/:video_id",
+            delete(playlists::remove_item),
+        )
+        .route("/sessions", get(sessions::list))
+        .route("/sessions/:key", delete(sessions::revoke))
+        .route("/users", get(users::list).post(users::create))
+        .route(
+            "/users/:username",
+            get(users::get).patch(users::update).delete(users::remove),
+        )
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        me,
+        videos::list,
+        videos::get,
+        videos::upload,
+        videos::update,
+        videos::remove,
+        playlists::list,
+        playlists::get,
+        playlists::create,
+        playlists::update,
+        playlists::remove,
+        playlists::add_item,
+        playlists::reorder_items,
+        playlists::remove_item,
+        collections::list,
+        collections::get,
+        collections::create,
+        collections::update,
+        collections::remove,
+        sessions::list,
+        sessions::revoke,
+        users::list,
+        users::get,
+        users::create,
+        users::update,
+        users::remove,
+    ),
+    components(schemas(
+        ErrorBody,
+        User,
+        Role,
+        Visibility,
+        Sort,
+        Session,
+        Account,
+        Playlist,
+        Collection,
+        videos::VideoInfo,
+        videos::VideoList,
+        videos::Upload,
+        videos::UpdateVideo,
+        playlists::PlaylistInfo,
+        playlists::CreatePlaylist,
+        playlists::UpdatePlaylist,
+        playlists::AddItem,
+        playlists::ReorderItems,
+        collections::CollectionInfo,
+        collections::CreateCollection,
+        collections::UpdateCollection,
+        users::CreateUser,
+        users::UpdateUser,
+    )),
+    modifiers(&SecuritySchemes),
+    security(("session" = []), ("token" = [])),
+    tags(
+        (name = "videos"),
+        (name = "playlists", description = "Ordered lists of videos"),
+        (name = "collections", description = "The signed in user's saved searches"),
+        (name = "sessions", description = "The signed in user's sessions"),
+        (name = "users", description = "User management, admins only"),
+    )
+)]
+struct ApiDoc;
+
+/// Requests are authenticated by the session cookie or a personal API token,
+/// the latter being what scripts should use.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(SESSION))),
+        );
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+/// The server depends on the base path, so it's only filled in here.
+async fn openapi(Extension(config): Extension<Arc<Config>>) -> Json<utoipa::openapi::OpenApi> {
+    let mut doc = ApiDoc::openapi();
+    doc.servers = Some(vec![Server::new(config.url("/api/v1"))]);
+
+    Json(doc)
+}
+
+/// The signed in user.
+#[utoipa::path(
+    get,
+    path = "/me",
+    responses(
+        (status = 200, body = User),
+        (status = 401, body = ErrorBody),
+    )
+)]
+async fn me(Api(auth): Api<Auth>) -> Json<User> {
+    Json(auth.user)
+}
+
+pub(super) fn not_found() -> ApiError {
+    StatusCode::NOT_FOUND.into()
+}
+
+/// The body of every error response.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub(crate) struct ErrorBody {
+    /// The HTTP status code.
+    status: u16,
+    error: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    pub(crate) fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.status.as_u16(), self.message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            status: self.status.as_u16(),
+            error: self.message,
+        };
+
+        (self.status, Json(body)).into_response()
+    }
+}
+
+impl From<StatusCode> for ApiError {
+    fn from(status: StatusCode) -> Self {
+        let message = status.canonical_reason().unwrap_or("error").to_lowercase();
+
+        ApiError::new(status, message)
+    }
+}
+
+/// Errors caused by the request are passed on, anything else is logged and
+/// hidden behind a `500`.
+impl From<Error> for ApiError {
+    fn from(err: Error) -> Self {
+        let status = match &err {
+            Error::UnknownUser(_) | Error::UnknownTag(_) => StatusCode::NOT_FOUND,
+            Error::UserExists(_) => StatusCode::CONFLICT,
+            Error::Password(_)
+            | Error::Details(_)
+            | Error::Search(_)
+            | Error::Playlist(_)
+            | Error::Collection(_)
+            | Error::InvalidCursor
+            | Error::Multipart(_) => StatusCode::BAD_REQUEST,
+            Error::Infer(_) | Error::InvalidFileType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::UploadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => {
+                tracing::error!("{}", err);
+
+                return StatusCode::INTERNAL_SERVER_ERROR.into();
+            }
+        };
+
+        ApiError::new(status, err.to_string())
+    }
+}
+
+macro_rules! rejection {
+    ($($rejection:ty),*) => {
+        $(
+            impl From<$rejection> for ApiError {
+                fn from(rejection: $rejection) -> Self {
+                    let message = rejection.to_string();
+
+                    ApiError::new(rejection.into_response().status(), message)
+                }
+            }
+        )*
+    };
+}
+
+rejection!(
+    JsonRejection,
+    PathRejection,
+    QueryRejection,
+    MultipartRejection
+);
+
+/// Extracts `T`, answering its rejection with an [`ErrorBody`] instead of the
+/// usual plain text.
+pub(crate) struct Api<T>(pub T);
+
+#[async_trait::async_trait]
+impl<B, T> FromRequest<B> for Api<T>
+where
+    B: Send,
+    T: FromRequest<B>,
+    T::Rejection: Into<ApiError>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        T::from_request(req).await.map(Api).map_err(Into::into)
+    }
+}
@@ -0,0 +1,363 @@
+use std::sync::Arc;
+
+use axum::{extract::Path, Extension, Json};
+use http::StatusCode;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use super::{videos::VideoInfo, Api, ApiError};
+use crate::{
+    auth::{self, Auth, MaybeAuth, Writer},
+    cache::Cache,
+    config::Config,
+    database,
+    models::{Playlist, Visibility},
+    signing::Signer,
+};
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub(crate) struct PlaylistInfo {
+    #[serde(flatten)]
+    playlist: Playlist,
+    /// The videos the user can watch, in order.
+    items: Vec<VideoInfo>,
+}
+
+/// Public playlists, along with the user's own and every playlist for admins.
+#[utoipa::path(
+    get,
+    path = "/playlists",
+    tag = "playlists",
+    security((), ("session" = []), ("token" = [])),
+    responses(
+        (status = 200, body = [Playlist]),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool), err)]
+pub(super) async fn list(
+    Api(MaybeAuth(auth)): Api<MaybeAuth>,
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<Json<Vec<Playlist>>, ApiError> {
+    let user = auth.map(|auth| auth.user);
+
+    let playlists = database::db_get_playlists(&pool)
+        .await?
+        .into_iter()
+        .filter(|playlist| playlist.is_listed_for(user.as_ref()))
+        .collect();
+
+    Ok(Json(playlists))
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CreatePlaylist {
+    name: String,
+    description: Option<String>,
+    /// Private by default, playlists can't be shared with users.
+    visibility: Option<Visibility>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/playlists",
+    tag = "playlists",
+    request_body = CreatePlaylist,
+    responses(
+        (status = 201, body = Playlist),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, create), err)]
+pub(super) async fn create(
+    Api(auth): Api<Auth<Writer>>,
+    Extension(pool): Extension<SqlitePool>,
+    Api(Json(create)): Api<Json<CreatePlaylist>>,
+) -> Result<(StatusCode, Json<Playlist>), ApiError> {
+    let visibility = create.visibility.unwrap_or(Visibility::Private);
+
+    let (name, description) = Playlist::validate(
+        &create.name,
+        create.description.as_deref().unwrap_or_default(),
+        visibility,
+    )?;
+
+    let id = database::db_create_playlist(
+        &pool,
+        &auth.user.id,
+        &name,
+        description.as_deref(),
+        visibility,
+    )
+    .await?;
+
+    let playlist = database::db_get_playlist(&pool, &id)
+        .await?
+        .ok_or_else(super::not_found)?;
+
+    Ok((StatusCode::CREATED, Json(playlist)))
+}
+
+/// Looks up a playlist the same way its page does, playlists the user can't
+/// see don't exist.
+async fn find(pool: &SqlitePool, auth: Option<&Auth>, id: &Uuid) -> Result<Playlist, ApiError> {
+    let user = auth.map(|auth| &auth.user);
+
+    match database::db_get_playlist(pool, id).await? {
+        Some(playlist) if playlist.is_viewable_by(user) => Ok(playlist),
+        _ if user.is_none() => Err(StatusCode::UNAUTHORIZED.into()),
+        _ => Err(StatusCode::NOT_FOUND.into()),
+    }
+}
+
+async fn find_editable(
+    pool: &SqlitePool,
+    auth: &Auth<Writer>,
+    id: &Uuid,
+) -> Result<Playlist, ApiError> {
+    match database::db_get_playlist(pool, id).await? {
+        Some(playlist) if playlist.is_editable_by(Some(&auth.user)) => Ok(playlist),
+        _ => Err(StatusCode::NOT_FOUND.into()),
+    }
+}
+
+/// A playlist along with the videos in it the user can watch.
+///
+/// Unlisted playlists can be fetched by anyone who knows their ID.
+#[utoipa::path(
+    get,
+    path = "/playlists/{id}",
+    tag = "playlists",
+    params(("id" = Uuid, Path, description = "ID of the playlist")),
+    security((), ("session" = []), ("token" = [])),
+    responses(
+        (status = 200, body = PlaylistInfo),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, cache, config, signer), err)]
+pub(super) async fn get(
+    Api(MaybeAuth(auth)): Api<MaybeAuth>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Api(Path(id)): Api<Path<Uuid>>,
+) -> Result<Json<PlaylistInfo>, ApiError> {
+    let playlist = find(&pool, auth.as_ref(), &id).await?;
+
+    let user = auth.map(|auth| auth.user);
+    let videos = database::db_get_playlist_videos(&pool, &cache, user.as_ref(), &id).await?;
+
+    let ids = videos.iter().map(|video| video.id).collect::<Vec<_>>();
+    let mut video_tags = database::db_get_tags_of_videos(&pool, &ids).await?;
+
+    let items = videos
+        .into_iter()
+        .map(|video| {
+            let tags = video_tags.remove(&video.id).unwrap_or_default();
+
+            VideoInfo::new(video, tags, &config, &signer)
+        })
+        .collect();
+
+    Ok(Json(PlaylistInfo { playlist, items }))
+}
+
+/// Fields that are left out stay unchanged, an empty description clears it.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct UpdatePlaylist {
+    name: Option<String>,
+    description: Option<String>,
+    visibility: Option<Visibility>,
+}
+
+/// Changes a playlist, only its owner and admins can.
+#[utoipa::path(
+    patch,
+    path = "/playlists/{id}",
+    tag = "playlists",
+    params(("id" = Uuid, Path, description = "ID of the playlist")),
+    request_body = UpdatePlaylist,
+    responses(
+        (status = 200, body = Playlist),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, update), err)]
+pub(super) async fn update(
+    Api(auth): Api<Auth<Writer>>,
+    Extension(pool): Extension<SqlitePool>,
+    Api(Path(id)): Api<Path<Uuid>>,
+    Api(Json(update)): Api<Json<UpdatePlaylist>>,
+) -> Result<Json<Playlist>, ApiError> {
+    let playlist = find_editable(&pool, &auth, &id).await?;
+
+    let visibility = update.visibility.unwrap_or(playlist.visibility);
+    let (name, description) = Playlist::validate(
+        update.name.as_deref().unwrap_or(&playlist.name),
+        update
+            .description
+            .as_deref()
+            .or(playlist.description.as_deref())
+            .unwrap_or_default(),
+        visibility,
+    )?;
+
+    database::db_update_playlist(&pool, &id, &name, description.as_deref(), visibility).await?;
+
+    let playlist = find_editable(&pool, &auth, &id).await?;
+
+    Ok(Json(playlist))
+}
+
+/// Removes a playlist, the videos in it are kept.
+#[utoipa::path(
+    delete,
+    path = "/playlists/{id}",
+    tag = "playlists",
+    params(("id" = Uuid, Path, description = "ID of the playlist")),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool), err)]
+pub(super) async fn remove(
+    Api(auth): Api<Auth<Writer>>,
+    Extension(pool): Extension<SqlitePool>,
+    Api(Path(id)): Api<Path<Uuid>>,
+) -> Result<StatusCode, ApiError> {
+    find_editable(&pool, &auth, &id).await?;
+
+    if !database::db_remove_playlist(&pool, &id).await? {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct AddItem {
+    video_id: Uuid,
+}
+
+/// Adds a video the user can watch to the end of a playlist, videos that are
+/// in it already stay where they are.
+#[utoipa::path(
+    post,
+    path = "/playlists/{id}/items",
+    tag = "playlists",
+    params(("id" = Uuid, Path, description = "ID of the playlist")),
+    request_body = AddItem,
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, cache, add), err)]
+pub(super) async fn add_item(
+    Api(auth): Api<Auth<Writer>>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Api(Path(id)): Api<Path<Uuid>>,
+    Api(Json(add)): Api<Json<AddItem>>,
+) -> Result<StatusCode, ApiError> {
+    find_editable(&pool, &auth, &id).await?;
+
+    match database::db_get_video(&pool, &cache, &add.video_id).await? {
+        Some(video) if auth::can_view(&pool, Some(&auth.user), &video).await? => {}
+        _ => {
+            return Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("unknown video: {}", add.video_id),
+            ))
+        }
+    }
+
+    database::db_add_to_playlist(&pool, &id, &add.video_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ReorderItems {
+    /// IDs of the videos in their new order. Videos that are left out, like
+    /// the ones the user can't watch, keep their order after these.
+    videos: Vec<Uuid>,
+}
+
+/// Reorders the videos of a playlist.
+#[utoipa::path(
+    put,
+    path = "/playlists/{id}/items",
+    tag = "playlists",
+    params(("id" = Uuid, Path, description = "ID of the playlist")),
+    request_body = ReorderItems,
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, reorder), err)]
+pub(super) async fn reorder_items(
+    Api(auth): Api<Auth<Writer>>,
+    Extension(pool): Extension<SqlitePool>,
+    Api(Path(id)): Api<Path<Uuid>>,
+    Api(Json(reorder)): Api<Json<ReorderItems>>,
+) -> Result<StatusCode, ApiError> {
+    find_editable(&pool, &auth, &id).await?;
+
+    database::db_reorder_playlist(&pool, &id, &reorder.videos).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Removes a video from a playlist.
+#[utoipa::path(
+    delete,
+    path = "/playlists/{id}/items/{video_id}",
+    tag = "playlists",
+    params(
+        ("id" = Uuid, Path, description = "ID of the playlist"),
+        ("video_id" = Uuid, Path, description = "ID of the video"),
+    ),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool), err)]
+pub(super) async fn remove_item(
+    Api(auth): Api<Auth<Writer>>,
+    Extension(pool): Extension<SqlitePool>,
+    Api(Path((id, video_id))): Api<Path<(Uuid, Uuid)>>,
+) -> Result<StatusCode, ApiError> {
+    find_editable(&pool, &auth, &id).await?;
+
+    if !database::db_remove_from_playlist(&pool, &id, &video_id).await? {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,67 @@
+use axum::{extract::Path, Extension, Json};
+use http::StatusCode;
+use sqlx::SqlitePool;
+use tower_cookies::Cookies;
+
+use super::{Api, ApiError};
+use crate::{
+    auth::{Auth, Writer},
+    cache::Cache,
+    database,
+    models::Session,
+    SESSION,
+};
+
+/// The user's sessions that haven't expired, most recently used first.
+#[utoipa::path(
+    get,
+    path = "/sessions",
+    tag = "sessions",
+    responses(
+        (status = 200, body = [Session]),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, cookies, pool), err)]
+pub(super) async fn list(
+    Api(auth): Api<Auth>,
+    cookies: Cookies,
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<Json<Vec<Session>>, ApiError> {
+    let current = cookies
+        .get(SESSION)
+        .map(|session| session.value().to_string())
+        .unwrap_or_default();
+
+    let sessions = database::db_get_sessions(&pool, &auth.user.id, &current).await?;
+
+    Ok(Json(sessions))
+}
+
+/// Signs a session out, revoking the current one signs out the request's own
+/// session.
+#[utoipa::path(
+    delete,
+    path = "/sessions/{key}",
+    tag = "sessions",
+    params(("key" = String, Path, description = "Key of the session")),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, cache, key), err)]
+pub(super) async fn revoke(
+    Api(auth): Api<Auth<Writer>>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Api(Path(key)): Api<Path<String>>,
+) -> Result<StatusCode, ApiError> {
+    if !database::db_revoke_session(&pool, &cache, &auth.user.id, &key).await? {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,178 @@
+use axum::{extract::Path, Extension, Json};
+use http::StatusCode;
+use sqlx::SqlitePool;
+
+use super::{Api, ApiError};
+use crate::{
+    auth::{Admin, Auth},
+    cache::Cache,
+    database,
+    error::Error,
+    models::{Account, Role},
+};
+
+#[utoipa::path(
+    get,
+    path = "/users",
+    tag = "users",
+    responses(
+        (status = 200, body = [Account]),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(_auth, pool), err)]
+pub(super) async fn list(
+    Api(_auth): Api<Auth<Admin>>,
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<Json<Vec<Account>>, ApiError> {
+    Ok(Json(database::db_get_accounts(&pool).await?))
+}
+
+async fn account(pool: &SqlitePool, username: &str) -> Result<Account, ApiError> {
+    database::db_get_account(pool, username)
+        .await?
+        .ok_or_else(|| Error::UnknownUser(username.to_string()).into())
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{username}",
+    tag = "users",
+    params(("username" = String, Path, description = "Username of the user")),
+    responses(
+        (status = 200, body = Account),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(_auth, pool), err)]
+pub(super) async fn get(
+    Api(_auth): Api<Auth<Admin>>,
+    Extension(pool): Extension<SqlitePool>,
+    Api(Path(username)): Api<Path<String>>,
+) -> Result<Json<Account>, ApiError> {
+    Ok(Json(account(&pool, &username).await?))
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CreateUser {
+    username: String,
+    password: String,
+    /// `viewer` by default.
+    role: Option<Role>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/users",
+    tag = "users",
+    request_body = CreateUser,
+    responses(
+        (status = 201, body = Account),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 409, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(_auth, pool, user), fields(username = %user.username), err)]
+pub(super) async fn create(
+    Api(_auth): Api<Auth<Admin>>,
+    Extension(pool): Extension<SqlitePool>,
+    Api(Json(user)): Api<Json<CreateUser>>,
+) -> Result<(StatusCode, Json<Account>), ApiError> {
+    let username = user.username.trim();
+    if username.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "username must not be empty",
+        ));
+    }
+
+    let role = user.role.unwrap_or(Role::Viewer);
+
+    database::db_create_user(&pool, username, &user.password, role).await?;
+
+    Ok((StatusCode::CREATED, Json(account(&pool, username).await?)))
+}
+
+/// Fields that are left out stay unchanged.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct UpdateUser {
+    role: Option<Role>,
+    /// Disabling a user also signs them out everywhere.
+    disabled: Option<bool>,
+    password: Option<String>,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/users/{username}",
+    tag = "users",
+    params(("username" = String, Path, description = "Username of the user")),
+    request_body = UpdateUser,
+    responses(
+        (status = 200, body = Account),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(_auth, pool, cache, update), err)]
+pub(super) async fn update(
+    Api(_auth): Api<Auth<Admin>>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Api(Path(username)): Api<Path<String>>,
+    Api(Json(update)): Api<Json<UpdateUser>>,
+) -> Result<Json<Account>, ApiError> {
+    let id = database::db_get_user_id(&pool, &username).await?;
+
+    database::db_update_user(
+        &pool,
+        &id,
+        update.password.as_deref(),
+        update.role,
+        update.disabled,
+    )
+    .await?;
+
+    // cached sessions still carry the old role
+    cache.invalidate_sessions();
+
+    Ok(Json(account(&pool, &username).await?))
+}
+
+/// Removes a user and signs them out everywhere, their videos are kept.
+#[utoipa::path(
+    delete,
+    path = "/users/{username}",
+    tag = "users",
+    params(("username" = String, Path, description = "Username of the user")),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(_auth, pool, cache), err)]
+pub(super) async fn remove(
+    Api(_auth): Api<Auth<Admin>>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Api(Path(username)): Api<Path<String>>,
+) -> Result<StatusCode, ApiError> {
+    let id = database::db_get_user_id(&pool, &username).await?;
+
+    database::db_remove_user(&pool, &id).await?;
+
+    cache.invalidate_sessions();
+
+    Ok(StatusCode::NO_CONTENT)
+}
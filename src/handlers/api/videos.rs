@@ -0,0 +1,490 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Multipart, Path, Query},
+    Extension, Json,
+};
+use http::StatusCode;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use super::{Api, ApiError};
+use crate::{
+    auth::{self, Auth, MaybeAuth, Uploader, Writer},
+    cache::Cache,
+    config::Config,
+    database,
+    error::Error,
+    handlers::upload,
+    jobs::Jobs,
+    models::{Sort, Tag, Video, VideoDetails, Visibility},
+    pagination::{VideoFilter, VideoPage},
+    search::Search,
+    signing::Signer,
+};
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 200;
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub(crate) struct VideoInfo {
+    id: Uuid,
+    /// Missing for videos uploaded before ownership was tracked.
+    owner_id: Option<Uuid>,
+    visibility: Visibility,
+    /// Defaults to the name of the uploaded file.
+    title: Option<String>,
+    description: Option<String>,
+    /// When the video was recorded, as `YYYY-MM-DD`.
+    recorded: Option<String>,
+    tags: Vec<String>,
+    /// Duration in seconds, missing until the video has been probed.
+    duration: Option<f64>,
+    width: Option<i64>,
+    height: Option<i64>,
+    video_codec: Option<String>,
+    audio_codec: Option<String>,
+    /// Overall bitrate in bit/s.
+    bitrate: Option<i64>,
+    /// File size in bytes.
+    size: Option<i64>,
+    mime: String,
+    /// The page the video is watched on.
+    page_url: String,
+    /// Signed URL of the original file, valid for a few hours.
+    file_url: String,
+    /// Signed URL of the thumbnail, valid for a few hours.
+    thumbnail_url: String,
+    /// When the video was uploaded, as `YYYY-MM-DD HH:MM:SS` in UTC.
+    created: String,
+    /// How often the video's page has been opened.
+    views: i64,
+    /// Usernames the video is shared with, only included for those who can
+    /// edit the video.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shared_with: Option<Vec<String>>,
+}
+
+impl VideoInfo {
+    pub(super) fn new(video: Video, tags: Vec<String>, config: &Config, signer: &Signer) -> Self {
+        VideoInfo {
+            tags,
+            mime: video.mime().to_string(),
+            page_url: config.url(&format!("/video/{}", video.id)),
+            file_url: signer.video_url(video.file_name()),
+            thumbnail_url: signer.thumbnail_url(&video.id),
+            shared_with: None,
+            id: video.id,
+            owner_id: video.owner_id,
+            visibility: video.visibility,
+            title: video.title,
+            description: video.description,
+            recorded: video.recorded,
+            created: video.created,
+            views: video.views,
+            duration: video.duration,
+            width: video.width,
+            height: video.height,
+            video_codec: video.video_codec,
+            audio_codec: video.audio_codec,
+            bitrate: video.bitrate,
+            size: video.size,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ListVideos {
+    /// Search query, using the same syntax as the search page. Results are
+    /// ordered by relevance when it has words or phrases.
+    q: Option<String>,
+    /// Newest first by default, or by relevance when searching.
+    sort: Option<Sort>,
+    /// Continues a listing after the page that returned it as `next_cursor`,
+    /// with the same sort.
+    cursor: Option<String>,
+    /// Number of videos to skip, after the cursor if there is one.
+    #[serde(default)]
+    offset: usize,
+    /// Number of videos to return, 50 by default and at most 200.
+    limit: Option<usize>,
+    visibility: Option<Visibility>,
+    owner_id: Option<Uuid>,
+    /// Only videos with this tag.
+    tag: Option<String>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub(crate) struct VideoList {
+    videos: Vec<VideoInfo>,
+    /// Number of videos matching the filters.
+    total: usize,
+    offset: usize,
+    limit: usize,
+    /// Cursor for the next page, missing on the last page and for search
+    /// results in order of relevance.
+    next_cursor: Option<String>,
+}
+
+/// Videos listed for the user, newest first unless sorted otherwise or
+/// searching.
+///
+/// Pages are best fetched with `next_cursor`, they don't shift when videos
+/// are uploaded or removed in between.
+///
+/// Unlisted videos are only included for their owner and admins, anonymous
+/// requests only get public videos.
+#[utoipa::path(
+    get,
+    path = "/videos",
+    tag = "videos",
+    params(ListVideos),
+    security((), ("session" = []), ("token" = [])),
+    responses(
+        (status = 200, body = VideoList),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, config, signer, query), err)]
+pub(super) async fn list(
+    Api(MaybeAuth(auth)): Api<MaybeAuth>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Api(Query(query)): Api<Query<ListVideos>>,
+) -> Result<Json<VideoList>, ApiError> {
+    let user = auth.map(|auth| auth.user);
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+
+    // a tag that can't exist matches nothing
+    let tag = query
+        .tag
+        .as_deref()
+        .map(|tag| Tag::normalize(tag).unwrap_or_default());
+    let tags = tag.into_iter().collect::<Vec<_>>();
+
+    let search = query.q.as_deref().map(Search::parse).transpose()?;
+
+    let filter = VideoFilter {
+        tags: &tags,
+        visibility: query.visibility,
+        owner_id: query.owner_id,
+        search: search.as_ref(),
+    };
+
+    let page = match (query.sort, &search) {
+        // relevance isn't something a cursor can point into
+        (None, Some(_)) if query.cursor.is_some() => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "cursors need a sort when searching",
+            ));
+        }
+        (None, Some(search)) => {
+            let found = database::db_search_videos(
+                &pool,
+                user.as_ref(),
+                search,
+                &filter,
+                query.offset,
+                limit,
+            )
+            .await?;
+
+            VideoPage {
+                videos: found.hits.into_iter().map(|hit| hit.video).collect(),
+                next: None,
+                total: found.total,
+            }
+        }
+        (sort, _) => {
+            database::db_get_video_page(
+                &pool,
+                user.as_ref(),
+                &filter,
+                sort.unwrap_or_default(),
+                query.cursor.as_deref(),
+                query.offset,
+                limit,
+            )
+            .await?
+        }
+    };
+
+    let ids = page.videos.iter().map(|video| video.id).collect::<Vec<_>>();
+    let mut video_tags = database::db_get_tags_of_videos(&pool, &ids).await?;
+
+    let videos = page
+        .videos
+        .into_iter()
+        .map(|video| {
+            let tags = video_tags.remove(&video.id).unwrap_or_default();
+
+            VideoInfo::new(video, tags, &config, &signer)
+        })
+        .collect();
+
+    Ok(Json(VideoList {
+        videos,
+        total: page.total,
+        offset: query.offset,
+        limit,
+        next_cursor: page.next,
+    }))
+}
+
+/// Looks up a video the same way the player page does, videos the user can't
+/// watch don't exist.
+async fn find<R>(
+    pool: &SqlitePool,
+    cache: &Cache,
+    auth: Option<&Auth<R>>,
+    id: &Uuid,
+) -> Result<Video, ApiError> {
+    let user = auth.map(|auth| &auth.user);
+
+    match database::db_get_video(pool, cache, id).await? {
+        Some(video) if auth::can_view(pool, user, &video).await? => Ok(video),
+        _ if user.is_none() => Err(StatusCode::UNAUTHORIZED.into()),
+        _ => Err(StatusCode::NOT_FOUND.into()),
+    }
+}
+
+async fn info<R>(
+    pool: &SqlitePool,
+    config: &Config,
+    signer: &Signer,
+    user: Option<&Auth<R>>,
+    video: Video,
+) -> Result<VideoInfo, ApiError> {
+    let editable = video.is_editable_by(user.map(|auth| &auth.user));
+    let id = video.id;
+
+    let tags = database::db_get_video_tags(pool, &id).await?;
+
+    let mut info = VideoInfo::new(video, tags, config, signer);
+    if editable {
+        info.shared_with = Some(database::db_get_video_shares(pool, &id).await?);
+    }
+
+    Ok(info)
+}
+
+#[utoipa::path(
+    get,
+    path = "/videos/{id}",
+    tag = "videos",
+    params(("id" = Uuid, Path, description = "ID of the video")),
+    security((), ("session" = []), ("token" = [])),
+    responses(
+        (status = 200, body = VideoInfo),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, cache, config, signer), err)]
+pub(super) async fn get(
+    Api(MaybeAuth(auth)): Api<MaybeAuth>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Api(Path(id)): Api<Path<Uuid>>,
+) -> Result<Json<VideoInfo>, ApiError> {
+    let video = find(&pool, &cache, auth.as_ref(), &id).await?;
+
+    Ok(Json(
+        info(&pool, &config, &signer, auth.as_ref(), video).await?,
+    ))
+}
+
+/// A `multipart/form-data` upload, every file field becomes a video.
+#[derive(utoipa::ToSchema)]
+#[allow(dead_code)]
+pub(crate) struct Upload {
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+}
+
+/// Uploads one or more videos, processing starts in the background.
+///
+/// Use the tus endpoint at `/upload/tus` for large files that may need to be
+/// resumed.
+#[utoipa::path(
+    post,
+    path = "/videos",
+    tag = "videos",
+    request_body(content = Upload, content_type = "multipart/form-data"),
+    responses(
+        (status = 201, body = [VideoInfo]),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 415, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, cache, config, signer, jobs, multipart), fields(user = %auth.user.id), err)]
+pub(super) async fn upload(
+    Api(auth): Api<Auth<Uploader>>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Extension(jobs): Extension<Jobs>,
+    Api(multipart): Api<Multipart>,
+) -> Result<(StatusCode, Json<Vec<VideoInfo>>), ApiError> {
+    let ids = upload::receive(&pool, &cache, &config, &jobs, &auth.user.id, multipart).await?;
+
+    let mut videos = Vec::with_capacity(ids.len());
+    for id in ids {
+        if let Some(video) = database::db_get_video(&pool, &cache, &id).await? {
+            videos.push(VideoInfo::new(video, Vec::new(), &config, &signer));
+        }
+    }
+
+    Ok((StatusCode::CREATED, Json(videos)))
+}
+
+/// Fields that are left out stay unchanged, empty strings clear the title,
+/// description and recorded date.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct UpdateVideo {
+    title: Option<String>,
+    description: Option<String>,
+    /// As `YYYY-MM-DD`.
+    recorded: Option<String>,
+    /// Replaces the current tags.
+    tags: Option<Vec<String>>,
+    visibility: Option<Visibility>,
+    /// Usernames to share the video with, replacing the current ones. Only
+    /// used when the video is shared.
+    shared_with: Option<Vec<String>>,
+}
+
+/// Changes a video, only its owner and admins can.
+#[utoipa::path(
+    patch,
+    path = "/videos/{id}",
+    tag = "videos",
+    params(("id" = Uuid, Path, description = "ID of the video")),
+    request_body = UpdateVideo,
+    responses(
+        (status = 200, body = VideoInfo),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, cache, config, signer, update), err)]
+pub(super) async fn update(
+    Api(auth): Api<Auth<Writer>>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Api(Path(id)): Api<Path<Uuid>>,
+    Api(Json(update)): Api<Json<UpdateVideo>>,
+) -> Result<Json<VideoInfo>, ApiError> {
+    let video = match database::db_get_video(&pool, &cache, &id).await? {
+        Some(video) if video.is_editable_by(Some(&auth.user)) => video,
+        _ => return Err(StatusCode::NOT_FOUND.into()),
+    };
+
+    // checked up front so an invalid request doesn't change anything
+    let details =
+        if update.title.is_some() || update.description.is_some() || update.recorded.is_some() {
+            let merge = |update: &Option<String>, current: &Option<String>| {
+                update
+                    .clone()
+                    .or_else(|| current.clone())
+                    .unwrap_or_default()
+            };
+
+            Some(VideoDetails::new(
+                &merge(&update.title, &video.title),
+                &merge(&update.description, &video.description),
+                &merge(&update.recorded, &video.recorded),
+            )?)
+        } else {
+            None
+        };
+
+    let shared_with = if update.visibility.is_some() || update.shared_with.is_some() {
+        match update.shared_with {
+            Some(shared_with) => Some(shared_with),
+            None => Some(database::db_get_video_shares(&pool, &id).await?),
+        }
+    } else {
+        None
+    };
+    let visibility = shared_with
+        .as_deref()
+        .map(|shared_with| (update.visibility.unwrap_or(video.visibility), shared_with));
+
+    let tags = update.tags.map(|tags| Tag::parse_list(&tags.join(",")));
+
+    match database::db_update_video(
+        &pool,
+        &cache,
+        &id,
+        visibility,
+        details.as_ref(),
+        tags.as_deref(),
+    )
+    .await
+    {
+        Ok(()) => {}
+        Err(Error::UnknownUser(username)) => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("unknown user: {}", username),
+            ));
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    let video = find(&pool, &cache, Some(&auth), &id).await?;
+
+    Ok(Json(
+        info(&pool, &config, &signer, Some(&auth), video).await?,
+    ))
+}
+
+/// Removes a video and all of its files, only its owner and admins can.
+#[utoipa::path(
+    delete,
+    path = "/videos/{id}",
+    tag = "videos",
+    params(("id" = Uuid, Path, description = "ID of the video")),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[tracing::instrument(skip(auth, pool, cache, config), err)]
+pub(super) async fn remove(
+    Api(auth): Api<Auth<Writer>>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Api(Path(id)): Api<Path<Uuid>>,
+) -> Result<StatusCode, ApiError> {
+    match database::db_get_video(&pool, &cache, &id).await? {
+        Some(video) if video.is_editable_by(Some(&auth.user)) => {}
+        _ => return Err(StatusCode::NOT_FOUND.into()),
+    }
+
+    if !database::db_remove_video(&pool, &cache, &config, &id).await? {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,11 +1,21 @@
-use axum::{extract::Path, response::IntoResponse};
-use http::{header, StatusCode};
-use tokio::{fs::File, io::AsyncReadExt as _};
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, RawQuery},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use http::{header, HeaderMap, Method, StatusCode};
+use sqlx::SqlitePool;
+use uuid::Uuid;
 
 use crate::{
+    auth::MediaAuth,
+    cache::Cache,
+    config::Config,
     error::Error,
-    response::{Css, Either, Js, Left, Right},
-    AXIOS_JS, STYLE_CSS,
+    response::{mime_for_ext, Css, Either, FileResponse, Js, Left, Right},
+    AXIOS_JS, HLS_JS, STYLE_CSS,
 };
 
 #[tracing::instrument(skip(name))]
@@ -14,39 +24,174 @@ pub(crate) async fn style_script_get(
 ) -> Either<Either<Css, Js>, StatusCode> {
     match name.as_str() {
         "axios.min.js" => Left(Right(Js(AXIOS_JS))),
+        "hls.min.js" => Left(Right(Js(HLS_JS))),
         "style.css" => Left(Left(Css(STYLE_CSS))),
         _ => Right(StatusCode::NOT_FOUND),
     }
 }
 
-#[tracing::instrument(skip(id))]
+/// Media file names start with the ID of the video they belong to.
+fn video_id(name: &str) -> Option<Uuid> {
+    name.get(..36).and_then(|id| id.parse().ok())
+}
+
+#[tracing::instrument(skip(auth, pool, cache, config, name, method, headers))]
 pub(crate) async fn images_get(
-    Path(id): Path<String>,
-) -> Result<Either<impl IntoResponse, StatusCode>, Error> {
-    let image_path = std::env::current_dir()?
-        .join("assets")
-        .join("images")
-        .join(id);
-
-    if !image_path.exists() {
+    auth: MediaAuth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Path(name): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Either<FileResponse, StatusCode>, Error> {
+    if name.starts_with('.') || name.contains(['/', '\\']) {
+        return Ok(Right(StatusCode::NOT_FOUND));
+    }
+
+    let id = match video_id(&name) {
+        Some(id) => id,
+        None => return Ok(Right(StatusCode::NOT_FOUND)),
+    };
+
+    if let Some(status) = auth.check(&pool, &cache, &id).await? {
+        return Ok(Right(status));
+    }
+
+    let path = config.paths.images.join(&name);
+
+    let mime = mime_for_ext(name.rsplit_once('.').map(|(_, ext)| ext).unwrap_or(""));
+
+    match FileResponse::open(path, mime, &method, &headers).await? {
+        Some(file) => Ok(Left(file)),
+        None => Ok(Right(StatusCode::NOT_FOUND)),
+    }
+}
+
+#[tracing::instrument(skip(auth, pool, cache, config, id, path, query, method, headers))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn hls_get(
+    auth: MediaAuth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Path((id, path)): Path<(Uuid, String)>,
+    RawQuery(query): RawQuery,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Either<Response, StatusCode>, Error> {
+    if let Some(status) = auth.check(&pool, &cache, &id).await? {
+        return Ok(Right(status));
+    }
+
+    // only `master.m3u8` and `{rung}/{file}` exist, anything else is refused
+    // before it can be joined onto the directory
+    let parts = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+    if parts.is_empty()
+        || parts.len() > 2
+        || parts
+            .iter()
+            .any(|part| part.is_empty() || part.starts_with('.'))
+    {
+        return Ok(Right(StatusCode::NOT_FOUND));
+    }
+
+    let ext = match parts[parts.len() - 1].rsplit_once('.') {
+        Some((_, ext @ ("m3u8" | "m4s" | "mp4"))) => ext,
+        _ => return Ok(Right(StatusCode::NOT_FOUND)),
+    };
+
+    let mut file_path = config.paths.hls.join(id.to_string());
+    for part in parts {
+        file_path.push(part);
+    }
+
+    // relative URIs don't inherit the query string, a signed request has to
+    // hand its signature down to the playlists and segments it references
+    if let (Some(query), "m3u8") = (query, ext) {
+        if !file_path.is_file() {
+            return Ok(Right(StatusCode::NOT_FOUND));
+        }
+
+        let playlist = tokio::fs::read(&file_path).await?;
+        let playlist = sign_playlist(&String::from_utf8_lossy(&playlist), &query);
+
+        return Ok(Left(
+            (
+                StatusCode::OK,
+                [
+                    (header::CACHE_CONTROL, "no-cache"),
+                    (header::CONTENT_TYPE, mime_for_ext(ext)),
+                ],
+                playlist,
+            )
+                .into_response(),
+        ));
+    }
+
+    let file = match FileResponse::open(file_path, mime_for_ext(ext), &method, &headers).await? {
+        // segments never change once packaged, playlists are always revalidated
+        Some(file) if ext == "m3u8" => file.cache_control("no-cache"),
+        Some(file) => file,
+        None => return Ok(Right(StatusCode::NOT_FOUND)),
+    };
+
+    Ok(Left(file.into_response()))
+}
+
+fn sign_playlist(playlist: &str, query: &str) -> String {
+    let mut signed = String::with_capacity(playlist.len() * 2);
+
+    for line in playlist.lines() {
+        if line.is_empty() {
+        } else if !line.starts_with('#') {
+            signed.push_str(line);
+            signed.push('?');
+            signed.push_str(query);
+        } else if let Some((head, tail)) = line.split_once("URI=\"") {
+            // `#EXT-X-MAP:URI="init.mp4"`
+            let (uri, rest) = tail.split_once('"').unwrap_or((tail, ""));
+
+            signed.push_str(&format!("{}URI=\"{}?{}\"{}", head, uri, query, rest));
+        } else {
+            signed.push_str(line);
+        }
+
+        signed.push('\n');
+    }
+
+    signed
+}
+
+#[tracing::instrument(skip(auth, pool, cache, config, method, headers))]
+pub(crate) async fn video_get(
+    auth: MediaAuth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Path(name): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Either<FileResponse, StatusCode>, Error> {
+    if name.starts_with('.') || name.contains(['/', '\\']) {
         return Ok(Right(StatusCode::NOT_FOUND));
     }
 
-    let mut image_file = File::open(&image_path).await?;
+    let id = match video_id(&name) {
+        Some(id) => id,
+        None => return Ok(Right(StatusCode::NOT_FOUND)),
+    };
 
-    let mut bytes = Vec::with_capacity(1024 * 1024);
+    if let Some(status) = auth.check(&pool, &cache, &id).await? {
+        return Ok(Right(status));
+    }
 
-    image_file.read_to_end(&mut bytes).await?;
+    let path = config.paths.videos.join(&name);
 
-    Ok(Left(
-        (
-            StatusCode::OK,
-            [
-                (header::CACHE_CONTROL, "public, max-age=604800"),
-                (header::CONTENT_TYPE, "image/webp"),
-            ],
-            bytes,
-        )
-            .into_response(),
-    ))
+    let mime = mime_for_ext(name.rsplit_once('.').map(|(_, ext)| ext).unwrap_or(""));
+
+    match FileResponse::open(path, mime, &method, &headers).await? {
+        Some(file) => Ok(Left(file)),
+        None => Ok(Right(StatusCode::NOT_FOUND)),
+    }
 }
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    extract::Path,
+    response::{Html, IntoResponse as _, Redirect, Response},
+    Extension, Form,
+};
+use http::StatusCode;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Auth, Writer},
+    config::Config,
+    database,
+    error::Error,
+    models::{Collection, User},
+    pagination::VideoFilter,
+    search::{Search, SearchHit, SearchPage, MAX_HITS},
+    signing::Signer,
+};
+
+#[tracing::instrument(skip(auth, pool, config), err)]
+pub(crate) async fn list(
+    auth: Auth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<Html<String>, Error> {
+    #[derive(Template)]
+    #[template(path = "collections.html")]
+    struct Page {
+        user: User,
+        collections: Vec<Collection>,
+        base: String,
+    }
+
+    let collections = database::db_get_collections(&pool, &auth.user.id).await?;
+
+    Ok(Html(
+        Page {
+            user: auth.user,
+            collections,
+            base: config.server.base_path.clone(),
+        }
+        .render()?,
+    ))
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct CollectionForm {
+    name: String,
+    query: String,
+    #[serde(default)]
+    pinned: bool,
+}
+
+/// Checks the form, `None` when it's invalid.
+fn validate(form: &CollectionForm) -> Result<Option<(String, String)>, Error> {
+    match Collection::validate(&form.name, &form.query) {
+        Ok(details) => Ok(Some(details)),
+        Err(Error::Collection(_) | Error::Search(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[tracing::instrument(skip(auth, pool, config, form), err)]
+pub(crate) async fn create(
+    auth: Auth<Writer>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Form(form): Form<CollectionForm>,
+) -> Result<Response, Error> {
+    let (name, query) = match validate(&form)? {
+        Some(details) => details,
+        None => return Ok(StatusCode::BAD_REQUEST.into_response()),
+    };
+
+    let id =
+        database::db_create_collection(&pool, &auth.user.id, &name, &query, form.pinned).await?;
+
+    Ok(Redirect::to(&config.url(&format!("/collection/{}", id))).into_response())
+}
+
+/// Finds a collection of `user`, those of other users don't exist.
+async fn find(pool: &SqlitePool, user: &User, id: &Uuid) -> Result<Option<Collection>, Error> {
+    let collection = database::db_get_collection(pool, id)
+        .await?
+        .filter(|collection| collection.owner_id == user.id);
+
+    Ok(collection)
+}
+
+/// Runs the collection's query, so it shows the videos matching it right now.
+#[tracing::instrument(skip(auth, pool, config, signer), err)]
+pub(crate) async fn get(
+    auth: Auth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, Error> {
+    #[derive(Template)]
+    #[template(path = "collection.html")]
+    struct Page {
+        collection: Collection,
+        hits: Vec<SearchHit>,
+        /// Number of videos matching, there may be more than hits shown.
+        total: usize,
+        /// Why the query couldn't be understood.
+        error: Option<&'static str>,
+        signer: Signer,
+        base: String,
+    }
+
+    let collection = match find(&pool, &auth.user, &id).await? {
+        Some(collection) => collection,
+        None => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+
+    // the query was valid when it was saved, but search syntax can change
+    let (found, error) = match Search::parse(&collection.query) {
+        Ok(search) => {
+            let found = database::db_search_videos(
+                &pool,
+                Some(&auth.user),
+                &search,
+                &VideoFilter::default(),
+                0,
+                MAX_HITS,
+            )
+            .await?;
+
+            (found, None)
+        }
+        Err(Error::Search(error)) => (
+            SearchPage {
+                hits: Vec::new(),
+                total: 0,
+            },
+            Some(error),
+        ),
+        Err(err) => return Err(err),
+    };
+
+    let page = Page {
+        collection,
+        hits: found.hits,
+        total: found.total,
+        error,
+        signer,
+        base: config.server.base_path.clone(),
+    };
+
+    Ok(Html(page.render()?).into_response())
+}
+
+#[tracing::instrument(skip(auth, pool, config, form), err)]
+pub(crate) async fn details_post(
+    auth: Auth<Writer>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Path(id): Path<Uuid>,
+    Form(form): Form<CollectionForm>,
+) -> Result<Response, Error> {
+    if find(&pool, &auth.user, &id).await?.is_none() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let (name, query) = match validate(&form)? {
+        Some(details) => details,
+        None => return Ok(StatusCode::BAD_REQUEST.into_response()),
+    };
+
+    database::db_update_collection(&pool, &id, &name, &query, form.pinned).await?;
+
+    Ok(Redirect::to(&config.url(&format!("/collection/{}", id))).into_response())
+}
+
+#[tracing::instrument(skip(auth, pool, config), err)]
+pub(crate) async fn remove_post(
+    auth: Auth<Writer>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, Error> {
+    if find(&pool, &auth.user, &id).await?.is_none()
+        || !database::db_remove_collection(&pool, &id).await?
+    {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    Ok(Redirect::to(&config.url("/collections")).into_response())
+}
@@ -1,22 +1,381 @@
+use std::sync::Arc;
+
 use askama::Template;
-use axum::{response::Html, Extension};
+use axum::{
+    extract::{Path, Query},
+    response::{Html, IntoResponse as _, Response},
+    Extension,
+};
+use http::StatusCode;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use sqlx::SqlitePool;
-use uuid::Uuid;
 
-use crate::{auth::Auth, database, error::Error};
+use crate::{
+    auth::MaybeAuth,
+    cache::Cache,
+    config::Config,
+    database,
+    error::Error,
+    models::{Collection, Sort, Tag, User, Video},
+    pagination::{VideoFilter, VideoPage},
+    signing::Signer,
+};
+
+/// Videos per page, fills whole rows of the grid at every width.
+const PAGE_SIZE: usize = 48;
+
+/// Everything but the unreserved characters, so a value can't end a path
+/// segment or query parameter early.
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+#[derive(Template)]
+#[template(path = "index.html")]
+struct Page {
+    user: Option<User>,
+    /// The user's collections that are linked from the navigation.
+    pinned: Vec<Collection>,
+    videos: Vec<Video>,
+    tags: Vec<TagFilter>,
+    /// The tags every listed video has.
+    selected: Vec<String>,
+    sorts: Vec<SortLink>,
+    /// Links to the first page when this isn't it.
+    start: Option<String>,
+    more: Option<More>,
+    signer: Signer,
+    base: String,
+}
+
+/// The videos of a page after the first, loaded into the index as it's
+/// scrolled.
+#[derive(Template)]
+#[template(path = "page.html")]
+struct Partial {
+    videos: Vec<Video>,
+    more: Option<More>,
+    signer: Signer,
+    base: String,
+}
+
+/// A tag in the index's tag bar, linking to the index with the tag toggled.
+struct TagFilter {
+    name: String,
+    videos: i64,
+    selected: bool,
+    href: String,
+}
 
-#[tracing::instrument(skip(_auth, pool), err)]
+struct SortLink {
+    label: &'static str,
+    selected: bool,
+    href: String,
+}
+
+/// Links to the next page.
+struct More {
+    href: String,
+    /// Just the videos of the next page, for infinite scrolling.
+    partial: String,
+}
+
+impl Page {
+    fn title(&self) -> String {
+        self.selected
+            .iter()
+            .map(|tag| format!("#{}", tag))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct IndexQuery {
+    /// Comma separated tags the videos must all have.
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    sort: Sort,
+    cursor: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct TagQuery {
+    #[serde(default)]
+    sort: Sort,
+    cursor: Option<String>,
+}
+
+#[tracing::instrument(skip(auth, pool, cache, config, signer, query), err)]
 pub(crate) async fn get(
-    _auth: Auth,
+    MaybeAuth(auth): MaybeAuth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Query(query): Query<IndexQuery>,
+) -> Result<Response, Error> {
+    let user = auth.map(|auth| auth.user);
+    let selected = Tag::parse_list(&query.tags);
+
+    let listing = Listing {
+        path: "/",
+        selected,
+        sort: query.sort,
+        cursor: query.cursor,
+    };
+
+    match listing.page(&pool, user.as_ref()).await {
+        Ok(page) => {
+            listing
+                .render(&pool, &cache, &config, signer, user, page)
+                .await
+        }
+        Err(Error::InvalidCursor) => Ok(StatusCode::BAD_REQUEST.into_response()),
+        Err(err) => Err(err),
+    }
+}
+
+/// The index limited to a single tag, `404` when no video listed for the user
+/// has it.
+#[tracing::instrument(skip(auth, pool, cache, config, signer, query), err)]
+pub(crate) async fn tag_get(
+    MaybeAuth(auth): MaybeAuth,
     Extension(pool): Extension<SqlitePool>,
-) -> Result<Html<String>, Error> {
-    #[derive(Template)]
-    #[template(path = "index.html")]
-    struct Page {
-        videos: Vec<Uuid>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Path(name): Path<String>,
+    Query(query): Query<TagQuery>,
+) -> Result<Response, Error> {
+    let user = auth.map(|auth| auth.user);
+
+    let name = match Tag::normalize(&name) {
+        Some(name) => name,
+        None => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+
+    let listing = Listing {
+        path: &format!("/tag/{}", utf8_percent_encode(&name, COMPONENT)),
+        selected: vec![name],
+        sort: query.sort,
+        cursor: query.cursor,
+    };
+
+    match listing.page(&pool, user.as_ref()).await {
+        Ok(page) if page.total == 0 => Ok(StatusCode::NOT_FOUND.into_response()),
+        Ok(page) => {
+            listing
+                .render(&pool, &cache, &config, signer, user, page)
+                .await
+        }
+        Err(Error::InvalidCursor) => Ok(StatusCode::BAD_REQUEST.into_response()),
+        Err(err) => Err(err),
     }
+}
+
+/// Only the videos of a page, without the rest of the index.
+#[tracing::instrument(skip(auth, pool, config, signer, query), err)]
+pub(crate) async fn partial_get(
+    MaybeAuth(auth): MaybeAuth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Query(query): Query<IndexQuery>,
+) -> Result<Response, Error> {
+    let user = auth.map(|auth| auth.user);
+    let selected = Tag::parse_list(&query.tags);
+
+    let listing = Listing {
+        path: "/",
+        selected,
+        sort: query.sort,
+        cursor: query.cursor,
+    };
 
-    let videos = database::db_get_all_videos(pool).await?;
+    let page = match listing.page(&pool, user.as_ref()).await {
+        Ok(page) => page,
+        Err(Error::InvalidCursor) => return Ok(StatusCode::BAD_REQUEST.into_response()),
+        Err(err) => return Err(err),
+    };
 
-    Ok(Html(Page { videos }.render()?))
+    let partial = Partial {
+        more: listing.more(&config, &page),
+        videos: page.videos,
+        signer,
+        base: config.server.base_path.clone(),
+    };
+
+    Ok(Html(partial.render()?).into_response())
+}
+
+/// What the index is showing, everything that ends up in its URL.
+struct Listing<'a> {
+    path: &'a str,
+    /// The tags every listed video has.
+    selected: Vec<String>,
+    sort: Sort,
+    cursor: Option<String>,
+}
+
+impl Listing<'_> {
+    async fn page(&self, pool: &SqlitePool, user: Option<&User>) -> Result<VideoPage, Error> {
+        let filter = VideoFilter {
+            tags: &self.selected,
+            ..Default::default()
+        };
+
+        database::db_get_video_page(
+            pool,
+            user,
+            &filter,
+            self.sort,
+            self.cursor.as_deref(),
+            0,
+            PAGE_SIZE,
+        )
+        .await
+    }
+
+    /// The query string for `tags`, `sort` and `cursor`, leaving out defaults.
+    fn query(tags: &[String], sort: Sort, cursor: Option<&str>) -> String {
+        let mut params = Vec::new();
+
+        if !tags.is_empty() {
+            let tags = tags
+                .iter()
+                .map(|tag| utf8_percent_encode(tag, COMPONENT).to_string())
+                .collect::<Vec<_>>();
+
+            params.push(format!("tags={}", tags.join(",")));
+        }
+        if sort != Sort::default() {
+            params.push(format!("sort={}", sort.as_str()));
+        }
+        if let Some(cursor) = cursor {
+            params.push(format!("cursor={}", utf8_percent_encode(cursor, COMPONENT)));
+        }
+
+        if params.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", params.join("&"))
+        }
+    }
+
+    /// Links to this listing with `sort` and `cursor`, the tags are part of
+    /// the path on tag pages.
+    fn url(&self, config: &Config, sort: Sort, cursor: Option<&str>) -> String {
+        let tags: &[String] = if self.path == "/" {
+            &self.selected
+        } else {
+            &[]
+        };
+
+        config.url(&format!("{}{}", self.path, Self::query(tags, sort, cursor)))
+    }
+
+    fn more(&self, config: &Config, page: &VideoPage) -> Option<More> {
+        let next = page.next.as_deref()?;
+
+        Some(More {
+            href: self.url(config, self.sort, Some(next)),
+            partial: config.url(&format!(
+                "/partials/videos{}",
+                Self::query(&self.selected, self.sort, Some(next))
+            )),
+        })
+    }
+
+    async fn render(
+        &self,
+        pool: &SqlitePool,
+        cache: &Cache,
+        config: &Config,
+        signer: Signer,
+        user: Option<User>,
+        page: VideoPage,
+    ) -> Result<Response, Error> {
+        let tags = database::db_get_listed_tags(pool, cache, user.as_ref())
+            .await?
+            .into_iter()
+            .map(|tag| {
+                let selected = self.selected.contains(&tag.name);
+
+                let mut toggled = self
+                    .selected
+                    .iter()
+                    .filter(|name| **name != tag.name)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if !selected {
+                    toggled.push(tag.name.clone());
+                }
+
+                TagFilter {
+                    href: config.url(&format!("/{}", Self::query(&toggled, self.sort, None))),
+                    name: tag.name,
+                    videos: tag.videos,
+                    selected,
+                }
+            })
+            .collect();
+
+        let sorts = Sort::ALL
+            .iter()
+            .map(|&sort| SortLink {
+                label: sort.label(),
+                selected: sort == self.sort,
+                href: self.url(config, sort, None),
+            })
+            .collect();
+
+        let pinned = match &user {
+            Some(user) => database::db_get_collections(pool, &user.id)
+                .await?
+                .into_iter()
+                .filter(|collection| collection.pinned)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let start = self
+            .cursor
+            .is_some()
+            .then(|| self.url(config, self.sort, None));
+
+        let page = Page {
+            user,
+            pinned,
+            more: self.more(config, &page),
+            videos: page.videos,
+            tags,
+            selected: self.selected.clone(),
+            sorts,
+            start,
+            signer,
+            base: config.server.base_path.clone(),
+        };
+
+        Ok(Html(page.render()?).into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_are_percent_encoded() {
+        let tags = ["a&b".to_string(), "c+d/é".to_string()];
+
+        assert_eq!(
+            Listing::query(&tags, Sort::Title, Some("x=y#z")),
+            "?tags=a%26b,c%2Bd%2F%C3%A9&sort=title&cursor=x%3Dy%23z"
+        );
+        assert_eq!(Listing::query(&[], Sort::default(), None), "");
+    }
 }
@@ -1,4 +1,4 @@
-use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
 use askama::Template;
 use axum::{
@@ -11,61 +11,117 @@ use time::{ext::NumericalDuration as _, Duration, OffsetDateTime};
 use tower_cookies::Cookies;
 use uuid::Uuid;
 
-use crate::{database, error::Error, models::Login, SESSION};
+use crate::{
+    auth::{Client, SESSION_LIFETIME_DAYS},
+    cache::Cache,
+    config::Config,
+    error::Error,
+    models::Login,
+    ratelimit::{Action, Limited, RateLimiter},
+    response::{Either, Left, Right},
+    SESSION,
+};
 
-#[tracing::instrument(err)]
-pub(crate) async fn get() -> Result<Html<String>, Error> {
+#[tracing::instrument(skip(config), err)]
+pub(crate) async fn get(Extension(config): Extension<Arc<Config>>) -> Result<Html<String>, Error> {
     #[derive(Template)]
     #[template(path = "login.html")]
-    struct Page {}
+    struct Page {
+        base: String,
+    }
 
-    Ok(Html(Page {}.render()?))
+    Ok(Html(
+        Page {
+            base: config.server.base_path.clone(),
+        }
+        .render()?,
+    ))
 }
 
-#[tracing::instrument(skip(pool, login), err)]
+#[tracing::instrument(skip(pool, config, limiter, client, login), err)]
 pub(crate) async fn post(
     cookies: Cookies,
     Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(limiter): Extension<RateLimiter>,
+    client: Client,
     Form(login): Form<Login>,
-) -> Result<Redirect, Error> {
+) -> Result<Either<Redirect, Limited>, Error> {
     if !login.email.is_empty() {
-        return Ok(Redirect::permanent("/"));
+        return Ok(Left(Redirect::permanent(&config.url("/"))));
+    }
+
+    if let Err(limited) = limiter.attempt(Action::SignIn, &client) {
+        tracing::warn!(
+            ip = client.ip.as_deref().unwrap_or("-"),
+            "too many sign in attempts"
+        );
+
+        return Ok(Right(limited));
     }
 
     let user = sqlx::query!(
-        r#"SELECT id as "id: Uuid", hash FROM users WHERE username = ? "#,
+        r#"SELECT id as "id: Uuid", hash, disabled FROM users WHERE username = ? "#,
         login.username
     )
     .fetch_one(&pool)
     .await?;
-    let valid = bcrypt::verify(&login.password, &user.hash)?;
+    let valid = !user.disabled && bcrypt::verify(&login.password, &user.hash)?;
 
     if !valid {
-        return Ok(Redirect::permanent("/"));
+        return Ok(Left(Redirect::permanent(&config.url("/"))));
     }
 
     let token = nanoid::nanoid!(64);
+    let key = nanoid::nanoid!(16);
 
     sqlx::query!(
-        "INSERT INTO sessions(id, token) VALUES (?, ?)",
+        "INSERT INTO sessions(id, token, key, user_agent, ip, last_seen) VALUES (?, ?, ?, ?, ?, DATETIME('now'))",
         user.id,
-        token
+        token,
+        key,
+        client.user_agent,
+        client.ip
     )
     .execute(&pool)
     .await?;
 
     cookies.add(
         Cookie::build(SESSION, token)
-            .path("/")
-            .secure(true)
+            .path(config.cookie_path().to_string())
+            .secure(client.https)
             .http_only(true)
             .same_site(SameSite::Strict)
-            .max_age(Duration::days(7))
-            .expires(OffsetDateTime::now_utc() + 7.days())
+            .max_age(Duration::days(SESSION_LIFETIME_DAYS))
+            .expires(OffsetDateTime::now_utc() + SESSION_LIFETIME_DAYS.days())
             .finish(),
     );
 
-    database::DB_GET_ALL_VIDEOS_CACHE_INVALIDATE.store(true, Ordering::Release);
+    Ok(Left(Redirect::permanent(&config.url("/"))))
+}
+
+#[tracing::instrument(skip(pool, cache, config), err)]
+pub(crate) async fn logout(
+    cookies: Cookies,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<Redirect, Error> {
+    if let Some(session) = cookies.get(SESSION) {
+        let token = session.value().to_string();
+
+        sqlx::query!("DELETE FROM sessions WHERE token = ?", token)
+            .execute(&pool)
+            .await?;
+
+        cache.invalidate_session(&token);
+    }
+
+    cookies.remove(
+        Cookie::build(SESSION, "")
+            .path(config.cookie_path().to_string())
+            .finish(),
+    );
 
-    Ok(Redirect::permanent("/"))
+    Ok(Redirect::to(&config.url("/login")))
 }
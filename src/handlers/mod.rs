@@ -1,11 +1,34 @@
 mod admin;
+mod api;
 mod assets;
+mod collection;
 mod index;
 mod login;
+mod playlist;
+mod search;
+mod sessions;
+mod settings;
+mod sharex;
+mod tus;
 mod upload;
 mod video;
 
-use axum::{routing::get, Router};
+use std::sync::Arc;
+
+use axum::{
+    response::{IntoResponse as _, Response},
+    routing::{get, options, post},
+    Extension, Router,
+};
+use http::{StatusCode, Uri};
+
+use crate::config::Config;
+
+pub(crate) use self::{
+    sessions::purge_expired as purge_expired_sessions,
+    sharex::config as sharex_config,
+    tus::{purge_expired as purge_expired_uploads, UploadLocks},
+};
 
 pub fn routes() -> Router {
     Router::new()
@@ -13,9 +36,67 @@ pub fn routes() -> Router {
         .route("/admin", get(admin::get))
         .route("/admin/clear", get(admin::clear_sessions))
         .route("/admin/remove", get(admin::remove_video))
+        .route("/admin/tags", post(admin::tag_videos))
+        .route("/admin/tags/remove", post(admin::remove_tag))
+        .route("/admin/tags/rename", post(admin::rename_tag))
         .route("/assets/:name", get(assets::style_script_get))
+        .route("/assets/hls/:id/*path", get(assets::hls_get))
         .route("/assets/images/:id", get(assets::images_get))
+        .route("/assets/video/:name", get(assets::video_get))
+        .route("/collection/:id", get(collection::get))
+        .route("/collection/:id/details", post(collection::details_post))
+        .route("/collection/:id/remove", post(collection::remove_post))
+        .route(
+            "/collections",
+            get(collection::list).post(collection::create),
+        )
         .route("/login", get(login::get).post(login::post))
+        .route("/logout", get(login::logout).post(login::logout))
+        .route("/partials/videos", get(index::partial_get))
+        .route("/playlist/:id", get(playlist::get))
+        .route("/playlist/:id/details", post(playlist::details_post))
+        .route(
+            "/playlist/:id/items/remove",
+            post(playlist::remove_item_post),
+        )
+        .route("/playlist/:id/remove", post(playlist::remove_post))
+        .route("/playlists", get(playlist::list).post(playlist::create))
+        .route("/search", get(search::get))
+        .route("/sessions", get(sessions::get))
+        .route("/sessions/revoke", post(sessions::revoke))
+        .route("/settings", get(settings::get))
+        .route("/settings/tokens", post(settings::create_token))
+        .route("/settings/tokens/revoke", post(settings::revoke_token))
+        .route("/settings/sharex", post(sharex::config_post))
+        .route("/tag/:name", get(index::tag_get))
         .route("/upload", get(upload::get).post(upload::post))
+        .route("/upload/sharex", post(sharex::upload))
+        .route(
+            "/upload/sharex/:id/delete",
+            get(sharex::delete_get).post(sharex::delete_post),
+        )
+        .route("/upload/tus", options(tus::options).post(tus::create))
+        .route(
+            "/upload/tus/:id",
+            options(tus::options)
+                .head(tus::head)
+                .patch(tus::patch)
+                .delete(tus::delete),
+        )
         .route("/video/:id", get(video::get))
+        .route("/video/:id/details", post(video::details_post))
+        .route("/video/:id/playlist", post(playlist::add_post))
+        .route("/video/:id/tags", post(video::tags_post))
+        .route("/video/:id/visibility", post(video::visibility_post))
+        .nest("/api/v1", api::routes())
+}
+
+/// Answers requests no route matched, has to be added to the outermost router
+/// as nested routers can't have fallbacks.
+pub(crate) async fn fallback(Extension(config): Extension<Arc<Config>>, uri: Uri) -> Response {
+    if uri.path().starts_with(&config.url("/api/")) {
+        return api::not_found().into_response();
+    }
+
+    StatusCode::NOT_FOUND.into_response()
 }
@@ -0,0 +1,289 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    extract::{Path, Query},
+    response::{Html, IntoResponse as _, Redirect, Response},
+    Extension, Form,
+};
+use http::StatusCode;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::{
+    auth::{self, Auth, MaybeAuth, Writer},
+    cache::Cache,
+    config::Config,
+    database,
+    error::Error,
+    models::{Playlist, User, Video, Visibility},
+    signing::Signer,
+};
+
+#[tracing::instrument(skip(auth, pool, config), err)]
+pub(crate) async fn list(
+    MaybeAuth(auth): MaybeAuth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<Html<String>, Error> {
+    #[derive(Template)]
+    #[template(path = "playlists.html")]
+    struct Page {
+        user: Option<User>,
+        playlists: Vec<Playlist>,
+        visibilities: &'static [Visibility],
+        base: String,
+    }
+
+    let user = auth.map(|auth| auth.user);
+
+    let playlists = database::db_get_playlists(&pool)
+        .await?
+        .into_iter()
+        .filter(|playlist| playlist.is_listed_for(user.as_ref()))
+        .collect();
+
+    Ok(Html(
+        Page {
+            user,
+            playlists,
+            visibilities: Playlist::VISIBILITIES,
+            base: config.server.base_path.clone(),
+        }
+        .render()?,
+    ))
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct PlaylistForm {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default = "private")]
+    visibility: Visibility,
+}
+
+fn private() -> Visibility {
+    Visibility::Private
+}
+
+#[tracing::instrument(skip(auth, pool, config, form), err)]
+pub(crate) async fn create(
+    auth: Auth<Writer>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Form(form): Form<PlaylistForm>,
+) -> Result<Response, Error> {
+    let (name, description) =
+        match Playlist::validate(&form.name, &form.description, form.visibility) {
+            Ok(details) => details,
+            Err(Error::Playlist(_)) => return Ok(StatusCode::BAD_REQUEST.into_response()),
+            Err(err) => return Err(err),
+        };
+
+    let id = database::db_create_playlist(
+        &pool,
+        &auth.user.id,
+        &name,
+        description.as_deref(),
+        form.visibility,
+    )
+    .await?;
+
+    Ok(Redirect::to(&config.url(&format!("/playlist/{}", id))).into_response())
+}
+
+/// A video of the playlist along with everything needed to play it.
+struct Item {
+    video: Video,
+    /// URLs and MIME types, in the order browsers should try them.
+    sources: Vec<(String, &'static str)>,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct PlaylistQuery {
+    /// The video to start with, the first one by default.
+    v: Option<Uuid>,
+}
+
+#[tracing::instrument(skip(auth, pool, cache, config, signer, query), err)]
+pub(crate) async fn get(
+    MaybeAuth(auth): MaybeAuth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<PlaylistQuery>,
+) -> Result<Response, Error> {
+    #[derive(Template)]
+    #[template(path = "playlist.html")]
+    struct Page {
+        playlist: Playlist,
+        items: Vec<Item>,
+        /// Index of the item that's playing.
+        current: usize,
+        editable: bool,
+        visibilities: &'static [Visibility],
+        signer: Signer,
+        base: String,
+    }
+
+    impl Page {
+        fn playing(&self) -> Option<&Item> {
+            self.items.get(self.current)
+        }
+    }
+
+    let user = auth.map(|auth| auth.user);
+
+    let playlist = match database::db_get_playlist(&pool, &id).await? {
+        Some(playlist) if playlist.is_viewable_by(user.as_ref()) => playlist,
+        _ if user.is_none() => return Ok(StatusCode::UNAUTHORIZED.into_response()),
+        _ => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+
+    let videos = database::db_get_playlist_videos(&pool, &cache, user.as_ref(), &id).await?;
+
+    let mut items = Vec::with_capacity(videos.len());
+    for video in videos {
+        let mut sources = database::db_get_renditions(&pool, &video.id)
+            .await?
+            .into_iter()
+            .map(|rendition| {
+                (
+                    signer.video_url(rendition.file_name(&video.id)),
+                    rendition.mime(),
+                )
+            })
+            .collect::<Vec<_>>();
+        sources.push((signer.video_url(video.file_name()), video.mime()));
+
+        items.push(Item { video, sources });
+    }
+
+    let current = query
+        .v
+        .and_then(|v| items.iter().position(|item| item.video.id == v))
+        .unwrap_or_default();
+
+    let page = Page {
+        editable: playlist.is_editable_by(user.as_ref()),
+        playlist,
+        items,
+        current,
+        visibilities: Playlist::VISIBILITIES,
+        signer,
+        base: config.server.base_path.clone(),
+    };
+
+    Ok(Html(page.render()?).into_response())
+}
+
+/// Finds a playlist `user` can change.
+async fn find_editable(
+    pool: &SqlitePool,
+    user: &User,
+    id: &Uuid,
+) -> Result<Option<Playlist>, Error> {
+    let playlist = database::db_get_playlist(pool, id)
+        .await?
+        .filter(|playlist| playlist.is_editable_by(Some(user)));
+
+    Ok(playlist)
+}
+
+#[tracing::instrument(skip(auth, pool, config, form), err)]
+pub(crate) async fn details_post(
+    auth: Auth<Writer>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Path(id): Path<Uuid>,
+    Form(form): Form<PlaylistForm>,
+) -> Result<Response, Error> {
+    if find_editable(&pool, &auth.user, &id).await?.is_none() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let (name, description) =
+        match Playlist::validate(&form.name, &form.description, form.visibility) {
+            Ok(details) => details,
+            Err(Error::Playlist(_)) => return Ok(StatusCode::BAD_REQUEST.into_response()),
+            Err(err) => return Err(err),
+        };
+
+    database::db_update_playlist(&pool, &id, &name, description.as_deref(), form.visibility)
+        .await?;
+
+    Ok(Redirect::to(&config.url(&format!("/playlist/{}", id))).into_response())
+}
+
+#[tracing::instrument(skip(auth, pool, config), err)]
+pub(crate) async fn remove_post(
+    auth: Auth<Writer>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, Error> {
+    if find_editable(&pool, &auth.user, &id).await?.is_none()
+        || !database::db_remove_playlist(&pool, &id).await?
+    {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    Ok(Redirect::to(&config.url("/playlists")).into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ItemForm {
+    video_id: Uuid,
+}
+
+#[tracing::instrument(skip(auth, pool, config, form), err)]
+pub(crate) async fn remove_item_post(
+    auth: Auth<Writer>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Path(id): Path<Uuid>,
+    Form(form): Form<ItemForm>,
+) -> Result<Response, Error> {
+    if find_editable(&pool, &auth.user, &id).await?.is_none()
+        || !database::db_remove_from_playlist(&pool, &id, &form.video_id).await?
+    {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    Ok(Redirect::to(&config.url(&format!("/playlist/{}", id))).into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct AddForm {
+    playlist_id: Uuid,
+}
+
+/// Adds the video to one of the user's playlists, from the video's page.
+#[tracing::instrument(skip(auth, pool, cache, config, form), err)]
+pub(crate) async fn add_post(
+    auth: Auth<Writer>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Path(video_id): Path<Uuid>,
+    Form(form): Form<AddForm>,
+) -> Result<Response, Error> {
+    match database::db_get_video(&pool, &cache, &video_id).await? {
+        Some(video) if auth::can_view(&pool, Some(&auth.user), &video).await? => {}
+        _ => return Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+
+    if find_editable(&pool, &auth.user, &form.playlist_id)
+        .await?
+        .is_none()
+    {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    database::db_add_to_playlist(&pool, &form.playlist_id, &video_id).await?;
+
+    Ok(Redirect::to(&config.url(&format!("/video/{}", video_id))).into_response())
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    extract::Query,
+    response::{Html, IntoResponse as _, Response},
+    Extension,
+};
+use http::StatusCode;
+use sqlx::SqlitePool;
+
+use crate::{
+    auth::MaybeAuth,
+    config::Config,
+    database,
+    error::Error,
+    models::User,
+    pagination::VideoFilter,
+    search::{Search, SearchHit, SearchPage, MAX_HITS},
+    signing::Signer,
+};
+
+#[derive(Template)]
+#[template(path = "search.html")]
+struct Page {
+    user: Option<User>,
+    query: String,
+    hits: Vec<SearchHit>,
+    /// Number of videos found, there may be more than hits shown.
+    total: usize,
+    /// Why the query couldn't be understood.
+    error: Option<&'static str>,
+    signer: Signer,
+    base: String,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct SearchQuery {
+    #[serde(default)]
+    q: String,
+}
+
+#[tracing::instrument(skip(auth, pool, config, signer), err)]
+pub(crate) async fn get(
+    MaybeAuth(auth): MaybeAuth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Query(SearchQuery { q: query }): Query<SearchQuery>,
+) -> Result<Response, Error> {
+    let user = auth.map(|auth| auth.user);
+
+    let none = || SearchPage {
+        hits: Vec::new(),
+        total: 0,
+    };
+
+    let (status, found, error) = match Search::parse(&query) {
+        Ok(search) if search.is_empty() => (StatusCode::OK, none(), None),
+        Ok(search) => {
+            let found = database::db_search_videos(
+                &pool,
+                user.as_ref(),
+                &search,
+                &VideoFilter::default(),
+                0,
+                MAX_HITS,
+            )
+            .await?;
+
+            (StatusCode::OK, found, None)
+        }
+        Err(Error::Search(error)) => (StatusCode::BAD_REQUEST, none(), Some(error)),
+        Err(err) => return Err(err),
+    };
+
+    let page = Page {
+        user,
+        query,
+        hits: found.hits,
+        total: found.total,
+        error,
+        signer,
+        base: config.server.base_path.clone(),
+    };
+
+    Ok((status, Html(page.render()?)).into_response())
+}
@@ -0,0 +1,103 @@
+use std::{sync::Arc, time::Duration};
+
+use askama::Template;
+use axum::{
+    response::{Html, Redirect},
+    Extension, Form,
+};
+use sqlx::SqlitePool;
+use tower_cookies::Cookies;
+
+use crate::{
+    auth::{self, Auth, Writer},
+    cache::Cache,
+    config::Config,
+    database,
+    error::Error,
+    models::Session,
+    SESSION,
+};
+
+/// How often expired sessions are removed.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[tracing::instrument(skip(auth, cookies, pool, config), err)]
+pub(crate) async fn get(
+    auth: Auth,
+    cookies: Cookies,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<Html<String>, Error> {
+    #[derive(Template)]
+    #[template(path = "sessions.html")]
+    struct Page {
+        sessions: Vec<Session>,
+        base: String,
+    }
+
+    let current = cookies
+        .get(SESSION)
+        .map(|session| session.value().to_string())
+        .unwrap_or_default();
+
+    let sessions = database::db_get_sessions(&pool, &auth.user.id, &current).await?;
+
+    Ok(Html(
+        Page {
+            sessions,
+            base: config.server.base_path.clone(),
+        }
+        .render()?,
+    ))
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct RevokeSession {
+    key: String,
+}
+
+#[tracing::instrument(skip(auth, pool, cache, config, form), err)]
+pub(crate) async fn revoke(
+    auth: Auth<Writer>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Form(form): Form<RevokeSession>,
+) -> Result<Redirect, Error> {
+    database::db_revoke_session(&pool, &cache, &auth.user.id, &form.key).await?;
+
+    Ok(Redirect::to(&config.url("/sessions")))
+}
+
+/// Periodically removes sessions that are past their absolute or idle timeout.
+pub(crate) async fn purge_expired(pool: SqlitePool) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = purge(&pool).await {
+            tracing::error!("unable to purge expired sessions: {}", err);
+        }
+    }
+}
+
+#[tracing::instrument(skip(pool), err)]
+async fn purge(pool: &SqlitePool) -> Result<(), Error> {
+    let (lifetime, idle) = (auth::session_lifetime(), auth::session_idle());
+
+    let purged = sqlx::query!(
+        "DELETE FROM sessions WHERE created <= DATETIME('now', ?) OR last_seen IS NULL OR last_seen <= DATETIME('now', ?)",
+        lifetime,
+        idle
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    if purged != 0 {
+        tracing::info!("purged {} expired sessions", purged);
+    }
+
+    Ok(())
+}
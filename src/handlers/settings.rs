@@ -0,0 +1,257 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension, Form,
+};
+use http::StatusCode;
+use sqlx::SqlitePool;
+
+use crate::{
+    auth::{Auth, Client, SessionAuth},
+    cache::Cache,
+    config::Config,
+    database,
+    error::Error,
+    models::{ApiToken, Scope, User},
+    ratelimit::{Action, RateLimiter},
+    response::{Either, Left, Right},
+};
+
+#[derive(Template)]
+#[template(path = "settings.html")]
+struct Page {
+    tokens: Vec<ApiToken>,
+    /// The scopes the user's role can make use of.
+    scopes: Vec<Scope>,
+    /// A token that was just created, it's only shown this once.
+    created: Option<String>,
+    /// Whether the created token can upload and links can be built, to offer a
+    /// ShareX config for it.
+    sharex: bool,
+    base: String,
+}
+
+async fn render(
+    pool: &SqlitePool,
+    config: &Config,
+    user: &User,
+    created: Option<String>,
+    sharex: bool,
+) -> Result<Html<String>, Error> {
+    let tokens = database::db_get_tokens(pool, &user.id).await?;
+
+    Ok(Html(
+        Page {
+            tokens,
+            scopes: allowed_scopes(user).collect(),
+            created,
+            sharex,
+            base: config.server.base_path.clone(),
+        }
+        .render()?,
+    ))
+}
+
+fn allowed_scopes(user: &User) -> impl Iterator<Item = Scope> + '_ {
+    Scope::ALL
+        .iter()
+        .copied()
+        .filter(|scope| scope.role() <= user.role)
+}
+
+#[tracing::instrument(skip(auth, pool, config), err)]
+pub(crate) async fn get(
+    auth: Auth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<Html<String>, Error> {
+    render(&pool, &config, &auth.user, None, false).await
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct CreateToken {
+    name: String,
+    read: Option<String>,
+    upload: Option<String>,
+    admin: Option<String>,
+    /// Days until the token expires, empty for never.
+    #[serde(default)]
+    expires: String,
+}
+
+#[tracing::instrument(skip(auth, pool, config, limiter, client, form), err)]
+pub(crate) async fn create_token(
+    SessionAuth(auth): SessionAuth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(limiter): Extension<RateLimiter>,
+    client: Client,
+    Form(form): Form<CreateToken>,
+) -> Result<Either<Html<String>, Response>, Error> {
+    if let Err(limited) = limiter.attempt(Action::CreateToken, &client) {
+        tracing::warn!(user = %auth.user.id, "too many API tokens created");
+
+        return Ok(Right(limited.into_response()));
+    }
+
+    let name = form.name.trim();
+
+    let scopes = allowed_scopes(&auth.user)
+        .filter(|scope| match scope {
+            Scope::Read => form.read.is_some(),
+            Scope::Upload => form.upload.is_some(),
+            Scope::Admin => form.admin.is_some(),
+        })
+        .collect::<Vec<_>>();
+
+    let expires = match form.expires.trim() {
+        "" => None,
+        days => match days.parse::<u32>() {
+            Ok(days) if days > 0 => Some(days),
+            _ => return Ok(Right(StatusCode::BAD_REQUEST.into_response())),
+        },
+    };
+
+    if name.is_empty() || scopes.is_empty() {
+        return Ok(Right(StatusCode::BAD_REQUEST.into_response()));
+    }
+
+    let (_, token) =
+        database::db_create_token(&pool, &auth.user.id, name, &scopes, expires).await?;
+
+    tracing::info!(user = %auth.user.id, name, "created API token");
+
+    let sharex =
+        config.server.public_url.is_some() && scopes.iter().any(|scope| *scope >= Scope::Upload);
+
+    Ok(Left(
+        render(&pool, &config, &auth.user, Some(token), sharex).await?,
+    ))
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct RevokeToken {
+    key: String,
+}
+
+#[tracing::instrument(skip(auth, pool, cache, config, form), err)]
+pub(crate) async fn revoke_token(
+    SessionAuth(auth): SessionAuth,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Form(form): Form<RevokeToken>,
+) -> Result<Redirect, Error> {
+    database::db_revoke_token(&pool, &cache, &auth.user.id, &form.key).await?;
+
+    Ok(Redirect::to(&config.url("/settings")))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, routing::post, Router};
+    use http::{header, Request};
+    use tower::ServiceExt as _;
+    use tower_cookies::CookieManagerLayer;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::models::Role;
+
+    fn app(pool: &SqlitePool) -> Router {
+        Router::new()
+            .route("/settings/tokens", post(create_token))
+            .route("/settings/tokens/revoke", post(revoke_token))
+            .layer(CookieManagerLayer::new())
+            .layer(Extension(pool.clone()))
+            .layer(Extension(Cache::new()))
+            .layer(Extension(Arc::new(Config::default())))
+            .layer(Extension(RateLimiter::new()))
+    }
+
+    async fn post_form(
+        pool: &SqlitePool,
+        uri: &str,
+        (name, value): (header::HeaderName, &str),
+        form: &str,
+    ) -> StatusCode {
+        let request = Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(name, value)
+            .body(Body::from(form.to_string()))
+            .unwrap();
+
+        app(pool).oneshot(request).await.unwrap().status()
+    }
+
+    async fn session(pool: &SqlitePool, user: &Uuid) -> String {
+        let token = nanoid::nanoid!(64);
+
+        sqlx::query!(
+            "INSERT INTO sessions(id, token, key, last_seen) VALUES (?, ?, 'key', DATETIME('now'))",
+            user,
+            token
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        format!("{}={}", crate::SESSION, token)
+    }
+
+    #[tokio::test]
+    async fn tokens_cant_manage_tokens() {
+        let pool = database::memory_pool().await;
+        let user = database::db_create_user(&pool, "admin", "password", Role::Admin)
+            .await
+            .unwrap();
+        let (key, token) =
+            database::db_create_token(&pool, &user, "leaked", &[Scope::Upload], None)
+                .await
+                .unwrap();
+        let bearer = (header::AUTHORIZATION, &*format!("Bearer {}", token));
+
+        assert_eq!(
+            post_form(
+                &pool,
+                "/settings/tokens",
+                bearer.clone(),
+                "name=mine&admin=on"
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            post_form(
+                &pool,
+                "/settings/tokens/revoke",
+                bearer,
+                &format!("key={}", key)
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+
+        let tokens = database::db_get_tokens(&pool, &user).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].key, key);
+
+        let cookie = session(&pool, &user).await;
+        assert_eq!(
+            post_form(
+                &pool,
+                "/settings/tokens",
+                (header::COOKIE, &cookie),
+                "name=mine&admin=on"
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            database::db_get_tokens(&pool, &user).await.unwrap().len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,255 @@
+//! Uploads from ShareX and other custom uploaders, which send a single file
+//! along with an API token and pick the links out of the JSON response.
+
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    extract::{Multipart, Path, Query},
+    response::{Html, IntoResponse as _, Response},
+    Extension, Form, Json,
+};
+use http::{header, StatusCode};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use super::{
+    api::{Api, ApiError},
+    upload,
+};
+use crate::{
+    auth::{Auth, Uploader},
+    cache::Cache,
+    config::Config,
+    database,
+    error::Error,
+    jobs::Jobs,
+    models::Visibility,
+    signing::Signer,
+};
+
+/// The `.sxcu` custom uploader config for uploading to `request_url` with
+/// `token`.
+pub(crate) fn config(request_url: &str, token: &str, visibility: Visibility) -> serde_json::Value {
+    serde_json::json!({
+        "Version": "14.1.0",
+        "Name": "hawk",
+        "DestinationType": "FileUploader",
+        "RequestMethod": "POST",
+        "RequestURL": request_url,
+        "Parameters": {
+            "visibility": visibility.as_str(),
+        },
+        "Headers": {
+            "Authorization": format!("Bearer {}", token),
+        },
+        "Body": "MultipartFormData",
+        "FileFormName": "file",
+        "URL": "{json:url}",
+        "ThumbnailURL": "{json:thumbnail_url}",
+        "DeletionURL": "{json:deletion_url}",
+        "ErrorMessage": "{json:error}",
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct UploadOptions {
+    /// `private` by default, like every other upload.
+    visibility: Option<Visibility>,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct Uploaded {
+    id: Uuid,
+    /// The page the video is watched on.
+    url: String,
+    /// The original file, signed unless the video can be watched without
+    /// signing in.
+    media_url: String,
+    thumbnail_url: String,
+    /// Removes the video without signing in.
+    deletion_url: String,
+}
+
+/// Takes the first file of a multipart upload and stores it as a video.
+#[tracing::instrument(skip(auth, pool, cache, config, signer, jobs, options, multipart), fields(user = %auth.user.id), err)]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn upload(
+    Api(auth): Api<Auth<Uploader>>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Extension(jobs): Extension<Jobs>,
+    Api(Query(options)): Api<Query<UploadOptions>>,
+    Api(mut multipart): Api<Multipart>,
+) -> Result<Json<Uploaded>, ApiError> {
+    // the links end up outside the browser, so they have to be absolute, and
+    // the request's own host can't be trusted to build them
+    let Some(public_url) = &config.server.public_url else {
+        return Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "server.public_url has to be configured for uploads with links",
+        ));
+    };
+
+    let visibility = options.visibility.unwrap_or(Visibility::Private);
+    if visibility == Visibility::Shared {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "shared uploads aren't supported, share the video once it's uploaded",
+        ));
+    }
+
+    let mut id = None;
+    while let Some(mut field) = multipart.next_field().await.map_err(Error::from)? {
+        // uploaders may send their arguments as fields too
+        if field.file_name().is_some() {
+            id = Some(
+                upload::store(&pool, &cache, &config, &jobs, &auth.user.id, &mut field).await?,
+            );
+            break;
+        }
+    }
+
+    let id = id.ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "no file was uploaded"))?;
+
+    if visibility != Visibility::Private {
+        database::db_set_visibility(&pool, &cache, &id, visibility, &[]).await?;
+    }
+
+    let video = database::db_get_video(&pool, &cache, &id)
+        .await?
+        .ok_or_else(|| ApiError::from(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    let (media_url, thumbnail_url) = if video.is_viewable_by(None, false) {
+        (
+            config.url(&format!("/assets/video/{}", video.file_name())),
+            config.url(&format!("/assets/images/{}.webp", id)),
+        )
+    } else {
+        (
+            signer.video_url(video.file_name()),
+            signer.thumbnail_url(&id),
+        )
+    };
+
+    Ok(Json(Uploaded {
+        id,
+        url: format!("{}{}", public_url, config.url(&format!("/video/{}", id))),
+        media_url: format!("{}{}", public_url, media_url),
+        thumbnail_url: format!("{}{}", public_url, thumbnail_url),
+        deletion_url: format!("{}{}", public_url, signer.deletion_url(&id)),
+    }))
+}
+
+#[derive(Template)]
+#[template(path = "delete.html")]
+struct DeletePage {
+    id: Uuid,
+    signature: String,
+    removed: bool,
+    base: String,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct Deletion {
+    signature: String,
+}
+
+/// Asks before removing, deletion links are opened in browsers which may
+/// prefetch them.
+#[tracing::instrument(skip(pool, cache, config, signer, deletion), err)]
+pub(crate) async fn delete_get(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Path(id): Path<Uuid>,
+    Query(deletion): Query<Deletion>,
+) -> Result<Response, Error> {
+    if !signer.verify_deletion(&id, &deletion.signature)
+        || database::db_get_video(&pool, &cache, &id).await?.is_none()
+    {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let page = DeletePage {
+        id,
+        signature: deletion.signature,
+        removed: false,
+        base: config.server.base_path.clone(),
+    };
+
+    Ok(Html(page.render()?).into_response())
+}
+
+#[tracing::instrument(skip(pool, cache, config, signer, deletion), err)]
+pub(crate) async fn delete_post(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
+    Path(id): Path<Uuid>,
+    Form(deletion): Form<Deletion>,
+) -> Result<Response, Error> {
+    if !signer.verify_deletion(&id, &deletion.signature)
+        || !database::db_remove_video(&pool, &cache, &config, &id).await?
+    {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    tracing::info!(%id, "removed video through its deletion link");
+
+    let page = DeletePage {
+        id,
+        signature: String::new(),
+        removed: true,
+        base: config.server.base_path.clone(),
+    };
+
+    Ok(Html(page.render()?).into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ConfigForm {
+    token: String,
+    #[serde(default = "unlisted")]
+    visibility: Visibility,
+}
+
+fn unlisted() -> Visibility {
+    Visibility::Unlisted
+}
+
+/// Hands out the `.sxcu` for a token the user just created, the token isn't
+/// stored so it has to be sent along.
+#[tracing::instrument(skip(_auth, config, form), err)]
+pub(crate) async fn config_post(
+    _auth: Auth<Uploader>,
+    Extension(config): Extension<Arc<Config>>,
+    Form(form): Form<ConfigForm>,
+) -> Result<Response, Error> {
+    let Some(public_url) = &config.server.public_url else {
+        return Ok(StatusCode::SERVICE_UNAVAILABLE.into_response());
+    };
+    let request_url = format!("{}{}", public_url, config.url("/upload/sharex"));
+
+    let sxcu = serde_json::to_string_pretty(&self::config(
+        &request_url,
+        form.token.trim(),
+        form.visibility,
+    ))?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"hawk.sxcu\"",
+            ),
+        ],
+        sxcu,
+    )
+        .into_response())
+}
@@ -0,0 +1,576 @@
+//! A [tus 1.0](https://tus.io/protocols/resumable-upload.html) server, supporting the
+//! creation, expiration and termination extensions.
+//!
+//! Uploads are written to the staging area and handed to the same pipeline as
+//! regular multipart uploads once the last byte has been received.
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{BodyStream, Path},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use futures_util::StreamExt as _;
+use http::{header, header::HeaderName, HeaderMap, StatusCode};
+use sqlx::SqlitePool;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncSeekExt as _, AsyncWriteExt as _},
+};
+use uuid::Uuid;
+
+use crate::{
+    auth::{Auth, Uploader},
+    cache::Cache,
+    config::Config,
+    error::Error,
+    jobs::Jobs,
+};
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+
+/// How long an unfinished upload is kept around after it was created.
+const UPLOAD_EXPIRY: &str = "+1 day";
+
+/// How often expired uploads are removed from the staging area.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+static TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+static TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+static TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+static UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+static UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+static UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+static UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+
+/// The uploads requests are currently writing to or removing, cheap to clone.
+///
+/// A client that retries after its connection dropped can reach the server
+/// while the first request is still writing, both would pass the offset
+/// check and interleave their bytes.
+#[derive(Clone, Default)]
+pub(crate) struct UploadLocks {
+    busy: Arc<Mutex<BTreeSet<Uuid>>>,
+}
+
+impl UploadLocks {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// `None` when another request holds the upload's lock.
+    fn acquire(&self, id: Uuid) -> Option<UploadLock> {
+        let acquired = self
+            .busy
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id);
+
+        // a guard for a failed attempt would release the holder's lock when
+        // dropped, so it's only created once the lock is ours
+        if acquired {
+            Some(UploadLock {
+                locks: self.clone(),
+                id,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Keeps other requests from touching an upload until it's dropped.
+struct UploadLock {
+    locks: UploadLocks,
+    id: Uuid,
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        self.locks
+            .busy
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.id);
+    }
+}
+
+struct Upload {
+    length: i64,
+    received: i64,
+    metadata: Option<String>,
+    /// Unix timestamp in seconds.
+    expires: i64,
+}
+
+impl Upload {
+    /// Whether `len` more bytes fit after the `received` ones.
+    fn fits(&self, received: i64, len: usize) -> bool {
+        i64::try_from(len).is_ok_and(|len| received.saturating_add(len) <= self.length)
+    }
+
+    fn expires(&self) -> String {
+        httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(self.expires as u64))
+    }
+}
+
+/// Uploads can only be seen and continued by the user that created them.
+async fn get_upload(
+    pool: &SqlitePool,
+    id: &Uuid,
+    owner_id: &Uuid,
+) -> Result<Option<Upload>, Error> {
+    let upload = sqlx::query_as!(
+        Upload,
+        r#"SELECT length, received, metadata, CAST(strftime('%s', expires) AS INTEGER) as "expires!: i64" FROM uploads WHERE id = ? AND owner_id = ? AND expires > DATETIME('now')"#,
+        id,
+        owner_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(upload)
+}
+
+/// Looks up `key` in an `Upload-Metadata` value, a comma separated list of
+/// keys followed by their base64 encoded values.
+fn metadata_value(metadata: &str, key: &str) -> Option<String> {
+    metadata.split(',').find_map(|pair| {
+        let mut pair = pair.trim().splitn(2, ' ');
+
+        if pair.next()? != key {
+            return None;
+        }
+
+        let value = base64::decode(pair.next().unwrap_or_default().trim()).ok()?;
+
+        String::from_utf8(value).ok()
+    })
+}
+
+fn header_i64(headers: &HeaderMap, name: &HeaderName) -> Option<i64> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|value| *value >= 0)
+}
+
+/// Every request apart from `OPTIONS` has to state the protocol version it speaks.
+fn version_mismatch(headers: &HeaderMap) -> Option<Response> {
+    if headers
+        .get(&TUS_RESUMABLE)
+        .and_then(|value| value.to_str().ok())
+        == Some(TUS_VERSION)
+    {
+        return None;
+    }
+
+    Some(
+        (
+            StatusCode::PRECONDITION_FAILED,
+            [(TUS_VERSION_HEADER.clone(), TUS_VERSION)],
+        )
+            .into_response(),
+    )
+}
+
+fn status(status: StatusCode) -> Response {
+    (status, [(TUS_RESUMABLE.clone(), TUS_VERSION)]).into_response()
+}
+
+#[tracing::instrument]
+pub(crate) async fn options() -> Response {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (TUS_RESUMABLE.clone(), TUS_VERSION),
+            (TUS_VERSION_HEADER.clone(), TUS_VERSION),
+            (TUS_EXTENSION.clone(), TUS_EXTENSIONS),
+        ],
+    )
+        .into_response()
+}
+
+#[tracing::instrument(skip(auth, pool, config, headers), fields(user = %auth.user.id), err)]
+pub(crate) async fn create(
+    auth: Auth<Uploader>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    if let Some(res) = version_mismatch(&headers) {
+        return Ok(res);
+    }
+
+    // deferred lengths aren't supported, the client always knows the file size
+    let length = match header_i64(&headers, &UPLOAD_LENGTH) {
+        Some(length) if length > 0 => length,
+        _ => return Ok(status(StatusCode::BAD_REQUEST)),
+    };
+
+    let metadata = headers
+        .get(&UPLOAD_METADATA)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let id = Uuid::new_v4();
+
+    File::create(config.paths.staging.join(id.to_string())).await?;
+
+    sqlx::query!(
+        "INSERT INTO uploads(id, owner_id, length, metadata, expires) VALUES (?, ?, ?, ?, DATETIME('now', ?))",
+        id,
+        auth.user.id,
+        length,
+        metadata,
+        UPLOAD_EXPIRY
+    )
+    .execute(&pool)
+    .await?;
+
+    let upload = get_upload(&pool, &id, &auth.user.id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+
+    Ok((
+        StatusCode::CREATED,
+        [
+            (TUS_RESUMABLE.clone(), TUS_VERSION.to_string()),
+            (header::LOCATION, config.url(&format!("/upload/tus/{}", id))),
+            (UPLOAD_EXPIRES.clone(), upload.expires()),
+        ],
+    )
+        .into_response())
+}
+
+#[tracing::instrument(skip(auth, pool, headers), err)]
+pub(crate) async fn head(
+    auth: Auth<Uploader>,
+    Extension(pool): Extension<SqlitePool>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    if let Some(res) = version_mismatch(&headers) {
+        return Ok(res);
+    }
+
+    let upload = match get_upload(&pool, &id, &auth.user.id).await? {
+        Some(upload) => upload,
+        None => return Ok(status(StatusCode::NOT_FOUND)),
+    };
+
+    let mut res = (
+        StatusCode::OK,
+        [
+            (TUS_RESUMABLE.clone(), TUS_VERSION.to_string()),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+            (UPLOAD_OFFSET.clone(), upload.received.to_string()),
+            (UPLOAD_LENGTH.clone(), upload.length.to_string()),
+            (UPLOAD_EXPIRES.clone(), upload.expires()),
+        ],
+    )
+        .into_response();
+
+    if let Some(metadata) = upload.metadata.and_then(|m| m.parse().ok()) {
+        res.headers_mut().insert(UPLOAD_METADATA.clone(), metadata);
+    }
+
+    Ok(res)
+}
+
+#[tracing::instrument(skip(auth, pool, cache, config, jobs, locks, headers, body), err)]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn patch(
+    auth: Auth<Uploader>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(jobs): Extension<Jobs>,
+    Extension(locks): Extension<UploadLocks>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    mut body: BodyStream,
+) -> Result<Response, Error> {
+    if let Some(res) = version_mismatch(&headers) {
+        return Ok(res);
+    }
+
+    if headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        != Some("application/offset+octet-stream")
+    {
+        return Ok(status(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+    }
+
+    let offset = match header_i64(&headers, &UPLOAD_OFFSET) {
+        Some(offset) => offset,
+        None => return Ok(status(StatusCode::BAD_REQUEST)),
+    };
+
+    let _lock = match locks.acquire(id) {
+        Some(lock) => lock,
+        None => return Ok(status(StatusCode::CONFLICT)),
+    };
+
+    let upload = match get_upload(&pool, &id, &auth.user.id).await? {
+        Some(upload) => upload,
+        None => return Ok(status(StatusCode::NOT_FOUND)),
+    };
+
+    if offset != upload.received {
+        return Ok(status(StatusCode::CONFLICT));
+    }
+
+    let path = config.paths.staging.join(id.to_string());
+
+    let mut file = OpenOptions::new().write(true).open(&path).await?;
+
+    // anything past the recorded offset is from a request that died before
+    // its progress could be saved, the client is going to send it again
+    file.set_len(upload.received as u64).await?;
+    file.seek(std::io::SeekFrom::End(0)).await?;
+
+    let mut received = upload.received;
+    let mut result = Ok(());
+
+    while let Some(chunk) = body.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                result = Err(Error::from(err));
+                break;
+            }
+        };
+
+        if !upload.fits(received, chunk.len()) {
+            result = Err(Error::UploadTooLarge);
+            break;
+        }
+
+        if let Err(err) = file.write_all(&chunk).await {
+            result = Err(Error::from(err));
+            break;
+        }
+
+        received += chunk.len() as i64;
+    }
+
+    file.flush().await?;
+    file.sync_data().await?;
+    drop(file);
+
+    sqlx::query!("UPDATE uploads SET received = ? WHERE id = ?", received, id)
+        .execute(&pool)
+        .await?;
+
+    match result {
+        Ok(()) => {}
+        Err(Error::UploadTooLarge) => return Ok(status(StatusCode::PAYLOAD_TOO_LARGE)),
+        Err(err) => return Err(err),
+    }
+
+    if received == upload.length {
+        sqlx::query!("DELETE FROM uploads WHERE id = ?", id)
+            .execute(&pool)
+            .await?;
+
+        let file_name = upload
+            .metadata
+            .as_deref()
+            .and_then(|metadata| metadata_value(metadata, "filename"));
+
+        super::upload::ingest(
+            &pool,
+            &cache,
+            &config,
+            &jobs,
+            &id,
+            &auth.user.id,
+            file_name,
+            &path,
+        )
+        .await?;
+    }
+
+    Ok((
+        StatusCode::NO_CONTENT,
+        [
+            (TUS_RESUMABLE.clone(), TUS_VERSION.to_string()),
+            (UPLOAD_OFFSET.clone(), received.to_string()),
+            (UPLOAD_EXPIRES.clone(), upload.expires()),
+        ],
+    )
+        .into_response())
+}
+
+#[tracing::instrument(skip(auth, pool, config, locks, headers), err)]
+pub(crate) async fn delete(
+    auth: Auth<Uploader>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(locks): Extension<UploadLocks>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    if let Some(res) = version_mismatch(&headers) {
+        return Ok(res);
+    }
+
+    let _lock = match locks.acquire(id) {
+        Some(lock) => lock,
+        None => return Ok(status(StatusCode::CONFLICT)),
+    };
+
+    let removed = sqlx::query!(
+        "DELETE FROM uploads WHERE id = ? AND owner_id = ?",
+        id,
+        auth.user.id
+    )
+    .execute(&pool)
+    .await?
+    .rows_affected();
+
+    if removed == 0 {
+        return Ok(status(StatusCode::NOT_FOUND));
+    }
+
+    let path = config.paths.staging.join(id.to_string());
+    tokio::fs::remove_file(&path).await?;
+
+    Ok(status(StatusCode::NO_CONTENT))
+}
+
+/// Periodically removes uploads that weren't finished before they expired.
+pub(crate) async fn purge_expired(pool: SqlitePool, config: Arc<Config>) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = purge(&pool, &config.paths.staging).await {
+            tracing::error!("unable to purge expired uploads: {}", err);
+        }
+    }
+}
+
+#[tracing::instrument(skip(pool), err)]
+async fn purge(pool: &SqlitePool, dir: &std::path::Path) -> Result<(), Error> {
+    let expired = sqlx::query_scalar!(
+        r#"SELECT id as "id: Uuid" FROM uploads WHERE expires <= DATETIME('now')"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    if expired.is_empty() {
+        return Ok(());
+    }
+
+    for id in &expired {
+        sqlx::query!("DELETE FROM uploads WHERE id = ?", id)
+            .execute(pool)
+            .await?;
+
+        let path = dir.join(id.to_string());
+        if path.exists() {
+            tokio::fs::remove_file(&path).await?;
+        }
+    }
+
+    tracing::info!("purged {} expired uploads", expired.len());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_i64_accepts_only_non_negative_integers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(header_i64(&headers, &UPLOAD_OFFSET), None);
+
+        for (value, expected) in [
+            ("0", Some(0)),
+            ("1048576", Some(1048576)),
+            ("-1", None),
+            ("1.5", None),
+            ("12abc", None),
+            ("", None),
+            ("99999999999999999999", None),
+        ] {
+            headers.insert(UPLOAD_OFFSET.clone(), value.parse().unwrap());
+            assert_eq!(
+                header_i64(&headers, &UPLOAD_OFFSET),
+                expected,
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn metadata_value_decodes_the_requested_key() {
+        // "filename" is "clip.mp4", "filetype" is "video/mp4"
+        let metadata = "filename Y2xpcC5tcDQ=,filetype dmlkZW8vbXA0, is_confidential";
+
+        assert_eq!(
+            metadata_value(metadata, "filename").as_deref(),
+            Some("clip.mp4")
+        );
+        assert_eq!(
+            metadata_value(metadata, "filetype").as_deref(),
+            Some("video/mp4")
+        );
+        assert_eq!(
+            metadata_value(metadata, "is_confidential").as_deref(),
+            Some("")
+        );
+        assert_eq!(metadata_value(metadata, "missing"), None);
+        assert_eq!(metadata_value("filename !!!", "filename"), None);
+    }
+
+    #[test]
+    fn fits_stops_at_the_declared_length() {
+        let upload = Upload {
+            length: 100,
+            received: 0,
+            metadata: None,
+            expires: 0,
+        };
+
+        assert!(upload.fits(0, 100));
+        assert!(upload.fits(60, 40));
+        assert!(upload.fits(100, 0));
+        assert!(!upload.fits(60, 41));
+        assert!(!upload.fits(100, 1));
+        assert!(!upload.fits(i64::MAX, usize::MAX));
+    }
+
+    #[test]
+    fn upload_lock_is_exclusive_until_dropped() {
+        let id = Uuid::new_v4();
+        let other = Uuid::new_v4();
+
+        let locks = UploadLocks::new();
+
+        let lock = locks.acquire(id).expect("first request gets the lock");
+        assert!(locks.acquire(id).is_none());
+        // a failed attempt mustn't release the lock
+        assert!(locks.acquire(id).is_none());
+        assert!(locks.acquire(other).is_some());
+        // separate instances don't share their locks
+        assert!(UploadLocks::new().acquire(id).is_some());
+
+        drop(lock);
+        assert!(locks.acquire(id).is_some());
+    }
+}
@@ -1,165 +1,165 @@
-use std::{
-    io::{Cursor, Write as _},
-    ops::DerefMut as _,
-    process::Stdio,
-    sync::atomic::Ordering,
-};
+use std::{path::Path, sync::Arc};
 
 use askama::Template;
-use axum::{extract::Multipart, http::StatusCode, response::Html, Extension};
-use infer::MatcherType;
-use sqlx::SqlitePool;
-use tokio::{
-    fs::File,
-    io::{AsyncReadExt as _, AsyncWriteExt as _},
-    process::Command,
+use axum::{
+    extract::{multipart::Field, Multipart},
+    http::StatusCode,
+    response::Html,
+    Extension,
 };
+use sqlx::SqlitePool;
+use tokio::{fs::File, io::AsyncWriteExt as _};
 use uuid::Uuid;
 
-use crate::{auth::Auth, database, error::Error};
+use crate::{
+    auth::{Auth, Uploader},
+    cache::Cache,
+    config::Config,
+    error::Error,
+    jobs::Jobs,
+    media,
+    models::VideoDetails,
+};
 
-#[tracing::instrument(skip(_auth), err)]
-pub(crate) async fn get(_auth: Auth) -> Result<Html<String>, Error> {
+#[tracing::instrument(skip(_auth, config), err)]
+pub(crate) async fn get(
+    _auth: Auth<Uploader>,
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<Html<String>, Error> {
     #[derive(Template)]
     #[template(path = "upload.html")]
-    struct Page {}
+    struct Page {
+        base: String,
+    }
 
-    Ok(Html(Page {}.render()?))
+    Ok(Html(
+        Page {
+            base: config.server.base_path.clone(),
+        }
+        .render()?,
+    ))
 }
 
-#[tracing::instrument(skip(_auth, pool, multipart), err)]
+#[tracing::instrument(skip(auth, pool, cache, config, jobs, multipart), fields(user = %auth.user.id), err)]
 pub(crate) async fn post(
-    _auth: Auth,
+    auth: Auth<Uploader>,
     Extension(pool): Extension<SqlitePool>,
-    mut multipart: Multipart,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(jobs): Extension<Jobs>,
+    multipart: Multipart,
 ) -> Result<StatusCode, Error> {
-    while let Some(mut field) = multipart.next_field().await? {
-        let name = field.name().unwrap().to_string();
-        let ext = name.split('.').last().unwrap();
+    receive(&pool, &cache, &config, &jobs, &auth.user.id, multipart).await?;
 
-        let id = Uuid::new_v4();
-
-        let path = std::env::current_dir()?
-            .join("assets")
-            .join("video")
-            .join(format!("{}.{}", id, ext));
-
-        {
-            let mut file = File::create(&path).await?;
+    Ok(StatusCode::CREATED)
+}
 
-            while let Some(mut chunk) = field.chunk().await? {
-                file.write_all_buf(&mut chunk).await?;
-            }
+/// Stores every field of a multipart upload as a new video, returning their IDs.
+pub(super) async fn receive(
+    pool: &SqlitePool,
+    cache: &Cache,
+    config: &Config,
+    jobs: &Jobs,
+    owner_id: &Uuid,
+    mut multipart: Multipart,
+) -> Result<Vec<Uuid>, Error> {
+    let mut ids = Vec::new();
 
-            file.flush().await?;
-        }
+    while let Some(mut field) = multipart.next_field().await? {
+        ids.push(store(pool, cache, config, jobs, owner_id, &mut field).await?);
+    }
 
-        let typ = get_type(&path).await?;
-        let ext = typ.extension();
+    Ok(ids)
+}
 
-        let old_path = path;
-        let path = std::env::current_dir()?
-            .join("assets")
-            .join("video")
-            .join(format!("{}.{}", id, ext));
+/// Stores a single multipart field as a new video, returning its ID.
+pub(super) async fn store(
+    pool: &SqlitePool,
+    cache: &Cache,
+    config: &Config,
+    jobs: &Jobs,
+    owner_id: &Uuid,
+    field: &mut Field<'_>,
+) -> Result<Uuid, Error> {
+    let id = Uuid::new_v4();
 
-        tokio::fs::rename(&old_path, &path).await?;
+    let path = config.paths.staging.join(id.to_string());
 
-        generate_thumbnail(&id, &path).await?;
+    // never leave a half written upload behind
+    if let Err(err) = write_field(field, &path).await {
+        let _ = tokio::fs::remove_file(&path).await;
 
-        sqlx::query!("INSERT INTO videos(id, ext) VALUES (?, ?)", id, ext)
-            .execute(&pool)
-            .await?;
+        return Err(err);
     }
 
-    database::DB_GET_ALL_VIDEOS_CACHE_INVALIDATE.store(true, Ordering::Release);
+    let file_name = field.file_name().map(str::to_string);
 
-    Ok(StatusCode::CREATED)
-}
+    ingest(pool, cache, config, jobs, &id, owner_id, file_name, &path).await?;
 
-#[tracing::instrument(skip(path), fields(path = %path.as_ref().display()), err)]
-async fn get_type<P: AsRef<std::path::Path>>(path: P) -> Result<infer::Type, Error> {
-    let file = File::open(&path).await?;
-
-    let limit = file
-        .metadata()
-        .await
-        .map(|m| std::cmp::min(m.len(), 8192) as usize + 1)
-        .unwrap_or(0);
-
-    let mut bytes = Vec::with_capacity(limit);
-    file.take(limit as u64).read_to_end(&mut bytes).await?;
+    Ok(id)
+}
 
-    let typ = infer::get(&bytes).ok_or(Error::Infer(
-        "unable to figure out mime type, buf may be empty",
-    ))?;
+async fn write_field(field: &mut Field<'_>, path: &Path) -> Result<(), Error> {
+    let mut file = File::create(path).await?;
 
-    if typ.matcher_type() != MatcherType::Video {
-        return Err(Error::InvalidFileType);
+    while let Some(mut chunk) = field.chunk().await? {
+        file.write_all_buf(&mut chunk).await?;
     }
 
-    Ok(typ)
+    file.flush().await?;
+
+    Ok(())
 }
 
-#[tracing::instrument(skip(path), fields(path = %path.as_ref().display()), err)]
-async fn generate_thumbnail<P: AsRef<std::path::Path>>(id: &Uuid, path: P) -> Result<(), Error> {
-    let bytes = get_webp_frame(path, 16).await?;
+/// Hands a fully received upload over to the processing pipeline, moving it
+/// out of the staging area and queueing its processing jobs.
+///
+/// The title defaults to `file_name`, the name of the uploaded file. Files that
+/// aren't videos are removed.
+#[tracing::instrument(skip(pool, cache, config, jobs, staged), fields(staged = %staged.display()), err)]
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn ingest(
+    pool: &SqlitePool,
+    cache: &Cache,
+    config: &Config,
+    jobs: &Jobs,
+    id: &Uuid,
+    owner_id: &Uuid,
+    file_name: Option<String>,
+    staged: &Path,
+) -> Result<(), Error> {
+    let typ = match media::get_type(staged).await {
+        Ok(typ) => typ,
+        Err(err) => {
+            let _ = tokio::fs::remove_file(staged).await;
+
+            return Err(err);
+        }
+    };
+    let ext = typ.extension();
 
-    let img =
-        image::io::Reader::with_format(Cursor::new(bytes), image::ImageFormat::WebP).decode()?;
-    let img = img.thumbnail(1920 / 5, 1080 / 5);
+    let path = config.paths.videos.join(format!("{}.{}", id, ext));
 
-    let path = std::env::current_dir()?
-        .join("assets")
-        .join("images")
-        .join(format!("{}.webp", id));
+    tokio::fs::rename(staged, &path).await?;
 
-    tokio::task::spawn_blocking(move || -> Result<(), Error> {
-        let image = webp::Encoder::from_image(&img).map_err(|err| Error::Webp(err.to_string()))?;
-        let mut mem = image.encode(75.0);
+    let title = file_name
+        .as_deref()
+        .and_then(VideoDetails::title_from_file_name);
 
-        let mut file = std::fs::File::create(&path)?;
-        file.write_all(mem.deref_mut())?;
+    sqlx::query!(
+        "INSERT INTO videos(id, ext, owner_id, title, original_name) VALUES (?, ?, ?, ?, ?)",
+        id,
+        ext,
+        owner_id,
+        title,
+        file_name
+    )
+    .execute(pool)
+    .await?;
 
-        Ok(())
-    })
-    .await??;
+    cache.invalidate_video(id);
 
-    Ok(())
-}
+    jobs.enqueue_video(id).await?;
 
-#[tracing::instrument(skip(path, index), err)]
-async fn get_webp_frame<P: AsRef<std::path::Path>>(
-    path: P,
-    index: usize,
-) -> Result<Vec<u8>, Error> {
-    let child = Command::new("ffmpeg")
-        .args([
-            "-loglevel",
-            "panic",
-            "-i",
-            path.as_ref().as_os_str().to_str().unwrap(),
-            "-vf",
-            format!("select=eq(n\\,{})", index).as_str(),
-            "-vframes",
-            "1",
-            "-c:v",
-            "webp",
-            "-movflags",
-            "empty_moov",
-            "-f",
-            "image2pipe",
-            "pipe:1",
-        ])
-        .stdout(Stdio::piped())
-        .spawn()?;
-
-    let output = child.wait_with_output().await?;
-    if output.status.success() && !output.stdout.is_empty() {
-        Ok(output.stdout)
-    } else {
-        Err(Error::Ffmpeg(
-            String::from_utf8_lossy(&output.stderr[..]).to_string(),
-        ))
-    }
+    Ok(())
 }
@@ -1,29 +1,215 @@
+use std::sync::Arc;
+
 use askama::Template;
-use axum::{extract::Path, response::Html, Extension};
+use axum::{
+    extract::Path,
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension, Form,
+};
+use http::StatusCode;
 use sqlx::SqlitePool;
 use uuid::Uuid;
 
-use crate::{auth::Auth, error::Error, models::Video};
+use crate::{
+    auth::{self, Auth, MaybeAuth, Writer},
+    cache::Cache,
+    config::Config,
+    database,
+    error::Error,
+    models::{Playlist, Rendition, Tag, Video, VideoDetails, Visibility},
+    signing::Signer,
+};
 
-#[tracing::instrument(skip(_auth, pool), err)]
+#[tracing::instrument(skip(auth, pool, cache, config, signer), err)]
 pub(crate) async fn get(
-    _auth: Auth,
+    MaybeAuth(auth): MaybeAuth,
     Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(signer): Extension<Signer>,
     Path(id): Path<Uuid>,
-) -> Result<Html<String>, Error> {
+) -> Result<Response, Error> {
     #[derive(Template)]
     #[template(path = "video.html")]
     struct Page {
         video: Video,
+        renditions: Vec<Rendition>,
+        hls: bool,
+        signer: Signer,
+        editable: bool,
+        tags: Vec<String>,
+        /// Tags to suggest when editing.
+        known_tags: Vec<Tag>,
+        /// The playlists the user can add the video to.
+        playlists: Vec<Playlist>,
+        visibilities: &'static [Visibility],
+        shared_with: String,
+        base: String,
     }
 
-    let video = sqlx::query_as!(
-        Video,
-        r#"SELECT id as "id: Uuid", ext FROM videos WHERE id = ?"#,
-        id
+    let user = auth.map(|auth| auth.user);
+
+    let video = match database::db_get_video(&pool, &cache, &id).await? {
+        Some(video) if auth::can_view(&pool, user.as_ref(), &video).await? => video,
+        _ if user.is_none() => return Ok(StatusCode::UNAUTHORIZED.into_response()),
+        _ => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+
+    database::db_add_view(&pool, &id).await?;
+
+    let renditions = database::db_get_renditions(&pool, &id).await?;
+
+    let hls = config
+        .paths
+        .hls
+        .join(id.to_string())
+        .join("master.m3u8")
+        .exists();
+
+    let editable = video.is_editable_by(user.as_ref());
+
+    let shared_with = if editable {
+        database::db_get_video_shares(&pool, &id).await?.join(", ")
+    } else {
+        String::new()
+    };
+
+    let tags = database::db_get_video_tags(&pool, &id).await?;
+
+    let playlists = match &user {
+        Some(user) => database::db_get_playlists(&pool)
+            .await?
+            .into_iter()
+            .filter(|playlist| playlist.is_editable_by(Some(user)))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let known_tags = if editable {
+        database::db_get_listed_tags(&pool, &cache, user.as_ref()).await?
+    } else {
+        Vec::new()
+    };
+
+    Ok(Html(
+        Page {
+            video,
+            renditions,
+            hls,
+            signer,
+            editable,
+            tags,
+            known_tags,
+            playlists,
+            visibilities: Visibility::ALL,
+            shared_with,
+            base: config.server.base_path.clone(),
+        }
+        .render()?,
     )
-    .fetch_one(&pool)
-    .await?;
+    .into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ChangeVisibility {
+    visibility: Visibility,
+    /// Comma separated usernames, only used for [`Visibility::Shared`].
+    #[serde(default)]
+    shared_with: String,
+}
+
+#[tracing::instrument(skip(auth, pool, cache, config, form), err)]
+pub(crate) async fn visibility_post(
+    auth: Auth<Writer>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Path(id): Path<Uuid>,
+    Form(form): Form<ChangeVisibility>,
+) -> Result<Response, Error> {
+    match database::db_get_video(&pool, &cache, &id).await? {
+        Some(video) if video.is_editable_by(Some(&auth.user)) => {}
+        _ => return Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+
+    let shared_with = form
+        .shared_with
+        .split(',')
+        .map(str::trim)
+        .filter(|username| !username.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    match database::db_set_visibility(&pool, &cache, &id, form.visibility, &shared_with).await {
+        Ok(()) => {}
+        Err(Error::UnknownUser(_)) => return Ok(StatusCode::BAD_REQUEST.into_response()),
+        Err(err) => return Err(err),
+    }
+
+    Ok(Redirect::to(&config.url(&format!("/video/{}", id))).into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ChangeDetails {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    recorded: String,
+}
+
+#[tracing::instrument(skip(auth, pool, cache, config, form), err)]
+pub(crate) async fn details_post(
+    auth: Auth<Writer>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Path(id): Path<Uuid>,
+    Form(form): Form<ChangeDetails>,
+) -> Result<Response, Error> {
+    match database::db_get_video(&pool, &cache, &id).await? {
+        Some(video) if video.is_editable_by(Some(&auth.user)) => {}
+        _ => return Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+
+    let details = match VideoDetails::new(&form.title, &form.description, &form.recorded) {
+        Ok(details) => details,
+        Err(Error::Details(_)) => return Ok(StatusCode::BAD_REQUEST.into_response()),
+        Err(err) => return Err(err),
+    };
+
+    database::db_set_details(&pool, &cache, &id, &details).await?;
+
+    Ok(Redirect::to(&config.url(&format!("/video/{}", id))).into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ChangeTags {
+    /// Comma separated tags to add.
+    #[serde(default)]
+    add: String,
+    /// Comma separated tags to remove.
+    #[serde(default)]
+    remove: String,
+}
+
+#[tracing::instrument(skip(auth, pool, cache, config, form), err)]
+pub(crate) async fn tags_post(
+    auth: Auth<Writer>,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(cache): Extension<Cache>,
+    Extension(config): Extension<Arc<Config>>,
+    Path(id): Path<Uuid>,
+    Form(form): Form<ChangeTags>,
+) -> Result<Response, Error> {
+    match database::db_get_video(&pool, &cache, &id).await? {
+        Some(video) if video.is_editable_by(Some(&auth.user)) => {}
+        _ => return Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+
+    database::db_add_tags(&pool, &[id], &Tag::parse_list(&form.add)).await?;
+    database::db_remove_tags(&pool, &[id], &Tag::parse_list(&form.remove)).await?;
 
-    Ok(Html(Page { video }.render()?))
+    Ok(Redirect::to(&config.url(&format!("/video/{}", id))).into_response())
 }
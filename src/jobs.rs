@@ -0,0 +1,374 @@
+use std::{sync::Arc, time::Duration};
+
+use sqlx::SqlitePool;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{
+    cache::Cache,
+    config::Config,
+    error::Error,
+    media::{self, Rendition},
+};
+
+/// How long an idle worker sleeps before checking for retries that became due.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The delay before the first retry, doubled for every failed attempt after that.
+const RETRY_BASE_SECONDS: i64 = 30;
+
+/// A piece of media processing for a single video.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum JobKind {
+    Probe,
+    Thumbnail,
+    Transcode(Rendition),
+    Hls,
+}
+
+impl JobKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobKind::Probe => "probe",
+            JobKind::Thumbnail => "thumbnail",
+            JobKind::Transcode(Rendition::H264) => "transcode-h264",
+            JobKind::Transcode(Rendition::Vp9) => "transcode-vp9",
+            JobKind::Hls => "hls",
+        }
+    }
+
+    fn from_str(kind: &str) -> Option<Self> {
+        match kind {
+            "probe" => Some(JobKind::Probe),
+            "thumbnail" => Some(JobKind::Thumbnail),
+            "transcode-h264" => Some(JobKind::Transcode(Rendition::H264)),
+            "transcode-vp9" => Some(JobKind::Transcode(Rendition::Vp9)),
+            "hls" => Some(JobKind::Hls),
+            _ => None,
+        }
+    }
+}
+
+/// Handle to the persistent job queue, cheap to clone.
+///
+/// Jobs are stored in the `jobs` table and picked up by a fixed number of
+/// workers, anything left `running` by a previous process is requeued on start.
+#[derive(Clone)]
+pub(crate) struct Jobs {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    pool: SqlitePool,
+    cache: Cache,
+    notify: Notify,
+    config: Arc<Config>,
+    /// Renditions produced for every uploaded video.
+    renditions: Vec<Rendition>,
+}
+
+struct Job {
+    id: i64,
+    video_id: Uuid,
+    kind: String,
+    attempts: i64,
+}
+
+impl Jobs {
+    pub(crate) async fn start(
+        pool: SqlitePool,
+        cache: Cache,
+        config: Arc<Config>,
+    ) -> Result<Self, Error> {
+        let requeued = sqlx::query!(
+            "UPDATE jobs SET status = 'queued', updated = DATETIME('now') WHERE status = 'running'"
+        )
+        .execute(&pool)
+        .await?
+        .rows_affected();
+
+        if requeued != 0 {
+            tracing::info!("requeued {} interrupted jobs", requeued);
+        }
+
+        let jobs = Self {
+            inner: Arc::new(Inner {
+                pool,
+                cache,
+                notify: Notify::new(),
+                renditions: config.renditions(),
+                config,
+            }),
+        };
+
+        for worker in 0..jobs.inner.config.media.workers {
+            tokio::spawn(jobs.clone().work(worker));
+        }
+
+        Ok(jobs)
+    }
+
+    /// Queues all of the processing an uploaded video needs.
+    ///
+    /// HLS packaging picks its rungs by the source's height, so it's only
+    /// queued once the probe has stored it.
+    pub(crate) async fn enqueue_video(&self, video_id: &Uuid) -> Result<(), Error> {
+        self.enqueue(video_id, JobKind::Probe).await?;
+        self.enqueue(video_id, JobKind::Thumbnail).await?;
+
+        for rendition in &self.inner.renditions {
+            self.enqueue(video_id, JobKind::Transcode(*rendition))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Queues `kind` for a video unless it already has such a job, a probe
+    /// that's retried after queueing HLS packaging mustn't queue it twice, two
+    /// jobs would package into the same directory at once.
+    pub(crate) async fn enqueue(&self, video_id: &Uuid, kind: JobKind) -> Result<(), Error> {
+        let kind = kind.as_str();
+
+        let queued = sqlx::query!(
+            "INSERT INTO jobs(video_id, kind) SELECT ?, ? WHERE NOT EXISTS (SELECT 1 FROM jobs WHERE video_id = ? AND kind = ?)",
+            video_id,
+            kind,
+            video_id,
+            kind
+        )
+        .execute(&self.inner.pool)
+        .await?
+        .rows_affected();
+
+        if queued == 0 {
+            tracing::debug!(video = %video_id, kind, "job is already queued");
+
+            return Ok(());
+        }
+
+        self.inner.notify.notify_one();
+
+        Ok(())
+    }
+
+    async fn work(self, worker: usize) {
+        loop {
+            match self.claim().await {
+                Ok(Some(job)) => self.run(job).await,
+                Ok(None) => {
+                    tokio::select! {
+                        _ = self.inner.notify.notified() => {},
+                        _ = tokio::time::sleep(POLL_INTERVAL) => {},
+                    }
+                }
+                Err(err) => {
+                    tracing::error!("job worker {} unable to claim job: {}", worker, err);
+
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// Takes the oldest due job, the conditional update makes sure that only
+    /// one worker can win a job even when several see it at the same time.
+    async fn claim(&self) -> Result<Option<Job>, Error> {
+        loop {
+            let job = sqlx::query_as!(
+                Job,
+                r#"SELECT id as "id!", video_id as "video_id: Uuid", kind, attempts FROM jobs WHERE status = 'queued' AND run_at <= DATETIME('now') ORDER BY run_at, id LIMIT 1"#
+            )
+            .fetch_optional(&self.inner.pool)
+            .await?;
+
+            let mut job = match job {
+                Some(job) => job,
+                None => return Ok(None),
+            };
+
+            let claimed = sqlx::query!(
+                "UPDATE jobs SET status = 'running', attempts = attempts + 1, updated = DATETIME('now') WHERE id = ? AND status = 'queued'",
+                job.id
+            )
+            .execute(&self.inner.pool)
+            .await?
+            .rows_affected();
+
+            if claimed == 1 {
+                job.attempts += 1;
+
+                return Ok(Some(job));
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(self, job), fields(id = job.id, video = %job.video_id, kind = %job.kind, attempt = job.attempts))]
+    async fn run(&self, job: Job) {
+        let result = match JobKind::from_str(&job.kind) {
+            Some(kind) => self.execute(&job.video_id, kind).await,
+            None => Err(Error::UnknownJob(job.kind.clone())),
+        };
+
+        if let Err(err) = self.finish(&job, result).await {
+            tracing::error!("unable to update job: {}", err);
+        }
+    }
+
+    async fn execute(&self, video_id: &Uuid, kind: JobKind) -> Result<(), Error> {
+        let video = sqlx::query!("SELECT ext, height FROM videos WHERE id = ?", video_id)
+            .fetch_optional(&self.inner.pool)
+            .await?;
+
+        let video = match video {
+            Some(video) => video,
+            None => {
+                tracing::warn!("video was removed before the job ran, skipping");
+
+                return Ok(());
+            }
+        };
+
+        let config = &self.inner.config;
+
+        let path = config
+            .paths
+            .videos
+            .join(format!("{}.{}", video_id, video.ext));
+
+        match kind {
+            JobKind::Probe => {
+                let probe = media::probe(config, &path).await?;
+
+                sqlx::query!(
+                    "UPDATE videos SET duration = ?, width = ?, height = ?, video_codec = ?, audio_codec = ?, bitrate = ?, size = ? WHERE id = ?",
+                    probe.duration,
+                    probe.width,
+                    probe.height,
+                    probe.video_codec,
+                    probe.audio_codec,
+                    probe.bitrate,
+                    probe.size,
+                    video_id
+                )
+                .execute(&self.inner.pool)
+                .await?;
+
+                self.inner.cache.invalidate_video(video_id);
+
+                if config.media.hls {
+                    self.enqueue(video_id, JobKind::Hls).await?;
+                }
+            }
+            JobKind::Thumbnail => media::generate_thumbnail(config, video_id, &path).await?,
+            JobKind::Transcode(rendition) => {
+                media::transcode(config, video_id, &path, rendition).await?;
+
+                let (name, ext) = (rendition.name(), rendition.ext());
+
+                sqlx::query!(
+                    "INSERT OR REPLACE INTO renditions(video_id, name, ext) VALUES (?, ?, ?)",
+                    video_id,
+                    name,
+                    ext
+                )
+                .execute(&self.inner.pool)
+                .await?;
+            }
+            JobKind::Hls => {
+                media::package_hls(config, video_id, &path, video.height).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn finish(&self, job: &Job, result: Result<(), Error>) -> Result<(), Error> {
+        let err = match result {
+            Ok(()) => {
+                sqlx::query!(
+                    "UPDATE jobs SET status = 'done', error = NULL, updated = DATETIME('now') WHERE id = ?",
+                    job.id
+                )
+                .execute(&self.inner.pool)
+                .await?;
+
+                return Ok(());
+            }
+            Err(err) => err.to_string(),
+        };
+
+        if job.attempts >= self.inner.config.media.max_attempts {
+            tracing::error!("job failed permanently: {}", err);
+
+            sqlx::query!(
+                "UPDATE jobs SET status = 'failed', error = ?, updated = DATETIME('now') WHERE id = ?",
+                err,
+                job.id
+            )
+            .execute(&self.inner.pool)
+            .await?;
+        } else {
+            let seconds = RETRY_BASE_SECONDS << (job.attempts - 1).min(16);
+            let delay = format!("+{} seconds", seconds);
+
+            tracing::warn!("job failed, retrying in {}s: {}", seconds, err);
+
+            sqlx::query!(
+                "UPDATE jobs SET status = 'queued', error = ?, run_at = DATETIME('now', ?), updated = DATETIME('now') WHERE id = ?",
+                err,
+                delay,
+                job.id
+            )
+            .execute(&self.inner.pool)
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::memory_pool;
+
+    #[tokio::test]
+    async fn jobs_are_queued_once_per_video() {
+        let pool = memory_pool().await;
+
+        let mut config = Config::default();
+        // nothing may pick the jobs up while they're counted
+        config.media.workers = 0;
+
+        let jobs = Jobs::start(pool.clone(), Cache::new(), Arc::new(config))
+            .await
+            .unwrap();
+
+        let (video, other) = (Uuid::new_v4(), Uuid::new_v4());
+        for _ in 0..2 {
+            jobs.enqueue(&video, JobKind::Hls).await.unwrap();
+        }
+        jobs.enqueue(&video, JobKind::Probe).await.unwrap();
+        jobs.enqueue(&other, JobKind::Hls).await.unwrap();
+
+        let count = |id: Uuid, kind: JobKind| {
+            let pool = pool.clone();
+
+            async move {
+                sqlx::query_scalar::<_, i64>(
+                    "SELECT COUNT(*) FROM jobs WHERE video_id = ? AND kind = ?",
+                )
+                .bind(id)
+                .bind(kind.as_str())
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+            }
+        };
+
+        assert_eq!(count(video, JobKind::Hls).await, 1);
+        assert_eq!(count(video, JobKind::Probe).await, 1);
+        assert_eq!(count(other, JobKind::Hls).await, 1);
+    }
+}
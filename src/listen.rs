@@ -0,0 +1,122 @@
+use std::{fmt, net::SocketAddr, path::PathBuf, str::FromStr};
+
+use axum::extract::connect_info::Connected;
+use hyper::server::conn::AddrStream;
+
+/// Where a listener accepts connections, written as `host:port` (IPv6 hosts
+/// in brackets) or `unix:/path/to/socket`.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_prefix("unix:") {
+            Some("") => Err("unix socket path must not be empty".into()),
+            Some(path) => Ok(ListenAddr::Unix(path.into())),
+            None => value
+                .parse()
+                .map(ListenAddr::Tcp)
+                .map_err(|err| format!("{}: {}", value, err)),
+        }
+    }
+}
+
+impl TryFrom<String> for ListenAddr {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ListenAddr> for String {
+    fn from(addr: ListenAddr) -> Self {
+        addr.to_string()
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Whether the listener a request came in on serves TLS, added to every
+/// request as an extension.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ListenerTls(pub(crate) bool);
+
+/// The other end of a connection, available to handlers as `ConnectInfo`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Peer {
+    Tcp(SocketAddr),
+    /// Unix sockets are only reachable from the same machine, there is no
+    /// address worth recording.
+    Unix,
+}
+
+impl Connected<&AddrStream> for Peer {
+    fn connect_info(target: &AddrStream) -> Self {
+        Peer::Tcp(target.remote_addr())
+    }
+}
+
+#[cfg(unix)]
+pub(crate) use self::unix::serve_unix;
+
+#[cfg(unix)]
+mod unix {
+    use std::{future::Future, path::PathBuf, task::Poll};
+
+    use axum::{extract::connect_info::Connected, Router};
+    use tokio::net::{UnixListener, UnixStream};
+
+    use super::Peer;
+    use crate::error::Error;
+
+    impl Connected<&UnixStream> for Peer {
+        fn connect_info(_target: &UnixStream) -> Self {
+            Peer::Unix
+        }
+    }
+
+    /// Serves `app` on a Unix socket until `shutdown` completes, then waits for
+    /// open connections to finish.
+    ///
+    /// A socket left behind by a previous run is replaced, the socket is
+    /// removed again once the server stops.
+    pub(crate) async fn serve_unix(
+        path: PathBuf,
+        app: Router,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), Error> {
+        if path.exists() {
+            tokio::fs::remove_file(&path).await?;
+        }
+
+        let listener = UnixListener::bind(&path)?;
+
+        let incoming = hyper::server::accept::poll_fn(move |cx| match listener.poll_accept(cx) {
+            Poll::Ready(result) => Poll::Ready(Some(result.map(|(stream, _)| stream))),
+            Poll::Pending => Poll::Pending,
+        });
+
+        let result = hyper::Server::builder(incoming)
+            .serve(app.into_make_service_with_connect_info::<Peer>())
+            .with_graceful_shutdown(shutdown)
+            .await;
+
+        let _ = tokio::fs::remove_file(&path).await;
+
+        Ok(result?)
+    }
+}
@@ -1,29 +1,60 @@
 mod handlers;
 
 mod auth;
+mod cache;
+mod cli;
+mod config;
 mod database;
 mod error;
+mod jobs;
+mod listen;
+mod media;
 mod models;
+mod pagination;
+mod proxy;
+mod ratelimit;
 mod response;
+mod search;
+mod signing;
+mod tls;
 
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+};
 
-use axum::{Extension, Router};
-use axum_extra::routing::SpaRouter;
-use axum_server::{tls_rustls::RustlsConfig, Handle};
+use axum::{
+    body::Body, handler::Handler as _, response::Redirect, routing::get, Extension, Router,
+};
+use axum_server::Handle;
 use clap::Parser as _;
-use sqlx::SqlitePool;
-use tokio::{fs::File, io::AsyncWriteExt as _};
+use futures_util::{future::BoxFuture, FutureExt as _, TryFutureExt as _};
+use http::Request;
+use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
 use tower_cookies::CookieManagerLayer;
-use tower_http::{compression::CompressionLayer, trace::TraceLayer};
+use tower_http::{
+    compression::{predicate::Predicate as _, CompressionLayer, DefaultPredicate},
+    trace::TraceLayer,
+};
 use tracing_subscriber::{layer::SubscriberExt as _, util::SubscriberInitExt as _};
 
-use crate::error::Error;
+use crate::{
+    auth::Client,
+    cache::Cache,
+    config::{Config, ListenerConfig},
+    error::Error,
+    jobs::Jobs,
+    listen::{ListenAddr, ListenerTls, Peer},
+    ratelimit::RateLimiter,
+    signing::Signer,
+};
 
 const SESSION: &str = "hawk-session";
 const MIGRATIONS: sqlx::migrate::Migrator = sqlx::migrate!();
 
 static AXIOS_JS: &str = include_str!("../node_modules/axios/dist/axios.min.js");
+static HLS_JS: &str = include_str!("../node_modules/hls.js/dist/hls.min.js");
 static STYLE_CSS: &str = include_str!("../assets/style.css");
 
 // convert images to webp: (for %i in (*.png) do ffmpeg -i %i %~ni.webp)
@@ -31,22 +62,135 @@ static STYLE_CSS: &str = include_str!("../assets/style.css");
 #[derive(clap::Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    /// Path of the TOML config file
+    #[clap(short, long, global = true, value_parser, env = "HAWK_CONFIG")]
+    config: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Run the web server
+    Serve(ServeArgs),
+
+    /// Manage user accounts
+    User {
+        #[clap(subcommand)]
+        command: cli::UserCommand,
+    },
+
+    /// Manage API tokens
+    Token {
+        #[clap(subcommand)]
+        command: cli::TokenCommand,
+    },
+
+    /// Inspect the configuration
+    Config {
+        #[clap(subcommand)]
+        command: cli::ConfigCommand,
+    },
+}
+
+/// Overrides for the config file, unset flags keep the configured value.
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
     /// The host the server should bind to
-    #[clap(short, long, value_parser, default_value = "0.0.0.0")]
-    host: String,
+    #[clap(short = 'H', long, value_parser)]
+    host: Option<IpAddr>,
 
     /// The port the server should listen to
-    #[clap(short, long, value_parser, default_value_t = 25575)]
-    port: u16,
+    #[clap(short, long, value_parser)]
+    port: Option<u16>,
+
+    /// Listen on another address, `host:port` or `unix:/path`, can be repeated
+    #[clap(short, long, value_parser)]
+    listen: Vec<ListenAddr>,
+
+    /// Serve plain HTTP, for running behind a reverse proxy that terminates TLS
+    #[clap(long, value_parser)]
+    no_tls: bool,
+
+    /// Also listen for plain HTTP on this port and redirect it to HTTPS
+    #[clap(long, value_parser)]
+    redirect_port: Option<u16>,
+
+    /// Also encode uploads into a VP9/Opus WebM rendition
+    #[clap(long, value_parser)]
+    webm: bool,
+
+    /// Also package uploads for adaptive streaming over HLS
+    #[clap(long, value_parser)]
+    hls: bool,
+
+    /// How many media processing jobs may run at the same time
+    #[clap(long, value_parser)]
+    workers: Option<usize>,
+
+    /// How many times a failed media processing job is attempted before giving up
+    #[clap(long, value_parser)]
+    max_attempts: Option<i64>,
+}
+
+impl ServeArgs {
+    fn apply(&self, config: &mut Config) {
+        if let Some(host) = self.host {
+            config.server.host = host;
+        }
+        if let Some(port) = self.port {
+            config.server.port = port;
+        }
+        if !self.listen.is_empty() {
+            config.server.listeners = self
+                .listen
+                .iter()
+                .map(|address| ListenerConfig {
+                    address: address.clone(),
+                    tls: None,
+                })
+                .collect();
+        }
+        if self.no_tls {
+            config.server.tls = false;
+        }
+        if let Some(redirect_port) = self.redirect_port {
+            config.server.redirect_port = Some(redirect_port);
+        }
+        if self.webm {
+            config.media.webm = true;
+        }
+        if self.hls {
+            config.media.hls = true;
+        }
+        if let Some(workers) = self.workers {
+            config.media.workers = workers;
+        }
+        if let Some(max_attempts) = self.max_attempts {
+            config.media.max_attempts = max_attempts;
+        }
+    }
 }
 
 fn main() -> Result<(), Error> {
     let args = Args::parse();
 
+    let mut config = Config::load(args.config.as_deref())?;
+    if let Command::Serve(serve) = &args.command {
+        serve.apply(&mut config);
+    }
+    config.validate()?;
+
+    // keep the output of the management commands readable
+    let default_filter = match args.command {
+        Command::Serve(_) => config.log.as_str(),
+        Command::User { .. } | Command::Token { .. } | Command::Config { .. } => "warn",
+    };
+
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG")
-                .unwrap_or_else(|_| "hawk=debug,sqlx=debug,tower_http=debug".into()),
+            std::env::var("RUST_LOG").unwrap_or_else(|_| default_filter.into()),
         ))
         .with(tracing_subscriber::fmt::layer())
         .init();
@@ -55,61 +199,182 @@ fn main() -> Result<(), Error> {
         .enable_all()
         .build()
         .expect("Failed to build Tokio runtime")
-        .block_on(entry(args))?;
+        .block_on(entry(args.command, config))?;
 
     Ok(())
 }
 
-async fn entry(args: Args) -> Result<(), Error> {
-    let db = std::env::current_dir()?.join("hawk.db");
-    if !db.exists() {
-        let mut db = File::create(&db).await?;
-        db.flush().await?;
+async fn entry(command: Command, config: Config) -> Result<(), Error> {
+    if let Command::Config { command } = command {
+        return cli::config(&config, command);
     }
 
-    let pool = SqlitePool::connect("sqlite://hawk.db").await?;
+    let options = SqliteConnectOptions::new()
+        .filename(&config.paths.database)
+        .create_if_missing(true);
+
+    let pool = SqlitePool::connect_with(options).await?;
 
     MIGRATIONS.run(&pool).await?;
 
-    // {
-    //     let id = Uuid::new_v4();
-    //     let hash = bcrypt::hash("******", bcrypt::DEFAULT_COST)?;
-    //     sqlx::query!("INSERT INTO users(id, username, hash) VALUES (?, ?, ?)", id, "******", hash).execute(&pool).await?;
-    // }
+    let result = match command {
+        Command::Serve(_) => serve(pool.clone(), Arc::new(config)).await,
+        Command::User { command } => cli::user(&pool, command).await,
+        Command::Token { command } => cli::token(&pool, command).await,
+        // handled above, it doesn't need the database
+        Command::Config { .. } => Ok(()),
+    };
 
+    pool.close().await;
+
+    result
+}
+
+async fn serve(pool: SqlitePool, config: Arc<Config>) -> Result<(), Error> {
+    for dir in [
+        &config.paths.videos,
+        &config.paths.images,
+        &config.paths.hls,
+        &config.paths.staging,
+    ] {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    tokio::spawn(handlers::purge_expired_uploads(
+        pool.clone(),
+        config.clone(),
+    ));
+    tokio::spawn(handlers::purge_expired_sessions(pool.clone()));
+
+    // TCP listeners stop through the handle, unix sockets through the channel
     let handle = Handle::new();
-    tokio::spawn(graceful_shutdown(handle.clone()));
+    let (shutdown, shutdown_rx) = tokio::sync::watch::channel(());
+    tokio::spawn(graceful_shutdown(handle.clone(), shutdown));
+
+    let cache = Cache::new();
+
+    let jobs = Jobs::start(pool.clone(), cache.clone(), config.clone()).await?;
+
+    let signer = Signer::load_or_create(&pool, config.server.base_path.clone()).await?;
+
+    let routes = match config.server.base_path.as_str() {
+        "" => handlers::routes(),
+        // the nested index only matches the base path without a trailing slash
+        base_path => {
+            let index = base_path.to_string();
+
+            Router::new()
+                .route(
+                    &format!("{}/", base_path),
+                    get(move || async move { Redirect::permanent(&index) }),
+                )
+                .nest(base_path, handlers::routes())
+        }
+    }
+    .fallback(handlers::fallback.into_service());
 
-    let config = RustlsConfig::from_pem_file("cert.pem", "key.pem")
-        .await
-        .unwrap();
+    let trusted = config.server.trusted_proxies.clone();
+    let make_span = move |req: &Request<Body>| {
+        let client = Client::new(req.extensions(), req.headers(), &trusted);
 
-    let app = Router::new()
-        .merge(handlers::routes())
-        .merge(SpaRouter::new("/assets/video", "./assets/video"))
+        tracing::debug_span!(
+            "request",
+            method = %req.method(),
+            uri = %req.uri(),
+            version = ?req.version(),
+            client = client.ip.as_deref().unwrap_or("-"),
+        )
+    };
+
+    let app = routes
         .layer(Extension(pool.clone()))
-        .layer(TraceLayer::new_for_http())
+        .layer(Extension(cache))
+        .layer(Extension(jobs))
+        .layer(Extension(signer))
+        .layer(Extension(RateLimiter::new()))
+        .layer(Extension(handlers::UploadLocks::new()))
+        .layer(Extension(config.clone()))
+        .layer(TraceLayer::new_for_http().make_span_with(make_span))
         .layer(CookieManagerLayer::new())
-        .layer(CompressionLayer::new());
+        .layer(
+            CompressionLayer::new()
+                .compress_when(DefaultPredicate::new().and(response::is_compressible)),
+        );
+
+    let primary = ListenerConfig {
+        address: ListenAddr::Tcp(SocketAddr::new(config.server.host, config.server.port)),
+        tls: Some(config.server.tls),
+    };
+    let listeners = std::iter::once(&primary)
+        .chain(&config.server.listeners)
+        .collect::<Vec<_>>();
 
-    let addr = args.host.parse::<Ipv4Addr>()?;
+    let tls = if listeners
+        .iter()
+        .any(|listener| listener.tls(&config.server))
+    {
+        let tls = tls::load(&config).await?;
+        tls::spawn_reload(tls.clone(), config.clone());
 
-    let addr = SocketAddr::from(SocketAddrV4::new(addr, args.port));
-    tracing::info!("listening on {}", addr);
-    axum_server::bind_rustls(addr, config)
-        .handle(handle)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+        Some(tls)
+    } else {
+        None
+    };
 
-    pool.close().await;
+    let mut servers: Vec<BoxFuture<'static, Result<(), Error>>> = Vec::new();
+
+    for listener in listeners {
+        let app = app
+            .clone()
+            .layer(Extension(ListenerTls(listener.tls(&config.server))));
+
+        match (&listener.address, &tls) {
+            (ListenAddr::Tcp(addr), Some(tls)) if listener.tls(&config.server) => {
+                tracing::info!("listening on https://{}", addr);
+                let server = axum_server::bind_rustls(*addr, tls.clone())
+                    .handle(handle.clone())
+                    .serve(app.into_make_service_with_connect_info::<Peer>());
+                servers.push(server.err_into().boxed());
+            }
+            (ListenAddr::Tcp(addr), _) => {
+                tracing::info!("listening on http://{}", addr);
+                let server = axum_server::bind(*addr)
+                    .handle(handle.clone())
+                    .serve(app.into_make_service_with_connect_info::<Peer>());
+                servers.push(server.err_into().boxed());
+            }
+            #[cfg(unix)]
+            (ListenAddr::Unix(path), _) => {
+                tracing::info!("listening on unix:{}", path.display());
+                let mut shutdown = shutdown_rx.clone();
+                let server = listen::serve_unix(path.clone(), app, async move {
+                    let _ = shutdown.changed().await;
+                });
+                servers.push(server.boxed());
+            }
+            // rejected when the config is validated
+            #[cfg(not(unix))]
+            (ListenAddr::Unix(_), _) => {}
+        }
+    }
+
+    if let Some(port) = config.server.redirect_port {
+        let addr = SocketAddr::new(config.server.host, port);
+        tracing::info!("redirecting http://{} to https", addr);
+        let server = axum_server::bind(addr)
+            .handle(handle.clone())
+            .serve(tls::redirect_routes(config.server.port).into_make_service());
+        servers.push(server.err_into().boxed());
+    }
+
+    futures_util::future::try_join_all(servers).await?;
 
     tracing::info!("graceful shutdown complete");
 
     Ok(())
 }
 
-async fn graceful_shutdown(handle: Handle) {
+async fn graceful_shutdown(handle: Handle, shutdown: tokio::sync::watch::Sender<()>) {
     let ctrl_c = async {
         tokio::signal::ctrl_c()
             .await
@@ -118,7 +383,7 @@ async fn graceful_shutdown(handle: Handle) {
 
     #[cfg(unix)]
     let terminate = async {
-        tokio::signal::unix::signal(signal::unix::SignalKind::terminate())
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
             .expect("failed to install signal handler")
             .recv()
             .await;
@@ -135,4 +400,5 @@ async fn graceful_shutdown(handle: Handle) {
     tracing::info!("signal received, starting graceful shutdown");
 
     handle.graceful_shutdown(None);
+    let _ = shutdown.send(());
 }
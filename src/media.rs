@@ -0,0 +1,465 @@
+use std::{
+    io::{Cursor, Write as _},
+    ops::DerefMut as _,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use infer::MatcherType;
+use tokio::{fs::File, io::AsyncReadExt as _, process::Command};
+use uuid::Uuid;
+
+use crate::{config::Config, error::Error};
+
+/// A browser-safe encoding of an uploaded video.
+///
+/// The original upload is always kept, renditions are stored next to it as
+/// `{id}-{name}.{ext}`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Rendition {
+    /// H.264/AAC in an MP4 container, playable by every browser.
+    H264,
+    /// VP9/Opus in a WebM container.
+    Vp9,
+}
+
+impl Rendition {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Rendition::H264 => "h264",
+            Rendition::Vp9 => "vp9",
+        }
+    }
+
+    pub(crate) fn ext(self) -> &'static str {
+        match self {
+            Rendition::H264 => "mp4",
+            Rendition::Vp9 => "webm",
+        }
+    }
+
+    pub(crate) fn file_name(self, id: &Uuid) -> String {
+        format!("{}-{}.{}", id, self.name(), self.ext())
+    }
+
+    fn args(self) -> &'static [&'static str] {
+        match self {
+            Rendition::H264 => &[
+                "-c:v",
+                "libx264",
+                "-preset",
+                "medium",
+                "-crf",
+                "23",
+                "-profile:v",
+                "high",
+                "-pix_fmt",
+                "yuv420p",
+                "-c:a",
+                "aac",
+                "-b:a",
+                "160k",
+                "-movflags",
+                "+faststart",
+                "-f",
+                "mp4",
+            ],
+            Rendition::Vp9 => &[
+                "-c:v",
+                "libvpx-vp9",
+                "-crf",
+                "32",
+                "-b:v",
+                "0",
+                "-row-mt",
+                "1",
+                "-pix_fmt",
+                "yuv420p",
+                "-c:a",
+                "libopus",
+                "-b:a",
+                "128k",
+                "-f",
+                "webm",
+            ],
+        }
+    }
+}
+
+/// A single variant of the HLS resolution ladder.
+struct Rung {
+    name: &'static str,
+    height: u32,
+    /// Video bitrate in kbit/s.
+    bitrate: u32,
+}
+
+const HLS_LADDER: &[Rung] = &[
+    Rung {
+        name: "1080p",
+        height: 1080,
+        bitrate: 5000,
+    },
+    Rung {
+        name: "720p",
+        height: 720,
+        bitrate: 2800,
+    },
+    Rung {
+        name: "480p",
+        height: 480,
+        bitrate: 1400,
+    },
+];
+
+/// Audio bitrate of every HLS variant in kbit/s.
+const HLS_AUDIO_BITRATE: u32 = 128;
+
+/// Length of a single HLS segment in seconds.
+const HLS_SEGMENT_SECONDS: u32 = 6;
+
+#[tracing::instrument(skip(path), fields(path = %path.as_ref().display()), err)]
+pub(crate) async fn get_type<P: AsRef<Path>>(path: P) -> Result<infer::Type, Error> {
+    let file = File::open(&path).await?;
+
+    let limit = file
+        .metadata()
+        .await
+        .map(|m| std::cmp::min(m.len(), 8192) as usize + 1)
+        .unwrap_or(0);
+
+    let mut bytes = Vec::with_capacity(limit);
+    file.take(limit as u64).read_to_end(&mut bytes).await?;
+
+    let typ = infer::get(&bytes).ok_or(Error::Infer(
+        "unable to figure out mime type, buf may be empty",
+    ))?;
+
+    if typ.matcher_type() != MatcherType::Video {
+        return Err(Error::InvalidFileType);
+    }
+
+    Ok(typ)
+}
+
+/// Technical metadata of a video file as reported by ffprobe.
+#[derive(Debug, Default)]
+pub(crate) struct Probe {
+    /// Duration in seconds.
+    pub duration: Option<f64>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    /// Overall bitrate in bit/s.
+    pub bitrate: Option<i64>,
+    /// File size in bytes.
+    pub size: Option<i64>,
+}
+
+#[derive(serde::Deserialize)]
+struct FfprobeOutput {
+    #[serde(default)]
+    streams: Vec<FfprobeStream>,
+    format: Option<FfprobeFormat>,
+}
+
+#[derive(serde::Deserialize)]
+struct FfprobeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    width: Option<i64>,
+    height: Option<i64>,
+}
+
+/// ffprobe prints most of the numeric format fields as strings.
+#[derive(serde::Deserialize)]
+struct FfprobeFormat {
+    duration: Option<String>,
+    bit_rate: Option<String>,
+    size: Option<String>,
+}
+
+#[tracing::instrument(skip(config, path), fields(path = %path.as_ref().display()), err)]
+pub(crate) async fn probe<P: AsRef<Path>>(config: &Config, path: P) -> Result<Probe, Error> {
+    let output = Command::new(&config.media.ffprobe)
+        .args([
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+            path.as_ref().as_os_str().to_str().unwrap(),
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(Error::Ffmpeg(
+            String::from_utf8_lossy(&output.stderr[..]).to_string(),
+        ));
+    }
+
+    let output: FfprobeOutput = serde_json::from_slice(&output.stdout)?;
+
+    let mut probe = Probe::default();
+
+    let video = output
+        .streams
+        .iter()
+        .find(|stream| stream.codec_type.as_deref() == Some("video"));
+    if let Some(video) = video {
+        probe.width = video.width;
+        probe.height = video.height;
+        probe.video_codec = video.codec_name.clone();
+    }
+
+    probe.audio_codec = output
+        .streams
+        .iter()
+        .find(|stream| stream.codec_type.as_deref() == Some("audio"))
+        .and_then(|stream| stream.codec_name.clone());
+
+    if let Some(format) = output.format {
+        probe.duration = format.duration.and_then(|d| d.parse().ok());
+        probe.bitrate = format.bit_rate.and_then(|b| b.parse().ok());
+        probe.size = format.size.and_then(|s| s.parse().ok());
+    }
+
+    Ok(probe)
+}
+
+#[tracing::instrument(skip(config, path), fields(path = %path.as_ref().display()), err)]
+pub(crate) async fn generate_thumbnail<P: AsRef<Path>>(
+    config: &Config,
+    id: &Uuid,
+    path: P,
+) -> Result<(), Error> {
+    let bytes = get_webp_frame(config, path, 16).await?;
+
+    let img =
+        image::io::Reader::with_format(Cursor::new(bytes), image::ImageFormat::WebP).decode()?;
+    let img = img.thumbnail(config.media.thumbnail_width, config.media.thumbnail_height);
+
+    let path = config.paths.images.join(format!("{}.webp", id));
+
+    tokio::task::spawn_blocking(move || -> Result<(), Error> {
+        let image = webp::Encoder::from_image(&img).map_err(|err| Error::Webp(err.to_string()))?;
+        let mut mem = image.encode(75.0);
+
+        let mut file = std::fs::File::create(&path)?;
+        file.write_all(mem.deref_mut())?;
+
+        Ok(())
+    })
+    .await??;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(config, path, index), err)]
+async fn get_webp_frame<P: AsRef<Path>>(
+    config: &Config,
+    path: P,
+    index: usize,
+) -> Result<Vec<u8>, Error> {
+    let child = Command::new(&config.media.ffmpeg)
+        .args([
+            "-loglevel",
+            "panic",
+            "-i",
+            path.as_ref().as_os_str().to_str().unwrap(),
+            "-vf",
+            format!("select=eq(n\\,{})", index).as_str(),
+            "-vframes",
+            "1",
+            "-c:v",
+            "webp",
+            "-movflags",
+            "empty_moov",
+            "-f",
+            "image2pipe",
+            "pipe:1",
+        ])
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let output = child.wait_with_output().await?;
+    if output.status.success() && !output.stdout.is_empty() {
+        Ok(output.stdout)
+    } else {
+        Err(Error::Ffmpeg(
+            String::from_utf8_lossy(&output.stderr[..]).to_string(),
+        ))
+    }
+}
+
+/// Encodes `path` into the given rendition, returning the path of the new file.
+///
+/// ffmpeg writes into a temporary file which is only renamed into place once
+/// the encode has succeeded, so a half finished rendition is never served.
+#[tracing::instrument(skip(config, path), fields(path = %path.as_ref().display()), err)]
+pub(crate) async fn transcode<P: AsRef<Path>>(
+    config: &Config,
+    id: &Uuid,
+    path: P,
+    rendition: Rendition,
+) -> Result<PathBuf, Error> {
+    let dir = &config.paths.videos;
+
+    let tmp_path = dir.join(format!("{}.part", rendition.file_name(id)));
+    let out_path = dir.join(rendition.file_name(id));
+
+    let output = Command::new(&config.media.ffmpeg)
+        .args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-i",
+            path.as_ref().as_os_str().to_str().unwrap(),
+            "-map",
+            "0:v:0",
+            "-map",
+            "0:a:0?",
+        ])
+        .args(rendition.args())
+        .arg(&tmp_path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+
+        return Err(Error::Ffmpeg(
+            String::from_utf8_lossy(&output.stderr[..]).to_string(),
+        ));
+    }
+
+    tokio::fs::rename(&tmp_path, &out_path).await?;
+
+    Ok(out_path)
+}
+
+/// Packages `path` into fMP4 HLS segments for every rung of the ladder, along
+/// with a master playlist at `{hls}/{id}/master.m3u8`.
+///
+/// Rungs taller than `height` (when known) are skipped, apart from the
+/// smallest one, so low resolution uploads aren't upscaled.
+///
+/// Like [`transcode`] everything is written into a temporary directory first,
+/// the player only ever sees a complete set of playlists.
+#[tracing::instrument(skip(config, path), fields(path = %path.as_ref().display()), err)]
+pub(crate) async fn package_hls<P: AsRef<Path>>(
+    config: &Config,
+    id: &Uuid,
+    path: P,
+    height: Option<i64>,
+) -> Result<PathBuf, Error> {
+    let dir = &config.paths.hls;
+
+    let tmp_dir = dir.join(format!("{}.part", id));
+    let out_dir = dir.join(id.to_string());
+
+    if tmp_dir.exists() {
+        tokio::fs::remove_dir_all(&tmp_dir).await?;
+    }
+
+    let mut master = String::from("#EXTM3U\n#EXT-X-VERSION:7\n");
+
+    let rungs = HLS_LADDER
+        .iter()
+        .enumerate()
+        .filter(|(i, rung)| match height {
+            Some(height) => i64::from(rung.height) <= height || *i == HLS_LADDER.len() - 1,
+            None => true,
+        })
+        .map(|(_, rung)| rung);
+
+    for rung in rungs {
+        let rung_dir = tmp_dir.join(rung.name);
+        tokio::fs::create_dir_all(&rung_dir).await?;
+
+        let output = Command::new(&config.media.ffmpeg)
+            .args([
+                "-y",
+                "-loglevel",
+                "error",
+                "-i",
+                path.as_ref().as_os_str().to_str().unwrap(),
+                "-map",
+                "0:v:0",
+                "-map",
+                "0:a:0?",
+                "-vf",
+                format!("scale=-2:'min({},ih)'", rung.height).as_str(),
+                "-c:v",
+                "libx264",
+                "-preset",
+                "veryfast",
+                "-profile:v",
+                "main",
+                "-pix_fmt",
+                "yuv420p",
+                "-b:v",
+                format!("{}k", rung.bitrate).as_str(),
+                "-maxrate",
+                format!("{}k", rung.bitrate * 107 / 100).as_str(),
+                "-bufsize",
+                format!("{}k", rung.bitrate * 3 / 2).as_str(),
+                "-force_key_frames",
+                format!("expr:gte(t,n_forced*{})", HLS_SEGMENT_SECONDS).as_str(),
+                "-c:a",
+                "aac",
+                "-ac",
+                "2",
+                "-b:a",
+                format!("{}k", HLS_AUDIO_BITRATE).as_str(),
+                "-f",
+                "hls",
+                "-hls_time",
+                HLS_SEGMENT_SECONDS.to_string().as_str(),
+                "-hls_playlist_type",
+                "vod",
+                "-hls_segment_type",
+                "fmp4",
+                "-hls_fmp4_init_filename",
+                "init.mp4",
+                "-hls_segment_filename",
+            ])
+            .arg(rung_dir.join("%05d.m4s"))
+            .arg(rung_dir.join("index.m3u8"))
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let _ = tokio::fs::remove_dir_all(&tmp_dir).await;
+
+            return Err(Error::Ffmpeg(
+                String::from_utf8_lossy(&output.stderr[..]).to_string(),
+            ));
+        }
+
+        master.push_str(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},NAME=\"{}\"\n{}/index.m3u8\n",
+            (rung.bitrate + HLS_AUDIO_BITRATE) * 1000,
+            rung.name,
+            rung.name,
+        ));
+    }
+
+    tokio::fs::write(tmp_dir.join("master.m3u8"), master).await?;
+
+    if out_dir.exists() {
+        tokio::fs::remove_dir_all(&out_dir).await?;
+    }
+    tokio::fs::rename(&tmp_dir, &out_dir).await?;
+
+    Ok(out_dir.join("master.m3u8"))
+}
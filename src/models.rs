@@ -1,5 +1,7 @@
 use uuid::Uuid;
 
+use crate::{error::Error, response, search::Search};
+
 #[derive(serde::Deserialize)]
 pub(crate) struct Login {
     pub username: String,
@@ -7,7 +9,709 @@ pub(crate) struct Login {
     pub email: String,
 }
 
+/// What a user is allowed to do, each role includes everything the ones
+/// before it can do.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    sqlx::Type,
+    clap::ValueEnum,
+    serde::Deserialize,
+    serde::Serialize,
+    utoipa::ToSchema,
+)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Role {
+    /// Can browse and watch videos.
+    Viewer,
+    /// Can also upload videos.
+    Uploader,
+    /// Can also remove videos and manage sessions.
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Uploader => "uploader",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// What an API token may be used for, each scope unlocks what the role of the
+/// same rank can do, as long as the token's user has that role. Like roles,
+/// scopes include the ones below them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub(crate) enum Scope {
+    /// Browse and watch videos.
+    Read,
+    /// Upload videos.
+    Upload,
+    /// Everything admins can do.
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: &'static [Scope] = &[Scope::Read, Scope::Upload, Scope::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Upload => "upload",
+            Scope::Admin => "admin",
+        }
+    }
+
+    /// The role a user needs for the scope to be of any use.
+    pub fn role(self) -> Role {
+        match self {
+            Scope::Read => Role::Viewer,
+            Scope::Upload => Role::Uploader,
+            Scope::Admin => Role::Admin,
+        }
+    }
+
+    pub fn for_role(role: Role) -> Self {
+        match role {
+            Role::Viewer => Scope::Read,
+            Role::Uploader => Scope::Upload,
+            Role::Admin => Scope::Admin,
+        }
+    }
+
+    /// Parses the comma separated scopes stored with a token, unknown ones are
+    /// skipped.
+    pub fn parse_list(scopes: &str) -> Vec<Scope> {
+        scopes
+            .split(',')
+            .filter_map(|scope| {
+                Scope::ALL
+                    .iter()
+                    .copied()
+                    .find(|known| known.as_str() == scope.trim())
+            })
+            .collect()
+    }
+
+    pub fn join(scopes: &[Scope]) -> String {
+        scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// The user a session belongs to.
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub(crate) struct User {
+    pub id: Uuid,
+    pub username: String,
+    pub role: Role,
+}
+
+impl User {
+    pub fn can_upload(&self) -> bool {
+        self.role >= Role::Uploader
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role >= Role::Admin
+    }
+}
+
+/// Who can find and watch a video, its owner and admins always can.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    sqlx::Type,
+    serde::Deserialize,
+    serde::Serialize,
+    utoipa::ToSchema,
+    clap::ValueEnum,
+)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Visibility {
+    Private,
+    /// Watchable by anyone with the link, but not listed.
+    Unlisted,
+    /// Listed and watchable for the users it has been shared with.
+    Shared,
+    /// Listed for every user and watchable without signing in.
+    Public,
+}
+
+impl Visibility {
+    pub const ALL: &'static [Visibility] = &[
+        Visibility::Private,
+        Visibility::Unlisted,
+        Visibility::Shared,
+        Visibility::Public,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Private => "private",
+            Visibility::Unlisted => "unlisted",
+            Visibility::Shared => "shared",
+            Visibility::Public => "public",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Visibility::Private => "Private",
+            Visibility::Unlisted => "Unlisted",
+            Visibility::Shared => "Shared with users",
+            Visibility::Public => "Public",
+        }
+    }
+}
+
+/// The orders videos can be listed in.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Deserialize,
+    serde::Serialize,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Sort {
+    #[default]
+    Newest,
+    Oldest,
+    /// Alphabetically by title.
+    Title,
+    /// Longest first.
+    Duration,
+    /// Largest first.
+    Size,
+    /// Most viewed first.
+    Views,
+}
+
+impl Sort {
+    pub const ALL: &'static [Sort] = &[
+        Sort::Newest,
+        Sort::Oldest,
+        Sort::Title,
+        Sort::Duration,
+        Sort::Size,
+        Sort::Views,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Sort::Newest => "newest",
+            Sort::Oldest => "oldest",
+            Sort::Title => "title",
+            Sort::Duration => "duration",
+            Sort::Size => "size",
+            Sort::Views => "views",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Sort::Newest => "Newest",
+            Sort::Oldest => "Oldest",
+            Sort::Title => "Title",
+            Sort::Duration => "Longest",
+            Sort::Size => "Largest",
+            Sort::Views => "Most viewed",
+        }
+    }
+
+    pub fn parse(sort: &str) -> Option<Sort> {
+        Sort::ALL
+            .iter()
+            .copied()
+            .find(|known| known.as_str() == sort)
+    }
+}
+
+/// A user as listed for administration.
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub(crate) struct Account {
+    pub username: String,
+    pub role: Role,
+    pub created: Option<String>,
+    pub disabled: bool,
+    /// Number of sessions, including expired ones that haven't been purged yet.
+    pub sessions: i64,
+}
+
+/// One of a user's sessions, identified by its key rather than the token so it
+/// can be shown and revoked without exposing the token.
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub(crate) struct Session {
+    pub key: String,
+    /// Whether this is the session the request was made with.
+    pub current: bool,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created: Option<String>,
+    pub last_seen: Option<String>,
+}
+
+impl Session {
+    fn display(value: &Option<String>) -> &str {
+        value.as_deref().unwrap_or("unknown")
+    }
+
+    pub fn display_user_agent(&self) -> &str {
+        Self::display(&self.user_agent)
+    }
+
+    pub fn display_ip(&self) -> &str {
+        Self::display(&self.ip)
+    }
+
+    pub fn display_created(&self) -> &str {
+        Self::display(&self.created)
+    }
+
+    pub fn display_last_seen(&self) -> &str {
+        Self::display(&self.last_seen)
+    }
+}
+
+/// The user behind an API token, along with what the token allows.
+#[derive(Clone, Debug)]
+pub(crate) struct TokenGrant {
+    pub user: User,
+    pub scopes: Vec<Scope>,
+}
+
+/// An API token as listed for its owner, the token itself is only shown once
+/// when it's created.
+#[derive(Clone, Debug)]
+pub(crate) struct ApiToken {
+    pub key: String,
+    pub name: String,
+    /// Comma separated [`Scope`]s.
+    pub scopes: String,
+    pub created: Option<String>,
+    pub expires: Option<String>,
+    pub last_used: Option<String>,
+    pub expired: bool,
+}
+
+impl ApiToken {
+    pub fn display_scopes(&self) -> String {
+        self.scopes.replace(',', ", ")
+    }
+
+    pub fn display_created(&self) -> &str {
+        self.created.as_deref().unwrap_or("unknown")
+    }
+
+    pub fn display_expires(&self) -> &str {
+        self.expires.as_deref().unwrap_or("never")
+    }
+
+    pub fn display_last_used(&self) -> &str {
+        self.last_used.as_deref().unwrap_or("never")
+    }
+}
+
+#[derive(Clone, sqlx::FromRow)]
 pub(crate) struct Video {
     pub id: Uuid,
     pub ext: String,
+    /// `None` for videos uploaded before ownership was tracked.
+    pub owner_id: Option<Uuid>,
+    pub visibility: Visibility,
+    /// Duration in seconds.
+    pub duration: Option<f64>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    /// Overall bitrate in bit/s.
+    pub bitrate: Option<i64>,
+    /// File size in bytes.
+    pub size: Option<i64>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// When the video was recorded, as `YYYY-MM-DD`.
+    pub recorded: Option<String>,
+    /// When the video was uploaded, as `YYYY-MM-DD HH:MM:SS` in UTC.
+    pub created: String,
+    /// How often the video's page has been opened.
+    pub views: i64,
+}
+
+impl Video {
+    /// The title, or the ID for videos that don't have one.
+    pub fn display_title(&self) -> String {
+        match &self.title {
+            Some(title) => title.clone(),
+            None => self.id.to_string(),
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{}.{}", self.id, self.ext)
+    }
+
+    /// The day the video was recorded, or uploaded when that isn't known, as
+    /// `YYYY-MM-DD`.
+    pub fn date(&self) -> &str {
+        match &self.recorded {
+            Some(recorded) => recorded,
+            None => self.created.get(..10).unwrap_or(&self.created),
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        response::mime_for_ext(&self.ext)
+    }
+
+    pub fn is_owned_by(&self, user: &User) -> bool {
+        self.owner_id == Some(user.id)
+    }
+
+    /// Whether `user` may change the video, owners and admins can.
+    pub fn is_editable_by(&self, user: Option<&User>) -> bool {
+        user.is_some_and(|user| user.is_admin() || self.is_owned_by(user))
+    }
+
+    /// Whether `user` may watch the video, `shared` being whether the video has
+    /// been shared with them.
+    pub fn is_viewable_by(&self, user: Option<&User>, shared: bool) -> bool {
+        match self.visibility {
+            Visibility::Public | Visibility::Unlisted => true,
+            Visibility::Shared if shared => true,
+            _ => self.is_editable_by(user),
+        }
+    }
+
+    /// Whether the video shows up on `user`'s index.
+    pub fn is_listed_for(&self, user: Option<&User>, shared: bool) -> bool {
+        match self.visibility {
+            Visibility::Unlisted => self.is_editable_by(user),
+            _ => self.is_viewable_by(user, shared),
+        }
+    }
+
+    /// Duration as `h:mm:ss` or `m:ss`, empty until the video has been probed.
+    pub fn display_duration(&self) -> String {
+        let seconds = match self.duration {
+            Some(duration) => duration.round() as u64,
+            None => return String::new(),
+        };
+
+        let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+        if hours == 0 {
+            format!("{}:{:02}", minutes, seconds)
+        } else {
+            format!("{}:{:02}:{:02}", hours, minutes, seconds)
+        }
+    }
+
+    pub fn display_resolution(&self) -> String {
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => format!("{}x{}", width, height),
+            _ => String::new(),
+        }
+    }
+
+    pub fn display_size(&self) -> String {
+        const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+
+        let mut size = match self.size {
+            Some(size) => size as f64,
+            None => return String::new(),
+        };
+
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+
+    pub fn display_bitrate(&self) -> String {
+        match self.bitrate {
+            Some(bitrate) => format!("{} kbit/s", bitrate / 1000),
+            None => String::new(),
+        }
+    }
+}
+
+/// The details of a video its owner can edit, blank values are left out.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct VideoDetails {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub recorded: Option<String>,
+}
+
+impl VideoDetails {
+    const MAX_TITLE: usize = 200;
+    const MAX_DESCRIPTION: usize = 5000;
+
+    pub fn new(title: &str, description: &str, recorded: &str) -> Result<Self, Error> {
+        let title = non_empty(title);
+        if title
+            .as_ref()
+            .is_some_and(|title| title.chars().count() > Self::MAX_TITLE)
+        {
+            return Err(Error::Details("title is too long"));
+        }
+
+        let description = non_empty(description);
+        if description
+            .as_ref()
+            .is_some_and(|description| description.chars().count() > Self::MAX_DESCRIPTION)
+        {
+            return Err(Error::Details("description is too long"));
+        }
+
+        let recorded = match non_empty(recorded) {
+            Some(recorded) => Some(parse_date(&recorded).ok_or(Error::Details(
+                "recorded date must be formatted as YYYY-MM-DD",
+            ))?),
+            None => None,
+        };
+
+        Ok(VideoDetails {
+            title,
+            description,
+            recorded,
+        })
+    }
+
+    /// A title from the name of an uploaded file, without its directory or
+    /// extension.
+    pub fn title_from_file_name(file_name: &str) -> Option<String> {
+        let name = file_name.rsplit(['/', '\\']).next().unwrap_or(file_name);
+        let stem = match name.rsplit_once('.') {
+            Some((stem, _)) if !stem.is_empty() => stem,
+            _ => name,
+        };
+
+        non_empty(stem).map(|title| title.chars().take(Self::MAX_TITLE).collect())
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Checks that `value` is a valid `YYYY-MM-DD` date.
+pub(crate) fn parse_date(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+
+    let month = time::Month::try_from(month.parse::<u8>().ok()?).ok()?;
+    let date = time::Date::from_calendar_date(year.parse().ok()?, month, day.parse().ok()?).ok()?;
+
+    Some(format!(
+        "{:04}-{:02}-{:02}",
+        date.year(),
+        u8::from(date.month()),
+        date.day()
+    ))
+}
+
+/// An ordered list of videos, which only shows the videos a user can watch.
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub(crate) struct Playlist {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    /// Never `shared`.
+    pub visibility: Visibility,
+    pub created: String,
+    /// Number of videos, including those the user can't watch.
+    pub videos: i64,
+}
+
+impl Playlist {
+    pub const VISIBILITIES: &'static [Visibility] = &[
+        Visibility::Private,
+        Visibility::Unlisted,
+        Visibility::Public,
+    ];
+
+    const MAX_NAME: usize = 100;
+    const MAX_DESCRIPTION: usize = 5000;
+
+    /// Checks the details of a playlist, returning its name and description
+    /// trimmed.
+    pub fn validate(
+        name: &str,
+        description: &str,
+        visibility: Visibility,
+    ) -> Result<(String, Option<String>), Error> {
+        let name = non_empty(name).ok_or(Error::Playlist("name is missing"))?;
+        if name.chars().count() > Self::MAX_NAME {
+            return Err(Error::Playlist("name is too long"));
+        }
+
+        let description = non_empty(description);
+        if description
+            .as_ref()
+            .is_some_and(|description| description.chars().count() > Self::MAX_DESCRIPTION)
+        {
+            return Err(Error::Playlist("description is too long"));
+        }
+
+        if !Self::VISIBILITIES.contains(&visibility) {
+            return Err(Error::Playlist("playlists can't be shared with users"));
+        }
+
+        Ok((name, description))
+    }
+
+    /// Owners and admins can change a playlist.
+    pub fn is_editable_by(&self, user: Option<&User>) -> bool {
+        user.is_some_and(|user| user.is_admin() || self.owner_id == user.id)
+    }
+
+    pub fn is_viewable_by(&self, user: Option<&User>) -> bool {
+        match self.visibility {
+            Visibility::Public | Visibility::Unlisted => true,
+            _ => self.is_editable_by(user),
+        }
+    }
+
+    pub fn is_listed_for(&self, user: Option<&User>) -> bool {
+        match self.visibility {
+            Visibility::Public => true,
+            _ => self.is_editable_by(user),
+        }
+    }
+}
+
+/// A saved search, showing whichever videos match its query when it's viewed.
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub(crate) struct Collection {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub name: String,
+    /// In the syntax of the search page.
+    pub query: String,
+    /// Whether the collection is linked from the index.
+    pub pinned: bool,
+    pub created: String,
+}
+
+impl Collection {
+    const MAX_NAME: usize = 100;
+    const MAX_QUERY: usize = 1000;
+
+    /// Checks the details of a collection, returning its name and query
+    /// trimmed.
+    pub fn validate(name: &str, query: &str) -> Result<(String, String), Error> {
+        let name = non_empty(name).ok_or(Error::Collection("name is missing"))?;
+        if name.chars().count() > Self::MAX_NAME {
+            return Err(Error::Collection("name is too long"));
+        }
+
+        let query = non_empty(query).ok_or(Error::Collection("query is missing"))?;
+        if query.chars().count() > Self::MAX_QUERY {
+            return Err(Error::Collection("query is too long"));
+        }
+
+        if Search::parse(&query)?.is_empty() {
+            return Err(Error::Collection("query doesn't search for anything"));
+        }
+
+        Ok((name, query))
+    }
+}
+
+/// A tag along with the number of videos it's on.
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub(crate) struct Tag {
+    pub name: String,
+    pub videos: i64,
+}
+
+impl Tag {
+    const MAX_LENGTH: usize = 40;
+
+    /// Tags are lowercase and use dashes instead of spaces, anything besides
+    /// letters, digits, `-`, `_` and `.` is dropped.
+    pub fn normalize(name: &str) -> Option<String> {
+        let mut normalized = String::new();
+
+        for word in name.split_whitespace() {
+            if !normalized.is_empty() {
+                normalized.push('-');
+            }
+
+            normalized.extend(
+                word.chars()
+                    .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                    .flat_map(char::to_lowercase),
+            );
+        }
+
+        let normalized = normalized
+            .trim_matches('-')
+            .chars()
+            .take(Self::MAX_LENGTH)
+            .collect::<String>();
+
+        (!normalized.is_empty()).then_some(normalized)
+    }
+
+    /// Normalizes comma separated tags, skipping empty and repeated ones.
+    pub fn parse_list(names: &str) -> Vec<String> {
+        let mut tags = Vec::new();
+
+        for tag in names.split(',').filter_map(Tag::normalize) {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+
+        tags
+    }
+}
+
+pub(crate) struct Rendition {
+    pub name: String,
+    pub ext: String,
+}
+
+impl Rendition {
+    pub fn file_name(&self, id: &Uuid) -> String {
+        format!("{}-{}.{}", id, self.name, self.ext)
+    }
+
+    pub fn mime(&self) -> &'static str {
+        response::mime_for_ext(&self.ext)
+    }
 }
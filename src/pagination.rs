@@ -0,0 +1,614 @@
+//! Keyset pagination over video listings.
+//!
+//! A cursor holds the sort key and ID of the last video on a page, the next
+//! page starts right after it. Unlike offsets, pages don't shift when videos
+//! are uploaded or removed in the meantime.
+//!
+//! The keys are computed by SQLite, which only has to return one page, and
+//! cursors hold exactly what it compares them to.
+
+use sqlx::{sqlite::SqliteRow, QueryBuilder, Row as _, Sqlite};
+use uuid::Uuid;
+
+use crate::{
+    error::Error,
+    models::{Sort, Video, Visibility},
+    search::Search,
+};
+
+/// The title videos without one are shown with, their ID.
+const DISPLAY_TITLE: &str = "COALESCE(title, LOWER(SUBSTR(HEX(id), 1, 8) || '-' || SUBSTR(HEX(id), 9, 4) || '-' || SUBSTR(HEX(id), 13, 4) || '-' || SUBSTR(HEX(id), 17, 4) || '-' || SUBSTR(HEX(id), 21)))";
+
+#[derive(Clone, Debug, PartialEq)]
+enum Key {
+    Text(String),
+    Number(f64),
+}
+
+impl Sort {
+    /// The expression videos are ordered by, before their ID.
+    pub(crate) fn key_sql(self) -> String {
+        match self {
+            Sort::Newest | Sort::Oldest => "created".to_string(),
+            Sort::Title => format!("LOWER({})", DISPLAY_TITLE),
+            // videos that haven't been probed yet go last
+            Sort::Duration => "CAST(COALESCE(duration, -1) AS REAL)".to_string(),
+            Sort::Size => "CAST(COALESCE(size, -1) AS REAL)".to_string(),
+            Sort::Views => "CAST(views AS REAL)".to_string(),
+        }
+    }
+
+    fn key_is_text(self) -> bool {
+        matches!(self, Sort::Newest | Sort::Oldest | Sort::Title)
+    }
+
+    fn is_descending(self) -> bool {
+        matches!(
+            self,
+            Sort::Newest | Sort::Duration | Sort::Size | Sort::Views
+        )
+    }
+
+    /// `ASC` or `DESC`, for both the key and the ID.
+    pub(crate) fn direction_sql(self) -> &'static str {
+        if self.is_descending() {
+            "DESC"
+        } else {
+            "ASC"
+        }
+    }
+}
+
+/// Where a page ends, only valid with the sort it was created for.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Cursor {
+    sort: Sort,
+    key: Key,
+    id: Uuid,
+}
+
+impl Cursor {
+    /// The cursor after a row selected with the sort key as `sort_key`.
+    fn after(sort: Sort, row: &SqliteRow, id: Uuid) -> Result<Self, Error> {
+        let key = if sort.key_is_text() {
+            Key::Text(row.try_get("sort_key")?)
+        } else {
+            Key::Number(row.try_get("sort_key")?)
+        };
+
+        Ok(Cursor { sort, key, id })
+    }
+
+    /// Parses a cursor for a listing in `sort` order.
+    pub fn decode_for(cursor: &str, sort: Sort) -> Result<Self, Error> {
+        let cursor = Self::decode(cursor)?;
+
+        if cursor.sort != sort {
+            return Err(Error::InvalidCursor);
+        }
+
+        Ok(cursor)
+    }
+
+    /// Narrows a query down to the videos after the cursor.
+    pub(crate) fn push_condition(&self, query: &mut QueryBuilder<'_, Sqlite>) {
+        let comparison = if self.sort.is_descending() { "<" } else { ">" };
+
+        query.push(format_args!(
+            " AND ({}, id) {} (",
+            self.sort.key_sql(),
+            comparison
+        ));
+
+        match &self.key {
+            Key::Text(text) => query.push_bind(text.clone()),
+            Key::Number(number) => query.push_bind(*number),
+        };
+
+        query.push(", ").push_bind(self.id).push(")");
+    }
+
+    /// An opaque, URL safe string.
+    pub fn encode(&self) -> String {
+        let key = match &self.key {
+            Key::Text(text) => text.clone(),
+            Key::Number(number) => number.to_string(),
+        };
+
+        base64::encode_config(
+            format!("{}:{}:{}", self.sort.as_str(), self.id, key),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, Error> {
+        let decoded = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .ok_or(Error::InvalidCursor)?;
+
+        let mut parts = decoded.splitn(3, ':');
+        let (sort, id, key) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(sort), Some(id), Some(key)) => (sort, id, key),
+            _ => return Err(Error::InvalidCursor),
+        };
+
+        let sort = Sort::parse(sort).ok_or(Error::InvalidCursor)?;
+        let id = id.parse().map_err(|_| Error::InvalidCursor)?;
+
+        let key = if sort.key_is_text() {
+            Key::Text(key.to_string())
+        } else {
+            Key::Number(
+                key.parse::<f64>()
+                    .ok()
+                    .filter(|number| number.is_finite())
+                    .ok_or(Error::InvalidCursor)?,
+            )
+        };
+
+        Ok(Cursor { sort, key, id })
+    }
+}
+
+/// What a listing is narrowed down to, besides the videos its user may see.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct VideoFilter<'a> {
+    /// Videos that have every one of these tags.
+    pub tags: &'a [String],
+    pub visibility: Option<Visibility>,
+    pub owner_id: Option<Uuid>,
+    /// Only videos matching the search's terms and passing its filters.
+    pub search: Option<&'a Search>,
+}
+
+/// A page of videos.
+#[derive(Clone)]
+pub(crate) struct VideoPage {
+    pub videos: Vec<Video>,
+    /// The cursor for the next page, `None` on the last one.
+    pub next: Option<String>,
+    /// Number of videos on all pages together.
+    pub total: usize,
+}
+
+impl VideoPage {
+    /// Builds a page from up to `limit + 1` rows selected with their sort key
+    /// as `sort_key`, the extra row only tells that there's a next page.
+    pub(crate) fn from_rows(
+        rows: Vec<SqliteRow>,
+        sort: Sort,
+        limit: usize,
+        total: usize,
+    ) -> Result<Self, Error> {
+        let videos = rows
+            .iter()
+            .take(limit)
+            .map(<Video as sqlx::FromRow<SqliteRow>>::from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let next = match videos.last() {
+            Some(last) if rows.len() > limit => {
+                Some(Cursor::after(sort, &rows[videos.len() - 1], last.id)?)
+            }
+            _ => None,
+        };
+
+        Ok(VideoPage {
+            videos,
+            next: next.map(|cursor| cursor.encode()),
+            total,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+
+    fn cursor(sort: Sort, key: Key) -> Cursor {
+        Cursor {
+            sort,
+            key,
+            id: Uuid::new_v4(),
+        }
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        for cursor in [
+            cursor(Sort::Newest, Key::Text("2023-06-15 12:00:00".to_string())),
+            cursor(Sort::Oldest, Key::Text(String::new())),
+            // titles may contain the separator
+            cursor(Sort::Title, Key::Text("a: b:c".to_string())),
+            cursor(Sort::Duration, Key::Number(-1.0)),
+            cursor(Sort::Duration, Key::Number(0.1 + 0.2)),
+            cursor(Sort::Size, Key::Number(9_007_199_254_740_992.0)),
+            cursor(Sort::Views, Key::Number(0.0)),
+        ] {
+            let encoded = cursor.encode();
+
+            assert!(encoded
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+            assert_eq!(Cursor::decode(&encoded).unwrap(), cursor);
+            assert_eq!(Cursor::decode_for(&encoded, cursor.sort).unwrap(), cursor);
+        }
+    }
+
+    #[test]
+    fn cursors_only_fit_their_sort() {
+        let encoded = cursor(Sort::Views, Key::Number(3.0)).encode();
+
+        assert!(matches!(
+            Cursor::decode_for(&encoded, Sort::Size),
+            Err(Error::InvalidCursor)
+        ));
+    }
+
+    #[test]
+    fn garbage_cursors_are_rejected() {
+        let encode = |text: &str| base64::encode_config(text, base64::URL_SAFE_NO_PAD);
+        let id = Uuid::new_v4();
+
+        for garbage in [
+            String::new(),
+            "not base64!".to_string(),
+            base64::encode_config([0xff, 0xfe, 0xfd], base64::URL_SAFE_NO_PAD),
+            encode("newest"),
+            encode(&format!("newest:{}", id)),
+            encode(&format!("shortest:{}:1", id)),
+            encode("views:not-a-uuid:1"),
+            encode(&format!("views:{}:many", id)),
+            encode(&format!("views:{}:NaN", id)),
+            encode(&format!("views:{}:inf", id)),
+            encode(&format!("views:{}:", id)),
+        ] {
+            assert!(
+                matches!(Cursor::decode(&garbage), Err(Error::InvalidCursor)),
+                "{:?}",
+                garbage
+            );
+        }
+    }
+
+    #[test]
+    fn tampered_cursors_are_rejected_or_stay_well_formed() {
+        let encoded = cursor(Sort::Size, Key::Number(1024.0)).encode();
+
+        // flipping any character either breaks the cursor or yields one that
+        // is still only a sort, an ID and a key of the right type
+        for i in 0..encoded.len() {
+            let mut tampered = encoded.clone().into_bytes();
+            tampered[i] = if tampered[i] == b'A' { b'B' } else { b'A' };
+            let tampered = String::from_utf8(tampered).unwrap();
+
+            if let Ok(cursor) = Cursor::decode(&tampered) {
+                assert_eq!(
+                    matches!(cursor.key, Key::Number(_)),
+                    !cursor.sort.key_is_text()
+                );
+            }
+        }
+    }
+
+    struct Fixture {
+        id: Uuid,
+        owner: Option<Uuid>,
+        visibility: Visibility,
+        title: Option<&'static str>,
+        duration: Option<f64>,
+        size: Option<i64>,
+        views: i64,
+        created: &'static str,
+    }
+
+    async fn insert(pool: &sqlx::SqlitePool, videos: &[Fixture]) {
+        for video in videos {
+            sqlx::query(
+                "INSERT INTO videos(id, ext, owner_id, visibility, title, duration, size, views, created) VALUES (?, 'mp4', ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(video.id)
+            .bind(video.owner)
+            .bind(video.visibility)
+            .bind(video.title)
+            .bind(video.duration)
+            .bind(video.size)
+            .bind(video.views)
+            .bind(video.created)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+    }
+
+    fn public(
+        title: Option<&'static str>,
+        duration: Option<f64>,
+        size: Option<i64>,
+        views: i64,
+        created: &'static str,
+    ) -> Fixture {
+        Fixture {
+            id: Uuid::new_v4(),
+            owner: None,
+            visibility: Visibility::Public,
+            title,
+            duration,
+            size,
+            views,
+            created,
+        }
+    }
+
+    /// The order `sort` should produce, worked out without SQL.
+    fn expected(sort: Sort, videos: &[Fixture]) -> Vec<Uuid> {
+        let title = |video: &Fixture| match video.title {
+            Some(title) => title.to_lowercase(),
+            None => video.id.to_string(),
+        };
+
+        let mut sorted = videos.iter().collect::<Vec<_>>();
+        sorted.sort_by(|a, b| {
+            let ordering = match sort {
+                Sort::Newest | Sort::Oldest => a.created.cmp(b.created),
+                Sort::Title => title(a).cmp(&title(b)),
+                Sort::Duration => a
+                    .duration
+                    .unwrap_or(-1.0)
+                    .total_cmp(&b.duration.unwrap_or(-1.0)),
+                Sort::Size => a.size.unwrap_or(-1).cmp(&b.size.unwrap_or(-1)),
+                Sort::Views => a.views.cmp(&b.views),
+            }
+            .then_with(|| a.id.cmp(&b.id));
+
+            if sort.is_descending() {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+
+        sorted.into_iter().map(|video| video.id).collect()
+    }
+
+    #[tokio::test]
+    async fn pages_follow_each_other_in_every_sort() {
+        let pool = database::memory_pool().await;
+
+        // plenty of ties, which only the ID breaks
+        let videos = [
+            public(
+                Some("Beta"),
+                Some(12.5),
+                Some(300),
+                3,
+                "2023-01-02 00:00:00",
+            ),
+            public(Some("alpha"), None, None, 0, "2023-01-01 00:00:00"),
+            public(
+                Some("Gamma"),
+                Some(12.5),
+                Some(300),
+                3,
+                "2023-01-02 00:00:00",
+            ),
+            public(None, Some(1.0), Some(5), 10, "2023-01-03 00:00:00"),
+            public(Some("beta"), Some(90.0), None, 0, "2023-01-02 00:00:00"),
+            public(None, None, Some(5), 10, "2023-01-01 00:00:00"),
+            public(
+                Some("delta"),
+                Some(0.1),
+                Some(1 << 40),
+                1,
+                "2023-01-04 00:00:00",
+            ),
+        ];
+        insert(&pool, &videos).await;
+
+        for &sort in Sort::ALL {
+            let expected = expected(sort, &videos);
+
+            for limit in 1..=videos.len() + 1 {
+                let mut ids = Vec::new();
+                let mut cursor = None;
+
+                loop {
+                    let page = database::db_get_video_page(
+                        &pool,
+                        None,
+                        &VideoFilter::default(),
+                        sort,
+                        cursor.as_deref(),
+                        0,
+                        limit,
+                    )
+                    .await
+                    .unwrap();
+
+                    assert_eq!(page.total, videos.len());
+                    assert!(page.videos.len() <= limit);
+                    ids.extend(page.videos.iter().map(|video| video.id));
+
+                    match page.next {
+                        Some(next) => cursor = Some(next),
+                        None => break,
+                    }
+                }
+
+                assert_eq!(ids, expected, "{:?} by {}", sort, limit);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_only_hold_listed_and_matching_videos() {
+        let pool = database::memory_pool().await;
+
+        let viewer = crate::models::User {
+            id: Uuid::new_v4(),
+            username: "viewer".to_string(),
+            role: crate::models::Role::Viewer,
+        };
+        let admin = crate::models::User {
+            id: Uuid::new_v4(),
+            username: "admin".to_string(),
+            role: crate::models::Role::Admin,
+        };
+
+        let with = |visibility, owner| Fixture {
+            visibility,
+            owner,
+            ..public(None, None, None, 0, "2023-01-01 00:00:00")
+        };
+        let videos = [
+            with(Visibility::Public, None),
+            with(Visibility::Unlisted, None),
+            with(Visibility::Unlisted, Some(viewer.id)),
+            with(Visibility::Private, Some(viewer.id)),
+            with(Visibility::Private, None),
+            with(Visibility::Shared, None),
+            with(Visibility::Shared, None),
+        ];
+        insert(&pool, &videos).await;
+
+        sqlx::query("INSERT INTO video_shares(video_id, user_id) VALUES (?, ?)")
+            .bind(videos[5].id)
+            .bind(viewer.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        sqlx::query("INSERT INTO tags(id, name) VALUES (1, 'cats'), (2, 'dogs')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        for (video, tag) in [(0, 1), (0, 2), (2, 1), (3, 2)] {
+            sqlx::query("INSERT INTO video_tags(video_id, tag_id) VALUES (?, ?)")
+                .bind(videos[video].id)
+                .bind(tag)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let listed = |user, filter| {
+            let pool = pool.clone();
+
+            async move {
+                let mut ids =
+                    database::db_get_video_page(&pool, user, &filter, Sort::Oldest, None, 0, 100)
+                        .await
+                        .unwrap()
+                        .videos
+                        .into_iter()
+                        .map(|video| video.id)
+                        .collect::<Vec<_>>();
+                ids.sort();
+                ids
+            }
+        };
+        let ids = |indices: &[usize]| {
+            let mut ids = indices.iter().map(|&i| videos[i].id).collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(listed(None, VideoFilter::default()).await, ids(&[0]));
+        assert_eq!(
+            listed(Some(&viewer), VideoFilter::default()).await,
+            ids(&[0, 2, 3, 5])
+        );
+        assert_eq!(
+            listed(Some(&admin), VideoFilter::default()).await,
+            ids(&[0, 1, 2, 3, 4, 5, 6])
+        );
+
+        let cats = ["cats".to_string()];
+        let both = ["cats".to_string(), "dogs".to_string(), "cats".to_string()];
+        let unknown = [String::new()];
+        let tagged = |tags| VideoFilter {
+            tags,
+            ..Default::default()
+        };
+        assert_eq!(listed(Some(&admin), tagged(&cats)).await, ids(&[0, 2]));
+        assert_eq!(listed(Some(&admin), tagged(&both)).await, ids(&[0]));
+        assert_eq!(listed(Some(&admin), tagged(&unknown)).await, ids(&[]));
+
+        let filter = VideoFilter {
+            visibility: Some(Visibility::Private),
+            owner_id: Some(viewer.id),
+            ..Default::default()
+        };
+        assert_eq!(listed(Some(&admin), filter).await, ids(&[3]));
+
+        for video in [1, 3, 4] {
+            sqlx::query("UPDATE videos SET title = 'needle' WHERE id = ?")
+                .bind(videos[video].id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let search = Search::parse("need tag:dogs").unwrap();
+        let filter = VideoFilter {
+            search: Some(&search),
+            ..Default::default()
+        };
+        assert_eq!(listed(Some(&viewer), filter).await, ids(&[3]));
+        assert_eq!(listed(Some(&admin), filter).await, ids(&[3]));
+    }
+
+    #[tokio::test]
+    async fn offsets_skip_after_the_cursor() {
+        let pool = database::memory_pool().await;
+
+        let videos = (0..5)
+            .map(|views| public(None, None, None, views, "2023-01-01 00:00:00"))
+            .collect::<Vec<_>>();
+        insert(&pool, &videos).await;
+
+        let page = |cursor: Option<String>, offset| {
+            let pool = pool.clone();
+
+            async move {
+                database::db_get_video_page(
+                    &pool,
+                    None,
+                    &VideoFilter::default(),
+                    Sort::Views,
+                    cursor.as_deref(),
+                    offset,
+                    2,
+                )
+                .await
+                .unwrap()
+            }
+        };
+
+        let first = page(None, 0).await;
+        let skipped = page(first.next.clone(), 1).await;
+
+        let views = skipped
+            .videos
+            .iter()
+            .map(|video| video.views)
+            .collect::<Vec<_>>();
+        assert_eq!(views, [1, 0]);
+        assert_eq!(skipped.next, None);
+
+        assert!(matches!(
+            database::db_get_video_page(
+                &pool,
+                None,
+                &VideoFilter::default(),
+                Sort::Size,
+                first.next.as_deref(),
+                0,
+                2,
+            )
+            .await,
+            Err(Error::InvalidCursor)
+        ));
+    }
+}
@@ -0,0 +1,316 @@
+//! Working out the original client behind reverse proxies.
+//!
+//! `Forwarded`, `X-Forwarded-For` and `X-Forwarded-Proto` are only looked at
+//! when the connection comes from a trusted proxy, otherwise anyone could
+//! claim to be anywhere.
+
+use std::net::IpAddr;
+
+use http::{header, HeaderMap};
+use ipnet::IpNet;
+
+use crate::listen::Peer;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// The client as seen by the first proxy in front of hawk.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Origin {
+    pub ip: Option<IpAddr>,
+    pub https: bool,
+}
+
+/// Resolves the origin of a request received from `peer` over a connection
+/// that is `https` or not.
+///
+/// The forwarded addresses are walked from the nearest proxy outwards, the
+/// first address that isn't a trusted proxy is the client. Unix sockets are
+/// only reachable from the same machine and always trusted.
+pub(crate) fn resolve(peer: Peer, https: bool, headers: &HeaderMap, trusted: &[IpNet]) -> Origin {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(&ip.to_canonical()));
+
+    let peer_ip = match peer {
+        Peer::Tcp(addr) => Some(addr.ip().to_canonical()),
+        Peer::Unix => None,
+    };
+
+    let proxied = match peer_ip {
+        Some(ip) => is_trusted(&ip),
+        None => true,
+    };
+
+    if !proxied {
+        return Origin { ip: peer_ip, https };
+    }
+
+    let forwarded = forwarded(headers);
+
+    let chain = if forwarded.is_empty() {
+        values(headers, X_FORWARDED_FOR)
+            .into_iter()
+            .map(parse_node)
+            .collect::<Vec<_>>()
+    } else {
+        forwarded.iter().map(|element| element.for_).collect()
+    };
+
+    let mut ip = peer_ip;
+    for hop in chain.into_iter().rev() {
+        match hop {
+            Some(hop) if is_trusted(&hop) => ip = Some(hop),
+            Some(hop) => {
+                ip = Some(hop);
+                break;
+            }
+            // obfuscated or garbled, nothing further out can be believed
+            None => break,
+        }
+    }
+
+    let proto = if forwarded.is_empty() {
+        values(headers, X_FORWARDED_PROTO)
+            .last()
+            .map(|proto| proto.to_string())
+    } else {
+        forwarded
+            .iter()
+            .rev()
+            .find_map(|element| element.proto.clone())
+    };
+
+    Origin {
+        ip,
+        https: match proto {
+            Some(proto) => proto.eq_ignore_ascii_case("https"),
+            None => https,
+        },
+    }
+}
+
+/// The comma separated values of every instance of a header.
+fn values<'a>(headers: &'a HeaderMap, name: &str) -> Vec<&'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+struct Element {
+    for_: Option<IpAddr>,
+    proto: Option<String>,
+}
+
+/// Parses RFC 7239 `Forwarded` headers, e.g.
+/// `for=192.0.2.60;proto=https, for="[2001:db8::17]:4711"`.
+fn forwarded(headers: &HeaderMap) -> Vec<Element> {
+    values(headers, header::FORWARDED.as_str())
+        .into_iter()
+        .map(|element| {
+            let mut parsed = Element {
+                for_: None,
+                proto: None,
+            };
+
+            for pair in element.split(';') {
+                let (key, value) = match pair.split_once('=') {
+                    Some((key, value)) => (key.trim(), value.trim().trim_matches('"')),
+                    None => continue,
+                };
+
+                if key.eq_ignore_ascii_case("for") {
+                    parsed.for_ = parse_node(value);
+                } else if key.eq_ignore_ascii_case("proto") {
+                    parsed.proto = Some(value.to_string());
+                }
+            }
+
+            parsed
+        })
+        .collect()
+}
+
+/// Parses `192.0.2.60`, `192.0.2.60:80`, `2001:db8::17` or
+/// `[2001:db8::17]:4711`, anything else like `unknown` yields `None`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim_matches('"');
+
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+
+    node.parse().ok().or_else(|| {
+        let (ip, _port) = node.split_once(':')?;
+        ip.parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn tcp(addr: &str) -> Peer {
+        Peer::Tcp(addr.parse::<SocketAddr>().unwrap())
+    }
+
+    fn trusted() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()]
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn nodes_parse_with_and_without_ports() {
+        assert_eq!(parse_node("192.0.2.60"), ip("192.0.2.60"));
+        assert_eq!(parse_node("192.0.2.60:80"), ip("192.0.2.60"));
+        assert_eq!(parse_node("2001:db8::17"), ip("2001:db8::17"));
+        assert_eq!(parse_node("[2001:db8::17]:4711"), ip("2001:db8::17"));
+        assert_eq!(parse_node("\"[2001:db8::17]\""), ip("2001:db8::17"));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+        assert_eq!(parse_node("[2001:db8::17"), None);
+    }
+
+    #[test]
+    fn forwarded_elements_parse() {
+        let elements = forwarded(&headers(&[
+            (
+                "forwarded",
+                "for=192.0.2.60;proto=https;host=example.com, For=\"[2001:db8::17]:4711\"",
+            ),
+            ("forwarded", "for=unknown;by=10.0.0.1"),
+        ]));
+
+        assert_eq!(elements.len(), 3);
+        assert_eq!(elements[0].for_, ip("192.0.2.60"));
+        assert_eq!(elements[0].proto.as_deref(), Some("https"));
+        assert_eq!(elements[1].for_, ip("2001:db8::17"));
+        assert_eq!(elements[1].proto, None);
+        assert_eq!(elements[2].for_, None);
+    }
+
+    #[test]
+    fn untrusted_peers_are_taken_at_their_word() {
+        let origin = resolve(
+            tcp("192.0.2.1:5000"),
+            false,
+            &headers(&[
+                ("x-forwarded-for", "203.0.113.9"),
+                ("x-forwarded-proto", "https"),
+                ("forwarded", "for=203.0.113.9;proto=https"),
+            ]),
+            &trusted(),
+        );
+
+        assert_eq!(origin.ip, ip("192.0.2.1"));
+        assert!(!origin.https);
+    }
+
+    #[test]
+    fn x_forwarded_for_stops_at_the_first_untrusted_hop() {
+        // the client made up the first address, the trusted proxy appended the
+        // address it actually saw
+        let origin = resolve(
+            tcp("10.0.0.2:5000"),
+            false,
+            &headers(&[
+                ("x-forwarded-for", "198.51.100.7, 203.0.113.9, 10.0.0.3"),
+                ("x-forwarded-proto", "https"),
+            ]),
+            &trusted(),
+        );
+
+        assert_eq!(origin.ip, ip("203.0.113.9"));
+        assert!(origin.https);
+    }
+
+    #[test]
+    fn all_trusted_hops_yield_the_outermost() {
+        let origin = resolve(
+            tcp("10.0.0.2:5000"),
+            false,
+            &headers(&[("x-forwarded-for", "10.0.0.4, 10.0.0.3")]),
+            &trusted(),
+        );
+
+        assert_eq!(origin.ip, ip("10.0.0.4"));
+    }
+
+    #[test]
+    fn garbled_hops_stop_the_walk() {
+        let origin = resolve(
+            tcp("10.0.0.2:5000"),
+            false,
+            &headers(&[("x-forwarded-for", "203.0.113.9, unknown, 10.0.0.3")]),
+            &trusted(),
+        );
+
+        assert_eq!(origin.ip, ip("10.0.0.3"));
+    }
+
+    #[test]
+    fn forwarded_takes_precedence_over_x_forwarded() {
+        let origin = resolve(
+            tcp("[::1]:5000"),
+            false,
+            &headers(&[
+                ("x-forwarded-for", "198.51.100.7"),
+                ("x-forwarded-proto", "http"),
+                (
+                    "forwarded",
+                    "for=198.51.100.8, for=\"[2001:db8::17]:4711\";proto=https;host=videos.test",
+                ),
+            ]),
+            &trusted(),
+        );
+
+        assert_eq!(origin.ip, ip("2001:db8::17"));
+        assert!(origin.https);
+    }
+
+    #[test]
+    fn ipv4_mapped_peers_match_ipv4_networks() {
+        let origin = resolve(
+            tcp("[::ffff:10.0.0.2]:5000"),
+            false,
+            &headers(&[("x-forwarded-for", "203.0.113.9")]),
+            &trusted(),
+        );
+
+        assert_eq!(origin.ip, ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn unix_sockets_are_trusted() {
+        let origin = resolve(
+            Peer::Unix,
+            true,
+            &headers(&[("x-forwarded-for", "203.0.113.9")]),
+            &[],
+        );
+
+        assert_eq!(origin.ip, ip("203.0.113.9"));
+        assert!(origin.https);
+
+        let direct = resolve(Peer::Unix, false, &HeaderMap::new(), &[]);
+        assert_eq!(direct.ip, None);
+    }
+}
@@ -0,0 +1,246 @@
+//! Limits on how often a client may try to sign in or create API tokens.
+//!
+//! Attempts are counted per client IP as resolved by [`Client`], so behind a
+//! trusted proxy it's the forwarded address that's limited, not the proxy.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::response::{IntoResponse, Response};
+use http::{header, StatusCode};
+
+use crate::auth::Client;
+
+/// Clients that are tracked at once, once reached the oldest window makes room.
+const CAPACITY: usize = 4096;
+
+/// Something a client may only do so often.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Action {
+    SignIn,
+    CreateToken,
+}
+
+impl Action {
+    /// How many attempts are allowed per window.
+    fn limit(self) -> (u32, Duration) {
+        match self {
+            Action::SignIn => (10, Duration::from_secs(5 * 60)),
+            Action::CreateToken => (20, Duration::from_secs(60 * 60)),
+        }
+    }
+}
+
+/// Rejection for a client that's over its limit, `429` with `Retry-After`.
+#[derive(Debug)]
+pub(crate) struct Limited {
+    retry_after: Duration,
+}
+
+impl IntoResponse for Limited {
+    fn into_response(self) -> Response {
+        // round up, retrying a fraction of a second early would just fail again
+        let seconds = self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0);
+
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, seconds.to_string())],
+        )
+            .into_response()
+    }
+}
+
+/// Unknown addresses, like plain unix socket connections, share one window.
+type Key = (Action, Option<IpAddr>);
+
+struct Window {
+    started: Instant,
+    attempts: u32,
+}
+
+/// Fixed window counters per action and client, cheap to clone.
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    windows: Arc<Mutex<HashMap<Key, Window>>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new() -> Self {
+        Self {
+            windows: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Counts an attempt at `action` by `client`.
+    pub(crate) fn attempt(&self, action: Action, client: &Client) -> Result<(), Limited> {
+        let ip = client
+            .ip
+            .as_deref()
+            .and_then(|ip| ip.parse::<IpAddr>().ok());
+
+        self.attempt_at(action, ip, Instant::now())
+    }
+
+    fn attempt_at(&self, action: Action, ip: Option<IpAddr>, now: Instant) -> Result<(), Limited> {
+        let (attempts, window) = action.limit();
+        let key = (action, ip.map(network));
+
+        // the map is always left in a consistent state, a panic elsewhere
+        // doesn't make it unusable
+        let mut windows = self.windows.lock().unwrap_or_else(|err| err.into_inner());
+
+        if !windows.contains_key(&key) && windows.len() >= CAPACITY {
+            windows
+                .retain(|(action, _), entry| now.duration_since(entry.started) < action.limit().1);
+
+            if windows.len() >= CAPACITY {
+                let oldest = windows
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.started)
+                    .map(|(key, _)| *key);
+
+                if let Some(oldest) = oldest {
+                    windows.remove(&oldest);
+                }
+            }
+        }
+
+        let entry = windows.entry(key).or_insert(Window {
+            started: now,
+            attempts: 0,
+        });
+
+        let elapsed = now.duration_since(entry.started);
+        if elapsed >= window {
+            entry.started = now;
+            entry.attempts = 0;
+        }
+
+        if entry.attempts >= attempts {
+            return Err(Limited {
+                retry_after: window - now.duration_since(entry.started),
+            });
+        }
+
+        entry.attempts += 1;
+
+        Ok(())
+    }
+}
+
+/// The address attempts are counted under, IPv6 clients usually get a whole
+/// /64 so that's what they're limited by.
+fn network(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+
+            IpAddr::V6(Ipv6Addr::new(
+                segments[0],
+                segments[1],
+                segments[2],
+                segments[3],
+                0,
+                0,
+                0,
+                0,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn attempts_are_limited_per_window() {
+        let limiter = RateLimiter::new();
+        let start = Instant::now();
+
+        for _ in 0..10 {
+            assert!(limiter
+                .attempt_at(Action::SignIn, ip("192.0.2.1"), start)
+                .is_ok());
+        }
+
+        let limited = limiter
+            .attempt_at(
+                Action::SignIn,
+                ip("192.0.2.1"),
+                start + Duration::from_secs(60),
+            )
+            .unwrap_err();
+        assert_eq!(limited.retry_after, Duration::from_secs(4 * 60));
+
+        assert!(limiter
+            .attempt_at(
+                Action::SignIn,
+                ip("192.0.2.1"),
+                start + Duration::from_secs(5 * 60)
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn clients_and_actions_are_counted_separately() {
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            limiter
+                .attempt_at(Action::SignIn, ip("192.0.2.1"), now)
+                .unwrap();
+        }
+
+        assert!(limiter
+            .attempt_at(Action::SignIn, ip("192.0.2.1"), now)
+            .is_err());
+        assert!(limiter
+            .attempt_at(Action::SignIn, ip("192.0.2.2"), now)
+            .is_ok());
+        assert!(limiter.attempt_at(Action::SignIn, None, now).is_ok());
+        assert!(limiter
+            .attempt_at(Action::CreateToken, ip("192.0.2.1"), now)
+            .is_ok());
+    }
+
+    #[test]
+    fn ipv6_clients_are_limited_by_their_prefix() {
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+
+        for host in 1..=10 {
+            limiter
+                .attempt_at(Action::SignIn, ip(&format!("2001:db8::{:x}", host)), now)
+                .unwrap();
+        }
+
+        assert!(limiter
+            .attempt_at(Action::SignIn, ip("2001:db8::ffff"), now)
+            .is_err());
+        assert!(limiter
+            .attempt_at(Action::SignIn, ip("2001:db8:0:1::1"), now)
+            .is_ok());
+    }
+
+    #[test]
+    fn retry_after_rounds_up() {
+        let response = Limited {
+            retry_after: Duration::from_millis(1500),
+        }
+        .into_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+    }
+}
@@ -1,5 +1,20 @@
-use axum::response::IntoResponse;
-use http::{header, StatusCode};
+use std::{
+    io::SeekFrom,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{self, Bytes, StreamBody},
+    response::IntoResponse,
+};
+use futures_util::{future, stream, Stream, StreamExt as _, TryStreamExt as _};
+use http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt as _, AsyncSeekExt as _},
+};
+use tokio_util::io::ReaderStream;
 
 pub use Either::*;
 
@@ -52,3 +67,472 @@ impl axum::response::IntoResponse for Js {
             .into_response()
     }
 }
+
+/// The largest number of ranges served from a single request, anything with
+/// more is answered with the whole file instead.
+const MAX_RANGES: usize = 32;
+
+/// Maps a stored file extension to its MIME type.
+pub fn mime_for_ext(ext: &str) -> &'static str {
+    match ext.to_ascii_lowercase().as_str() {
+        "3gp" => "video/3gpp",
+        "avi" => "video/x-msvideo",
+        "flv" => "video/x-flv",
+        "m4v" => "video/x-m4v",
+        "mkv" => "video/x-matroska",
+        "mov" => "video/quicktime",
+        "mp4" => "video/mp4",
+        "mpg" | "mpeg" => "video/mpeg",
+        "webm" => "video/webm",
+        "wmv" => "video/x-ms-wmv",
+        "m3u8" => "application/vnd.apple.mpegurl",
+        "m4s" => "video/iso.segment",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Compression predicate that leaves file responses alone, their bytes have to
+/// stay addressable by `Range` requests.
+pub fn is_compressible(
+    _status: StatusCode,
+    _version: http::Version,
+    headers: &HeaderMap,
+    _extensions: &http::Extensions,
+) -> bool {
+    !headers.contains_key(header::ACCEPT_RANGES)
+}
+
+/// A file streamed from disk, with support for `HEAD`, conditional requests
+/// (`If-None-Match`, `If-Modified-Since`) and single or multiple byte ranges
+/// (`Range`, `If-Range`).
+pub struct FileResponse {
+    path: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+    mime: &'static str,
+    cache_control: &'static str,
+    head: bool,
+    headers: HeaderMap,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Ranges {
+    /// No or an invalid `Range` header, the whole file is sent.
+    Full,
+    Unsatisfiable,
+    Partial(Vec<(u64, u64)>),
+}
+
+impl FileResponse {
+    /// Returns `None` when `path` doesn't point to a file.
+    pub async fn open(
+        path: impl Into<PathBuf>,
+        mime: &'static str,
+        method: &Method,
+        headers: &HeaderMap,
+    ) -> std::io::Result<Option<Self>> {
+        let path = path.into();
+
+        let metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Ok(_) => return Ok(None),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Some(Self {
+            path,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            mime,
+            cache_control: "private, max-age=604800",
+            head: method == Method::HEAD,
+            headers: headers.clone(),
+        }))
+    }
+
+    pub fn cache_control(mut self, cache_control: &'static str) -> Self {
+        self.cache_control = cache_control;
+        self
+    }
+
+    fn etag(&self) -> String {
+        let modified = self
+            .modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_secs())
+            .unwrap_or(0);
+
+        format!("\"{:x}-{:x}\"", self.len, modified)
+    }
+
+    fn last_modified(&self) -> Option<String> {
+        self.modified.map(httpdate::fmt_http_date)
+    }
+
+    fn header(&self, name: header::HeaderName) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    fn is_not_modified(&self, etag: &str) -> bool {
+        if let Some(if_none_match) = self.header(header::IF_NONE_MATCH) {
+            // weak comparison, `If-Modified-Since` is ignored when this is present
+            return if_none_match.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_start_matches("W/") == etag
+            });
+        }
+
+        match (self.header(header::IF_MODIFIED_SINCE), self.modified) {
+            (Some(since), Some(modified)) => match httpdate::parse_http_date(since) {
+                Ok(since) => seconds(modified) <= seconds(since),
+                Err(_) => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// A `Range` is only honoured when `If-Range` is missing or still matches
+    /// the current version of the file.
+    fn is_range_fresh(&self, etag: &str) -> bool {
+        let if_range = match self.header(header::IF_RANGE) {
+            Some(if_range) => if_range.trim(),
+            None => return true,
+        };
+
+        if if_range.starts_with('"') {
+            return if_range == etag;
+        }
+
+        match (httpdate::parse_http_date(if_range), self.modified) {
+            (Ok(date), Some(modified)) => seconds(date) == seconds(modified),
+            _ => false,
+        }
+    }
+
+    fn ranges(&self) -> Ranges {
+        match self.header(header::RANGE) {
+            Some(range) => parse_ranges(range, self.len),
+            None => Ranges::Full,
+        }
+    }
+}
+
+/// Parses a `Range` header for a file of `len` bytes.
+///
+/// Overlapping and adjacent ranges are merged, so the response never repeats
+/// bytes. Requests whose ranges add up to more than the file are answered with
+/// the whole file instead of many copies of it.
+fn parse_ranges(range: &str, len: u64) -> Ranges {
+    let specs = match range.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None => return Ranges::Full,
+    };
+
+    let mut ranges = Vec::new();
+
+    for spec in specs.split(',') {
+        let (start, end) = match spec.trim().split_once('-') {
+            Some(spec) => spec,
+            None => return Ranges::Full,
+        };
+
+        let range = match (start.parse::<u64>(), end) {
+            // `-500`, the last 500 bytes
+            (Err(_), end) if start.is_empty() => match end.parse::<u64>() {
+                Ok(0) => None,
+                Ok(suffix) if len > 0 => Some((len.saturating_sub(suffix), len - 1)),
+                Ok(_) => None,
+                Err(_) => return Ranges::Full,
+            },
+            // `500-`, everything from byte 500
+            (Ok(start), "") => (start < len).then(|| (start, len - 1)),
+            (Ok(start), end) => match end.parse::<u64>() {
+                Ok(end) if end < start => return Ranges::Full,
+                Ok(end) => (start < len).then(|| (start, end.min(len - 1))),
+                Err(_) => return Ranges::Full,
+            },
+            (Err(_), _) => return Ranges::Full,
+        };
+
+        ranges.extend(range);
+    }
+
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    let requested = ranges.iter().fold(0u64, |total, (start, end)| {
+        total.saturating_add(end - start + 1)
+    });
+    if ranges.len() > MAX_RANGES || requested > len {
+        return Ranges::Full;
+    }
+
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    Ranges::Partial(merged)
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+fn read_range(
+    path: PathBuf,
+    start: u64,
+    len: u64,
+) -> impl Stream<Item = std::io::Result<Bytes>> + Send + 'static {
+    stream::once(async move {
+        let mut file = File::open(&path).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+
+        Ok::<_, std::io::Error>(ReaderStream::new(file.take(len)))
+    })
+    .try_flatten()
+}
+
+impl IntoResponse for FileResponse {
+    fn into_response(self) -> axum::response::Response {
+        let etag = self.etag();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(self.cache_control),
+        );
+        if let Ok(value) = HeaderValue::from_str(&etag) {
+            headers.insert(header::ETAG, value);
+        }
+        if let Some(value) = self
+            .last_modified()
+            .and_then(|value| HeaderValue::from_str(&value).ok())
+        {
+            headers.insert(header::LAST_MODIFIED, value);
+        }
+
+        if self.is_not_modified(&etag) {
+            return (StatusCode::NOT_MODIFIED, headers).into_response();
+        }
+
+        let ranges = if self.is_range_fresh(&etag) {
+            self.ranges()
+        } else {
+            Ranges::Full
+        };
+
+        let (status, len, body) = match ranges {
+            Ranges::Full => (
+                StatusCode::OK,
+                self.len,
+                body::boxed(StreamBody::new(read_range(self.path, 0, self.len))),
+            ),
+            Ranges::Unsatisfiable => {
+                headers.insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::from_str(&format!("bytes */{}", self.len)).unwrap(),
+                );
+
+                return (StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response();
+            }
+            Ranges::Partial(ranges) if ranges.len() == 1 => {
+                let (start, end) = ranges[0];
+
+                headers.insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, self.len))
+                        .unwrap(),
+                );
+
+                (
+                    StatusCode::PARTIAL_CONTENT,
+                    end - start + 1,
+                    body::boxed(StreamBody::new(read_range(
+                        self.path,
+                        start,
+                        end - start + 1,
+                    ))),
+                )
+            }
+            Ranges::Partial(ranges) => {
+                let boundary = nanoid::nanoid!(32);
+
+                let closing = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+                let mut len = closing.len() as u64;
+
+                let mut parts = Vec::with_capacity(ranges.len());
+                for (start, end) in ranges {
+                    let head = Bytes::from(format!(
+                        "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                        boundary, self.mime, start, end, self.len
+                    ));
+
+                    len += head.len() as u64 + (end - start + 1);
+                    parts.push((head, start, end - start + 1));
+                }
+
+                let path = self.path;
+                let parts = stream::iter(parts).flat_map(move |(head, start, len)| {
+                    stream::once(future::ready(Ok(head))).chain(read_range(
+                        path.clone(),
+                        start,
+                        len,
+                    ))
+                });
+                let body = parts.chain(stream::once(future::ready(Ok(closing))));
+
+                headers.insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_str(&format!("multipart/byteranges; boundary={}", boundary))
+                        .unwrap(),
+                );
+
+                (
+                    StatusCode::PARTIAL_CONTENT,
+                    len,
+                    body::boxed(StreamBody::new(body)),
+                )
+            }
+        };
+
+        headers
+            .entry(header::CONTENT_TYPE)
+            .or_insert_with(|| HeaderValue::from_static(self.mime));
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+
+        if self.head {
+            return (status, headers).into_response();
+        }
+
+        (status, headers, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEN: u64 = 1000;
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(
+            parse_ranges("bytes=0-499", LEN),
+            Ranges::Partial(vec![(0, 499)])
+        );
+        assert_eq!(
+            parse_ranges(" bytes=500-999 ", LEN),
+            Ranges::Partial(vec![(500, 999)])
+        );
+        // the end is clamped to the file
+        assert_eq!(
+            parse_ranges("bytes=900-5000", LEN),
+            Ranges::Partial(vec![(900, 999)])
+        );
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(
+            parse_ranges("bytes=-100", LEN),
+            Ranges::Partial(vec![(900, 999)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=-5000", LEN),
+            Ranges::Partial(vec![(0, 999)])
+        );
+        assert_eq!(parse_ranges("bytes=-0", LEN), Ranges::Unsatisfiable);
+        assert_eq!(parse_ranges("bytes=-10", 0), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn open_ended_ranges() {
+        assert_eq!(
+            parse_ranges("bytes=0-", LEN),
+            Ranges::Partial(vec![(0, 999)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=999-", LEN),
+            Ranges::Partial(vec![(999, 999)])
+        );
+    }
+
+    #[test]
+    fn out_of_bounds_ranges() {
+        assert_eq!(parse_ranges("bytes=1000-", LEN), Ranges::Unsatisfiable);
+        assert_eq!(parse_ranges("bytes=1000-1999", LEN), Ranges::Unsatisfiable);
+        assert_eq!(parse_ranges("bytes=0-0", 0), Ranges::Unsatisfiable);
+        // satisfiable ranges are served, the others dropped
+        assert_eq!(
+            parse_ranges("bytes=2000-2999,0-9", LEN),
+            Ranges::Partial(vec![(0, 9)])
+        );
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_ranges_are_merged() {
+        assert_eq!(
+            parse_ranges("bytes=0-99,50-149", LEN),
+            Ranges::Partial(vec![(0, 149)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=100-199,0-99", LEN),
+            Ranges::Partial(vec![(0, 199)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=0-9,20-29,5-14", LEN),
+            Ranges::Partial(vec![(0, 14), (20, 29)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=10-19,0-4", LEN),
+            Ranges::Partial(vec![(0, 4), (10, 19)])
+        );
+    }
+
+    #[test]
+    fn ranges_larger_than_the_file_are_served_whole() {
+        assert_eq!(parse_ranges("bytes=0-,0-,0-,0-", LEN), Ranges::Full);
+        assert_eq!(parse_ranges("bytes=0-599,400-", LEN), Ranges::Full);
+        assert_eq!(parse_ranges("bytes=-1000,-1000", LEN), Ranges::Full);
+
+        let many = (0..=MAX_RANGES)
+            .map(|i| format!("{}-{}", i * 2, i * 2))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_eq!(parse_ranges(&format!("bytes={}", many), LEN), Ranges::Full);
+    }
+
+    #[test]
+    fn malformed_ranges_are_ignored() {
+        for range in [
+            "",
+            "0-99",
+            "items=0-99",
+            "bytes=",
+            "bytes=abc",
+            "bytes=a-b",
+            "bytes=10-a",
+            "bytes=-a",
+            "bytes=99-0",
+            "bytes=0-99,",
+            "bytes=--1",
+            "bytes=18446744073709551616-",
+        ] {
+            assert_eq!(parse_ranges(range, LEN), Ranges::Full, "{:?}", range);
+        }
+    }
+}
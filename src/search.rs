@@ -0,0 +1,554 @@
+//! Search queries, made of words and `"quoted phrases"` looked up in the full
+//! text index along with filters:
+//!
+//! - `tag:name` only matches videos with the tag, repeat it to require several
+//! - `before:YYYY-MM-DD` and `after:YYYY-MM-DD` compare the recorded date, or
+//!   the upload date for videos without one
+//! - `duration:<5m`, `duration:>=30s` or `duration:1m-5m`, durations are
+//!   seconds unless they have an `s`, `m` or `h` suffix or are written as `m:ss`
+//!
+//! Words match as prefixes, phrases only as a whole.
+
+use std::ops::Bound;
+
+use sqlx::{QueryBuilder, Sqlite};
+
+use crate::{
+    error::Error,
+    models::{self, Tag, Video},
+};
+
+/// Most hits the search and collection pages show, the best ones.
+pub(crate) const MAX_HITS: usize = 100;
+
+/// Surround the matched terms in highlighted text coming out of the index.
+pub(crate) const HIGHLIGHT_START: char = '\u{2}';
+pub(crate) const HIGHLIGHT_END: char = '\u{3}';
+
+#[derive(Debug, Default)]
+pub(crate) struct Search {
+    /// The terms as an FTS5 query.
+    terms: Vec<String>,
+    tags: Vec<String>,
+    before: Option<String>,
+    after: Option<String>,
+    duration: Option<(Bound<f64>, Bound<f64>)>,
+}
+
+impl Search {
+    pub fn parse(query: &str) -> Result<Self, Error> {
+        let mut search = Search::default();
+
+        for token in tokens(query) {
+            let word = match token {
+                Token::Phrase(phrase) => {
+                    search.terms.push(quote(&phrase));
+                    continue;
+                }
+                Token::Word(word) => word,
+            };
+
+            let (key, value) = word.split_once(':').unwrap_or_default();
+
+            match key.to_lowercase().as_str() {
+                "tag" => match Tag::normalize(value) {
+                    Some(tag) => search.tags.push(tag),
+                    None => return Err(Error::Search("tag: needs the name of a tag")),
+                },
+                "before" => search.before = Some(parse_date(value)?),
+                "after" => search.after = Some(parse_date(value)?),
+                "duration" => search.duration = Some(parse_duration(value)?),
+                // prefix queries need something the tokenizer keeps
+                _ if word.chars().any(char::is_alphanumeric) => {
+                    search.terms.push(format!("{}*", quote(&word)));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(search)
+    }
+
+    /// The FTS5 query for the index, `None` when there are only filters.
+    pub fn fts_query(&self) -> Option<String> {
+        (!self.terms.is_empty()).then(|| self.terms.join(" "))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+            && self.tags.is_empty()
+            && self.before.is_none()
+            && self.after.is_none()
+            && self.duration.is_none()
+    }
+
+    /// The tags videos need to have.
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Narrows a query down to the videos passing the date and duration
+    /// filters, tags are left to the caller to combine with any others.
+    pub(crate) fn push_conditions(&self, query: &mut QueryBuilder<'_, Sqlite>) {
+        // mirrors `Video::date`
+        const DATE: &str = "COALESCE(recorded, SUBSTR(created, 1, 10))";
+
+        if let Some(before) = &self.before {
+            query
+                .push(format_args!(" AND {} < ", DATE))
+                .push_bind(before.clone());
+        }
+
+        if let Some(after) = &self.after {
+            query
+                .push(format_args!(" AND {} > ", DATE))
+                .push_bind(after.clone());
+        }
+
+        // videos that haven't been probed yet have no duration to compare
+        if let Some((start, end)) = self.duration {
+            query.push(" AND duration IS NOT NULL");
+
+            match start {
+                Bound::Included(start) => query.push(" AND duration >= ").push_bind(start),
+                Bound::Excluded(start) => query.push(" AND duration > ").push_bind(start),
+                Bound::Unbounded => query,
+            };
+
+            match end {
+                Bound::Included(end) => query.push(" AND duration <= ").push_bind(end),
+                Bound::Excluded(end) => query.push(" AND duration < ").push_bind(end),
+                Bound::Unbounded => query,
+            };
+        }
+    }
+}
+
+enum Token {
+    Word(String),
+    Phrase(String),
+}
+
+/// Splits a query into words and phrases, an unterminated quote runs until the
+/// end of the query.
+fn tokens(query: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+
+            let phrase = chars.by_ref().take_while(|&c| c != '"').collect::<String>();
+            if !phrase.trim().is_empty() {
+                tokens.push(Token::Phrase(phrase));
+            }
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '"' {
+                    break;
+                }
+
+                word.push(c);
+                chars.next();
+            }
+
+            tokens.push(Token::Word(word));
+        }
+    }
+
+    tokens
+}
+
+/// Quotes `text` as an FTS5 string, so none of it is read as query syntax.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+fn parse_date(value: &str) -> Result<String, Error> {
+    models::parse_date(value).ok_or(Error::Search(
+        "before: and after: need a date formatted as YYYY-MM-DD",
+    ))
+}
+
+fn parse_duration(value: &str) -> Result<(Bound<f64>, Bound<f64>), Error> {
+    const INVALID: Error =
+        Error::Search("duration: needs a comparison like <5m or >=30s, or a range like 1m-5m");
+
+    let seconds = |value: &str| parse_seconds(value).ok_or(INVALID);
+
+    let range = if let Some(value) = value.strip_prefix("<=") {
+        (Bound::Unbounded, Bound::Included(seconds(value)?))
+    } else if let Some(value) = value.strip_prefix(">=") {
+        (Bound::Included(seconds(value)?), Bound::Unbounded)
+    } else if let Some(value) = value.strip_prefix('<') {
+        (Bound::Unbounded, Bound::Excluded(seconds(value)?))
+    } else if let Some(value) = value.strip_prefix('>') {
+        (Bound::Excluded(seconds(value)?), Bound::Unbounded)
+    } else if let Some((min, max)) = value.split_once('-') {
+        (
+            Bound::Included(seconds(min)?),
+            Bound::Included(seconds(max)?),
+        )
+    } else {
+        return Err(INVALID);
+    };
+
+    Ok(range)
+}
+
+/// Parses `90`, `90s`, `1.5m`, `2h` or `1:30` into seconds.
+fn parse_seconds(value: &str) -> Option<f64> {
+    let value = value.trim().to_lowercase();
+
+    if value.contains(':') {
+        return value.split(':').try_fold(0.0, |total, part| {
+            let part = part.parse::<f64>().ok().filter(|part| *part >= 0.0)?;
+
+            Some(total * 60.0 + part)
+        });
+    }
+
+    let (number, unit) = match value.char_indices().last()? {
+        (i, 's') => (&value[..i], 1.0),
+        (i, 'm') => (&value[..i], 60.0),
+        (i, 'h') => (&value[..i], 3600.0),
+        _ => (value.as_str(), 1.0),
+    };
+
+    let number = number
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite() && *n >= 0.0)?;
+
+    Some(number * unit)
+}
+
+/// A piece of highlighted text.
+#[derive(Clone, Debug)]
+pub(crate) struct Fragment {
+    pub text: String,
+    /// Whether this is one of the terms that were searched for.
+    pub matched: bool,
+}
+
+impl Fragment {
+    pub fn plain(text: String) -> Vec<Fragment> {
+        vec![Fragment {
+            text,
+            matched: false,
+        }]
+    }
+
+    /// Splits text highlighted with [`HIGHLIGHT_START`] and [`HIGHLIGHT_END`].
+    pub fn split(highlighted: &str) -> Vec<Fragment> {
+        let mut fragments = Vec::new();
+        let mut rest = highlighted;
+
+        while !rest.is_empty() {
+            let (plain, after) = rest.split_once(HIGHLIGHT_START).unwrap_or((rest, ""));
+            let (matched, after) = after.split_once(HIGHLIGHT_END).unwrap_or((after, ""));
+
+            for (text, matched) in [(plain, false), (matched, true)] {
+                if !text.is_empty() {
+                    fragments.push(Fragment {
+                        text: text.to_string(),
+                        matched,
+                    });
+                }
+            }
+
+            rest = after;
+        }
+
+        fragments
+    }
+}
+
+/// A video found by a search.
+#[derive(Clone)]
+pub(crate) struct SearchHit {
+    pub video: Video,
+    /// The title with the matched terms highlighted.
+    pub title: Vec<Fragment>,
+    /// The part of the description that matched, if any did.
+    pub snippet: Vec<Fragment>,
+}
+
+impl SearchHit {
+    /// A hit for a search that only used filters, so there's nothing to
+    /// highlight.
+    pub fn plain(video: Video) -> Self {
+        SearchHit {
+            title: Fragment::plain(video.display_title()),
+            snippet: Vec::new(),
+            video,
+        }
+    }
+}
+
+/// Some of the videos a search found.
+pub(crate) struct SearchPage {
+    pub hits: Vec<SearchHit>,
+    /// Number of videos found on all pages together.
+    pub total: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{database, pagination::VideoFilter};
+
+    #[test]
+    fn words_match_as_prefixes_and_phrases_whole() {
+        let search = Search::parse(r#"cat "big dog"  fish"#).unwrap();
+
+        assert_eq!(
+            search.fts_query().as_deref(),
+            Some(r#""cat"* "big dog" "fish"*"#)
+        );
+    }
+
+    #[test]
+    fn query_syntax_is_quoted() {
+        let search = Search::parse(r#"NEAR(a b) c"d "#).unwrap();
+
+        assert_eq!(
+            search.fts_query().as_deref(),
+            Some(r#""NEAR(a"* "b)"* "c"* "d ""#)
+        );
+    }
+
+    #[test]
+    fn unterminated_phrases_run_to_the_end() {
+        let search = Search::parse(r#"a "b c"#).unwrap();
+
+        assert_eq!(search.fts_query().as_deref(), Some(r#""a"* "b c""#));
+    }
+
+    #[test]
+    fn words_without_letters_or_digits_are_skipped() {
+        let search = Search::parse(r#"- * "" " ""#).unwrap();
+
+        assert!(search.is_empty());
+        assert_eq!(search.fts_query(), None);
+    }
+
+    #[test]
+    fn filters_are_parsed() {
+        let search =
+            Search::parse("TAG:Cats tag:big-dogs after:2023-01-01 before:2023-12-31 duration:<5m")
+                .unwrap();
+
+        assert_eq!(search.fts_query(), None);
+        assert!(!search.is_empty());
+        assert_eq!(search.tags, ["cats", "big-dogs"]);
+        assert_eq!(search.after.as_deref(), Some("2023-01-01"));
+        assert_eq!(search.before.as_deref(), Some("2023-12-31"));
+        assert_eq!(
+            search.duration,
+            Some((Bound::Unbounded, Bound::Excluded(300.0)))
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_words() {
+        let search = Search::parse("http://example.com").unwrap();
+
+        assert_eq!(
+            search.fts_query().as_deref(),
+            Some(r#""http://example.com"*"#)
+        );
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        for query in [
+            "tag:",
+            "tag:!!",
+            "before:yesterday",
+            "after:2023-1-1",
+            "after:2023-02-30",
+            "duration:5m",
+            "duration:<",
+            "duration:<-5",
+            "duration:<5x",
+            "duration:1m-",
+            "duration:>inf",
+        ] {
+            assert!(
+                matches!(Search::parse(query), Err(Error::Search(_))),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn durations_parse() {
+        assert_eq!(parse_seconds("90"), Some(90.0));
+        assert_eq!(parse_seconds("90s"), Some(90.0));
+        assert_eq!(parse_seconds("1.5m"), Some(90.0));
+        assert_eq!(parse_seconds("2H"), Some(7200.0));
+        assert_eq!(parse_seconds("1:30"), Some(90.0));
+        assert_eq!(parse_seconds("1:02:03"), Some(3723.0));
+        assert_eq!(parse_seconds("1:-2"), None);
+        assert_eq!(parse_seconds(""), None);
+        assert_eq!(parse_seconds("m"), None);
+
+        assert_eq!(
+            parse_duration(">=30s").unwrap(),
+            (Bound::Included(30.0), Bound::Unbounded)
+        );
+        assert_eq!(
+            parse_duration("<=1:00").unwrap(),
+            (Bound::Unbounded, Bound::Included(60.0))
+        );
+        assert_eq!(
+            parse_duration(">10").unwrap(),
+            (Bound::Excluded(10.0), Bound::Unbounded)
+        );
+        assert_eq!(
+            parse_duration("1m-5m").unwrap(),
+            (Bound::Included(60.0), Bound::Included(300.0))
+        );
+    }
+
+    async fn insert(
+        pool: &sqlx::SqlitePool,
+        title: &str,
+        duration: Option<f64>,
+        recorded: Option<&str>,
+        tags: &[&str],
+    ) -> Uuid {
+        let id = Uuid::new_v4();
+
+        sqlx::query(
+            "INSERT INTO videos(id, ext, visibility, title, duration, recorded, created) VALUES (?, 'mp4', 'public', ?, ?, ?, '2023-06-15 12:00:00')",
+        )
+        .bind(id)
+        .bind(title)
+        .bind(duration)
+        .bind(recorded)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        for tag in tags {
+            sqlx::query("INSERT OR IGNORE INTO tags(name) VALUES (?)")
+                .bind(tag)
+                .execute(pool)
+                .await
+                .unwrap();
+            sqlx::query(
+                "INSERT INTO video_tags(video_id, tag_id) SELECT ?, id FROM tags WHERE name = ?",
+            )
+            .bind(id)
+            .bind(tag)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+
+        id
+    }
+
+    async fn found(pool: &sqlx::SqlitePool, query: &str) -> Vec<Uuid> {
+        let search = Search::parse(query).unwrap();
+
+        database::db_search_videos(pool, None, &search, &VideoFilter::default(), 0, 10)
+            .await
+            .unwrap()
+            .hits
+            .into_iter()
+            .map(|hit| hit.video.id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn filters_match() {
+        let pool = database::memory_pool().await;
+
+        let matching = insert(&pool, "a", Some(120.0), None, &["cats", "dogs"]).await;
+        insert(&pool, "b", Some(120.0), None, &["dogs"]).await;
+        insert(&pool, "c", Some(30.0), None, &["cats"]).await;
+        insert(&pool, "d", None, None, &["cats"]).await;
+        // the recorded date wins over the upload date
+        let recorded = insert(&pool, "e", Some(120.0), Some("2022-12-31"), &["cats"]).await;
+
+        assert_eq!(
+            found(&pool, "tag:cats after:2023-01-01 duration:1m-5m").await,
+            [matching]
+        );
+        assert_eq!(found(&pool, "before:2023-06-15").await, [recorded]);
+        assert_eq!(found(&pool, "tag:cats tag:dogs").await, [matching]);
+        assert_eq!(found(&pool, "duration:<=30s").await.len(), 1);
+        assert_eq!(found(&pool, "after:2020-01-01").await.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn terms_are_ranked_and_highlighted() {
+        let pool = database::memory_pool().await;
+
+        let description = insert(&pool, "other", None, None, &[]).await;
+        sqlx::query("UPDATE videos SET description = 'about a cat' WHERE id = ?")
+            .bind(description)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let title = insert(&pool, "Cats", None, None, &[]).await;
+        insert(&pool, "dogs", None, None, &[]).await;
+        let tagged = insert(&pool, "c", Some(60.0), None, &["cat"]).await;
+
+        // titles weigh most, then tags, then descriptions
+        assert_eq!(found(&pool, "cat").await, [title, tagged, description]);
+        assert_eq!(found(&pool, "cat duration:>10").await, [tagged]);
+
+        let search = Search::parse("cat").unwrap();
+        let page = database::db_search_videos(&pool, None, &search, &VideoFilter::default(), 1, 1)
+            .await
+            .unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(page.hits.len(), 1);
+        assert_eq!(page.hits[0].video.id, tagged);
+
+        let hits = database::db_search_videos(&pool, None, &search, &VideoFilter::default(), 0, 3)
+            .await
+            .unwrap()
+            .hits;
+        assert!(hits[0].title[0].matched);
+        assert_eq!(hits[0].title[0].text, "Cats");
+        assert!(hits[2].snippet.iter().any(|fragment| fragment.matched));
+        assert!(hits[1].snippet.is_empty());
+    }
+
+    #[test]
+    fn highlights_split_into_fragments() {
+        let fragments = Fragment::split("a \u{2}cat\u{3} and a \u{2}dog\u{3}");
+        let fragments = fragments
+            .iter()
+            .map(|fragment| (fragment.text.as_str(), fragment.matched))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            fragments,
+            [
+                ("a ", false),
+                ("cat", true),
+                (" and a ", false),
+                ("dog", true)
+            ]
+        );
+
+        let unterminated = Fragment::split("\u{2}cat");
+        assert_eq!(unterminated.len(), 1);
+        assert!(unterminated[0].matched);
+
+        assert!(Fragment::split("").is_empty());
+    }
+}
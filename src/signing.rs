@@ -0,0 +1,307 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac as _};
+use sha2::Sha256;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::error::Error;
+
+/// How long a signed media URL stays valid.
+const URL_TTL_SECONDS: u64 = 6 * 60 * 60;
+
+/// Expiry times are rounded up to this, so pages rendered close together hand
+/// out identical URLs and the browser cache keeps working.
+const URL_BUCKET_SECONDS: u64 = 60 * 60;
+
+/// Signs and verifies expiring media URLs, letting `<video>` tags and external
+/// players fetch media without a session cookie.
+///
+/// The key is generated on first boot and kept in the `settings` table, so
+/// URLs survive restarts.
+#[derive(Clone)]
+pub(crate) struct Signer {
+    mac: Hmac<Sha256>,
+    /// Prepended to the URLs handed out, signatures cover the path without it.
+    base_path: String,
+}
+
+impl Signer {
+    pub(crate) async fn load_or_create(
+        pool: &SqlitePool,
+        base_path: String,
+    ) -> Result<Self, Error> {
+        let key = nanoid::nanoid!(64);
+
+        sqlx::query!(
+            "INSERT OR IGNORE INTO settings(key, value) VALUES ('signing_key', ?)",
+            key
+        )
+        .execute(pool)
+        .await?;
+
+        let key = sqlx::query_scalar!("SELECT value FROM settings WHERE key = 'signing_key'")
+            .fetch_one(pool)
+            .await?;
+
+        Ok(Self::from_key(&key, base_path))
+    }
+
+    fn from_key(key: &str, base_path: String) -> Self {
+        let mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+            .expect("HMAC accepts keys of any length");
+
+        Self { mac, base_path }
+    }
+
+    fn signature(&self, scope: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac = self.mac.clone();
+        mac.update(expires.to_string().as_bytes());
+        mac.update(b"\n");
+        mac.update(scope.as_bytes());
+        mac
+    }
+
+    /// Signs `scope`, returning the query string that has to be appended to the URL.
+    pub(crate) fn sign(&self, scope: &str) -> String {
+        let now = now();
+        let expires = (now / URL_BUCKET_SECONDS + 1) * URL_BUCKET_SECONDS + URL_TTL_SECONDS;
+
+        let signature = hex::encode(self.signature(scope, expires).finalize().into_bytes());
+
+        format!("expires={}&signature={}", expires, signature)
+    }
+
+    pub(crate) fn verify(&self, scope: &str, expires: u64, signature: &str) -> bool {
+        if expires < now() {
+            return false;
+        }
+
+        let signature = match hex::decode(signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+
+        self.signature(scope, expires)
+            .verify_slice(&signature)
+            .is_ok()
+    }
+
+    pub(crate) fn thumbnail_url(&self, id: &Uuid) -> String {
+        let path = format!("/assets/images/{}.webp", id);
+        let query = self.sign(&path);
+
+        format!("{}{}?{}", self.base_path, path, query)
+    }
+
+    pub(crate) fn video_url(&self, file_name: String) -> String {
+        let path = format!("/assets/video/{}", file_name);
+        let query = self.sign(&path);
+
+        format!("{}{}?{}", self.base_path, path, query)
+    }
+
+    /// A URL that removes the video without signing in, handed out to upload
+    /// tools. It doesn't expire, so it's only given to the uploader.
+    pub(crate) fn deletion_url(&self, id: &Uuid) -> String {
+        let path = deletion_path(id);
+        let signature = hex::encode(self.signature(&path, 0).finalize().into_bytes());
+
+        format!("{}{}?signature={}", self.base_path, path, signature)
+    }
+
+    pub(crate) fn verify_deletion(&self, id: &Uuid, signature: &str) -> bool {
+        let signature = match hex::decode(signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+
+        self.signature(&deletion_path(id), 0)
+            .verify_slice(&signature)
+            .is_ok()
+    }
+
+    /// HLS URLs are signed for the video's whole directory, the query string is
+    /// carried over to the variant playlists and segments when they're served.
+    pub(crate) fn hls_url(&self, id: &Uuid) -> String {
+        let scope = hls_scope(id);
+        let query = self.sign(&scope);
+
+        format!("{}{}master.m3u8?{}", self.base_path, scope, query)
+    }
+}
+
+pub(crate) fn hls_scope(id: &Uuid) -> String {
+    format!("/assets/hls/{}/", id)
+}
+
+fn deletion_path(id: &Uuid) -> String {
+    format!("/upload/sharex/{}/delete", id)
+}
+
+/// The part of a request path that a signature has to cover.
+pub(crate) fn scope(path: &str) -> String {
+    if let Some(rest) = path.strip_prefix("/assets/hls/") {
+        if let Some(id) = rest.split('/').next().and_then(|id| id.parse().ok()) {
+            return hls_scope(&id);
+        }
+    }
+
+    path.to_string()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer() -> Signer {
+        Signer::from_key("test key", "/hawk".to_string())
+    }
+
+    /// Splits a signed URL into its path and the `expires` and `signature`
+    /// parameters.
+    fn parse(url: &str) -> (String, u64, String) {
+        let (path, query) = url.split_once('?').unwrap();
+        let (expires, signature) = query.split_once('&').unwrap();
+
+        (
+            path.to_string(),
+            expires.strip_prefix("expires=").unwrap().parse().unwrap(),
+            signature.strip_prefix("signature=").unwrap().to_string(),
+        )
+    }
+
+    fn signature_for(signer: &Signer, scope: &str, expires: u64) -> String {
+        hex::encode(signer.signature(scope, expires).finalize().into_bytes())
+    }
+
+    #[test]
+    fn signed_urls_verify() {
+        let signer = signer();
+        let id = Uuid::new_v4();
+
+        let (path, expires, signature) = parse(&signer.thumbnail_url(&id));
+        assert_eq!(path, format!("/hawk/assets/images/{}.webp", id));
+
+        // the base path isn't covered by the signature
+        let scope = path.strip_prefix("/hawk").unwrap();
+        assert!(signer.verify(scope, expires, &signature));
+        assert!(!signer.verify(&path, expires, &signature));
+    }
+
+    #[test]
+    fn expiry_is_bucketed() {
+        let signer = signer();
+
+        let (_, expires, _) = parse(&signer.video_url("a.mp4".to_string()));
+        assert_eq!(expires % URL_BUCKET_SECONDS, 0);
+        assert!(expires >= now() + URL_TTL_SECONDS);
+        assert!(expires <= now() + URL_TTL_SECONDS + URL_BUCKET_SECONDS);
+
+        assert_eq!(signer.sign("/a"), signer.sign("/a"));
+    }
+
+    #[test]
+    fn tampered_signatures_are_rejected() {
+        let signer = signer();
+        let (_, expires, signature) = parse(&format!("/?{}", signer.sign("/assets/video/a.mp4")));
+
+        assert!(signer.verify("/assets/video/a.mp4", expires, &signature));
+
+        // another file, a later expiry or another key
+        assert!(!signer.verify("/assets/video/b.mp4", expires, &signature));
+        assert!(!signer.verify("/assets/video/a.mp4", expires + 1, &signature));
+        assert!(!Signer::from_key("other key", String::new()).verify(
+            "/assets/video/a.mp4",
+            expires,
+            &signature
+        ));
+
+        let mut flipped = signature.clone();
+        let last = if flipped.ends_with('0') { "1" } else { "0" };
+        flipped.replace_range(flipped.len() - 1.., last);
+        assert!(!signer.verify("/assets/video/a.mp4", expires, &flipped));
+
+        assert!(!signer.verify("/assets/video/a.mp4", expires, ""));
+        assert!(!signer.verify("/assets/video/a.mp4", expires, "not hex"));
+        assert!(!signer.verify("/assets/video/a.mp4", expires, &signature[..32]));
+    }
+
+    #[test]
+    fn expired_signatures_are_rejected() {
+        let signer = signer();
+        let expires = now() - 1;
+        let signature = signature_for(&signer, "/assets/video/a.mp4", expires);
+
+        assert!(!signer.verify("/assets/video/a.mp4", expires, &signature));
+    }
+
+    #[test]
+    fn deletion_signatures_only_delete_their_video() {
+        let signer = signer();
+        let id = Uuid::new_v4();
+
+        let url = signer.deletion_url(&id);
+        let (path, signature) = url.split_once("?signature=").unwrap();
+        assert_eq!(path, format!("/hawk/upload/sharex/{}/delete", id));
+
+        assert!(signer.verify_deletion(&id, signature));
+        assert!(!signer.verify_deletion(&Uuid::new_v4(), signature));
+        assert!(!signer.verify_deletion(&id, "not hex"));
+
+        // they never expire, so they mustn't pass as media signatures
+        assert!(!signer.verify(&deletion_path(&id), 0, signature));
+        for scope in [
+            format!("/assets/video/{}.mp4", id),
+            format!("/assets/images/{}.webp", id),
+            hls_scope(&id),
+        ] {
+            assert!(!signer.verify(&scope, 0, signature));
+        }
+    }
+
+    #[test]
+    fn media_signatures_dont_delete() {
+        let signer = signer();
+        let id = Uuid::new_v4();
+
+        let (_, _, signature) = parse(&signer.thumbnail_url(&id));
+        assert!(!signer.verify_deletion(&id, &signature));
+
+        let (_, _, signature) = parse(&signer.hls_url(&id));
+        assert!(!signer.verify_deletion(&id, &signature));
+    }
+
+    #[test]
+    fn hls_signatures_cover_the_whole_directory() {
+        let signer = signer();
+        let id = Uuid::new_v4();
+
+        let (path, expires, signature) = parse(&signer.hls_url(&id));
+        assert_eq!(path, format!("/hawk/assets/hls/{}/master.m3u8", id));
+
+        for file in ["master.m3u8", "720p/index.m3u8", "720p/segment-001.m4s"] {
+            let scope = scope(&format!("/assets/hls/{}/{}", id, file));
+            assert!(signer.verify(&scope, expires, &signature), "{}", file);
+        }
+
+        let other = scope(&format!("/assets/hls/{}/master.m3u8", Uuid::new_v4()));
+        assert!(!signer.verify(&other, expires, &signature));
+    }
+
+    #[test]
+    fn scope_is_the_path_outside_hls() {
+        assert_eq!(scope("/assets/video/a.mp4"), "/assets/video/a.mp4");
+        assert_eq!(
+            scope("/assets/hls/not-a-uuid/master.m3u8"),
+            "/assets/hls/not-a-uuid/master.m3u8"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    handler::Handler as _,
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use http::{header, HeaderMap, StatusCode, Uri};
+
+use crate::{config::Config, error::Error};
+
+/// How often the certificate and key are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+pub(crate) async fn load(config: &Config) -> Result<RustlsConfig, Error> {
+    let tls = RustlsConfig::from_pem_file(&config.server.cert, &config.server.key).await?;
+
+    Ok(tls)
+}
+
+/// Swaps in the certificate and key whenever they change on disk or on
+/// `SIGHUP`, new connections use them right away while existing ones are
+/// left alone.
+pub(crate) fn spawn_reload(tls: RustlsConfig, config: Arc<Config>) {
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(tls.clone(), config.clone()));
+
+    tokio::spawn(reload_on_change(tls, config));
+}
+
+#[cfg(unix)]
+async fn reload_on_hangup(tls: RustlsConfig, config: Arc<Config>) {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("failed to install SIGHUP handler");
+
+    while hangup.recv().await.is_some() {
+        tracing::info!("SIGHUP received, reloading certificate");
+
+        reload(&tls, &config).await;
+    }
+}
+
+async fn reload_on_change(tls: RustlsConfig, config: Arc<Config>) {
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    let mut last = modified(&config).await;
+
+    loop {
+        interval.tick().await;
+
+        let current = modified(&config).await;
+        if current != last {
+            tracing::info!("certificate changed on disk, reloading");
+
+            reload(&tls, &config).await;
+            last = current;
+        }
+    }
+}
+
+async fn modified(config: &Config) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path| async move {
+        tokio::fs::metadata(path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+
+    (
+        modified(&config.server.cert).await,
+        modified(&config.server.key).await,
+    )
+}
+
+/// A failed reload keeps serving the previous certificate.
+async fn reload(tls: &RustlsConfig, config: &Config) {
+    match tls
+        .reload_from_pem_file(&config.server.cert, &config.server.key)
+        .await
+    {
+        Ok(()) => tracing::info!("certificate reloaded"),
+        Err(err) => tracing::error!("unable to reload certificate: {}", err),
+    }
+}
+
+/// Routes for the plain HTTP listener, everything is redirected to the same
+/// path on the HTTPS port.
+pub(crate) fn redirect_routes(https_port: u16) -> Router {
+    let handler =
+        move |headers: HeaderMap, uri: Uri| async move { redirect(&headers, &uri, https_port) };
+
+    Router::new().fallback(handler.into_service())
+}
+
+fn redirect(headers: &HeaderMap, uri: &Uri, https_port: u16) -> Response {
+    let host = match headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+    {
+        Some(host) => host,
+        None => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    // drop the port of the plain listener, keeping IPv6 literals intact
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+
+    let path = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+
+    let location = match https_port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path),
+    };
+
+    Redirect::permanent(&location).into_response()
+}